@@ -11,7 +11,9 @@ fn counter(_req: &HttpReq, res: &mut HttpRes) {
         1 => "This app has been run once.".into(),
         _ => format!("This app has been run {} times!", counter)
     };
-    svc::kv::kv_set("counter_app", "counter", &(counter + 1));
-    Text(text).modify_response(res);
+    match svc::kv::kv_set("counter_app", "counter", &(counter + 1)) {
+        Ok(_) => Text(text).modify_response(res),
+        Err(e) => StatusCode(500, Text(format!("Could not count this run: {}", e))).modify_response(res),
+    }
 }
 handle_http!(counter);
\ No newline at end of file
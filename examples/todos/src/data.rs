@@ -1,65 +1,100 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde::{Deserialize, Serialize};
-use zhur_sdk::svc::kv::*;
+use zhur_sdk::svc::collection::Collection;
+use zhur_sdk::svc::kv::{kv_del, kv_get, kv_increment};
 use crate::todo::Todo;
 
-#[derive(Deserialize, Serialize)]
-struct Todos {
-    todos: Vec<Todo>,
+/// Where earlier versions of this app kept every todo, in a single record.
+const LEGACY_TABLE: &str = "todo";
+const LEGACY_KEY: &str = "todos";
+/// A counter in the legacy table, incremented by whoever goes to migrate the legacy record. Only whoever gets 1 goes ahead.
+const MIGRATION_CLAIM_KEY: &str = "migration_claim";
+/// Set once this instance of the app knows the legacy record is taken care of, so it stops checking.
+static MIGRATION_CHECKED: AtomicBool = AtomicBool::new(false);
+/// The single record earlier versions of this app kept every todo in.
+#[derive(Deserialize)]
+struct LegacyTodos {
+    todos: Vec<LegacyTodo>,
     counter: i32,
 }
-impl Todos {
-    fn load() -> Self {
-        match kv_get("todo", "todos") {
-            Some(s) => s,
-            None => Self {
-                todos: Vec::new(),
-                counter: 0,
-            }
+#[derive(Deserialize)]
+struct LegacyTodo {
+    id: i32,
+    text: String,
+    complete: bool,
+}
+
+/// A todo as stored; its ID is the collection's key rather than a field.
+#[derive(Deserialize, Serialize)]
+struct StoredTodo {
+    text: String,
+    complete: bool,
+}
+fn todos() -> Collection<StoredTodo> {
+    let todos = Collection::new("todos");
+    if !MIGRATION_CHECKED.load(Ordering::Relaxed) {
+        // Should this fail, such as on a read-only replica, the legacy record stays put for a later request to try again.
+        if migrate_legacy_todos(&todos).is_ok() {
+            MIGRATION_CHECKED.store(true, Ordering::Relaxed);
         }
     }
-    fn save(&self) {
-       kv_set("todo", "todos", &self);
+    todos
+}
+/// Moves todos saved by earlier versions of this app into the collection, keeping their IDs.
+/// Only one request ever does this, so no other can write back todos it read before they were changed in the collection.
+fn migrate_legacy_todos(todos: &Collection<StoredTodo>) -> Result<(), String> {
+    if kv_get::<LegacyTodos>(LEGACY_TABLE, LEGACY_KEY).is_none() {
+        return Ok(());
+    }
+    if kv_increment(LEGACY_TABLE, MIGRATION_CLAIM_KEY)? != 1 {
+        return Ok(());
     }
-    fn add(&mut self, text: String) {
-        self.counter += 1;
-        self.todos.push(Todo {complete: false, text, id: self.counter});
+    let migrated = (|| {
+        let legacy = match kv_get::<LegacyTodos>(LEGACY_TABLE, LEGACY_KEY) {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        for todo in legacy.todos {
+            todos.put(todo.id as u64, &StoredTodo {text: todo.text, complete: todo.complete})?;
+        }
+        todos.reserve_ids(legacy.counter.max(0) as u64)?;
+        kv_del(LEGACY_TABLE, LEGACY_KEY)
+    })();
+    if migrated.is_err() {
+        // Give the claim up, so a later request can have another go.
+        let _ = kv_del(LEGACY_TABLE, MIGRATION_CLAIM_KEY);
     }
+    migrated
 }
-pub fn get_all_todos() -> Vec<Todo> {
-    let todos = Todos::load();
-    todos.todos
+pub fn get_all_todos() -> Result<Vec<Todo>, String> {
+    todos().iter()
+    .map(|stored| stored.map(|(id, todo)| Todo {id, text: todo.text, complete: todo.complete}))
+    .collect()
 }
 
-pub fn mark_todo(id: i32, done: bool) {
-    let mut todos = Todos::load();
-    for each in todos.todos.iter_mut() {
-        if each.id == id {
-            each.complete = done;
-        }
-    }
-    todos.save();
+pub fn mark_todo(id: u64, done: bool) -> Result<(), String> {
+    todos().update(id, |todo| todo.complete = done).map(|_| ())
+}
+pub fn edit_todo(id: u64, text: String) -> Result<(), String> {
+    todos().update(id, |todo| todo.text = text).map(|_| ())
 }
-pub fn edit_todo(id: i32, text: String) {
-    let mut todos = Todos::load();
-    for each in todos.todos.iter_mut() {
-        if each.id == id {
-            each.text = text.clone();
+pub fn delete_todo(id: u64) -> Result<(), String> {
+    todos().delete(id)
+}
+pub fn clear_done_todos() -> Result<(), String> {
+    let todos = todos();
+    let mut done = Vec::new();
+    for stored in todos.iter() {
+        let (id, todo) = stored?;
+        if todo.complete {
+            done.push(id);
         }
     }
-    todos.save();
-}
-pub fn delete_todo(id: i32) {
-    let mut todos = Todos::load();
-    todos.todos.retain(|each| each.id != id);
-    todos.save();
+    for id in done {
+        todos.delete(id)?;
+    }
+    Ok(())
 }
-pub fn clear_done_todos() {
-    let mut todos = Todos::load();
-    todos.todos.retain(|each| !each.complete);
-    todos.save();
+pub fn add_todo(text: String) -> Result<(), String> {
+    todos().insert(&StoredTodo {text, complete: false}).map(|_| ())
 }
-pub fn add_todo(text: String) {
-    let mut todos = Todos::load();
-    todos.add(text);
-    todos.save();
-}
\ No newline at end of file
@@ -5,6 +5,10 @@ use crate::data;
 const INDEX_HTML: &str = include_str!("../res/index.html");
 const INDEX_JS: &[u8] = include_bytes!("../res/index.js");
 
+/// The response to a change to the todos that could not be saved.
+fn save_failed(e: String) -> StatusCode<Text> {
+    StatusCode(500, Text(format!("Your change couldn't be saved: {}", e)))
+}
 pub fn index() -> Html {
     Html(INDEX_HTML.into())
 }
@@ -12,9 +16,11 @@ pub fn index_js() -> File {
     File(INDEX_JS.to_vec(), Some(String::from("text/javascript")))
 }
 
-pub fn get_todos() -> Result<Json<Vec<Todo>>, StatusCode<Html>> {
-    let todos: Vec<Todo> = crate::data::get_all_todos();
-    Ok(Json(todos))
+pub fn get_todos() -> Result<Json<Vec<Todo>>, StatusCode<Text>> {
+    match data::get_all_todos() {
+        Ok(todos) => Ok(Json(todos)),
+        Err(e) => Err(StatusCode(500, Text(format!("Your todos couldn't be read: {}", e)))),
+    }
 }
 pub fn add_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
     let new_todo: TodoNewRequest = match serde_json::from_slice(&req.body) {
@@ -27,8 +33,7 @@ pub fn add_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
             )
         }
     };
-    data::add_todo(new_todo.text);
-    Ok(())
+    data::add_todo(new_todo.text).map_err(save_failed)
 }
 pub fn mark_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
     let marked: TodoMarkRequest = match serde_json::from_slice(&req.body) {
@@ -41,8 +46,7 @@ pub fn mark_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
             )
         }
     };
-    data::mark_todo(marked.id, marked.complete);
-    Ok(())
+    data::mark_todo(marked.id, marked.complete).map_err(save_failed)
 }
 pub fn edit_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
     let edit: TodoEditRequest = match serde_json::from_slice(&req.body) {
@@ -55,18 +59,15 @@ pub fn edit_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
             )
         }
     };
-    data::edit_todo(edit.id, edit.text);
-    Ok(())
+    data::edit_todo(edit.id, edit.text).map_err(save_failed)
 }
 pub fn clear_complete_todos() -> Result<(), StatusCode<Text>> {
-    data::clear_done_todos();
-    Ok(())
+    data::clear_done_todos().map_err(save_failed)
 }
 pub fn delete_todo(req: &HttpReq) -> Result<(), StatusCode<Text>> {
     let del: TodoDelRequest = match serde_json::from_slice(&req.body) {
         Ok(todo) => todo,
         Err(_) => return Err(StatusCode(400, Text("Couldn't parse your delete todo request.".to_string())))
     };
-    data::delete_todo(del.id);
-    Ok(())
+    data::delete_todo(del.id).map_err(save_failed)
 }
\ No newline at end of file
@@ -0,0 +1,105 @@
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::apst_admin::{Admin2Apst, Apst2Admin};
+use zhur_common::msg::core_sql::{Core2Sql, Sql2Core, TaggedCore2Sql, TaggedSql2Core};
+use zhur_common::zmq::{Context, Socket, SocketType};
+use crate::domains::Domains;
+use crate::manifest::Manifests;
+
+/// How long a deploy waits for the SQL service to run an app's migrations before failing, in milliseconds.
+const MIGRATION_TIMEOUT_MS: i32 = 60_000;
+
+/// Serves `Admin2Apst` requests on their own socket.
+pub struct AdminServer {
+    /// ZMQ rep socket for handling incoming requests.
+    rep_socket: Socket,
+    domains: Domains,
+    /// Where deployed apps' migrations are read from.
+    manifests: Manifests,
+    /// Used to connect to the SQL service for every deploy.
+    zmq_ctx: Context,
+    /// The SQL service's endpoint.
+    sql_endpoint: String,
+}
+
+impl AdminServer {
+    pub fn new(rep_socket: Socket, domains: Domains, manifests: Manifests, zmq_ctx: Context, sql_endpoint: String) -> Self {
+        Self { rep_socket, domains, manifests, zmq_ctx, sql_endpoint }
+    }
+    fn handle(&self) {
+        let request_bytes = self.rep_socket.recv_bytes(0)
+        .expect("Expected to be able to receive an admin request!");
+        let reply = match deserialize::<Admin2Apst>(&request_bytes) {
+            Ok(request) => self.handle_admin(request),
+            Err(_) => {
+                warn!("Got bytes on the admin socket that could not be deserialized as Admin2Apst.");
+                Apst2Admin::Error("Malformed request.".to_owned())
+            }
+        };
+        let reply_bytes = serialize(&reply)
+        .expect("Expected to serialize into Apst2Admin");
+        self.rep_socket.send(reply_bytes, 0)
+        .expect("Expected to send a reply back to the admin client.");
+    }
+    fn handle_admin(&self, request: Admin2Apst) -> Apst2Admin {
+        let result = match request {
+            Admin2Apst::SetDomain(domain, owner, app_name) => {
+                self.domains.set(&domain, &owner, &app_name).map(|_| Apst2Admin::Done)
+            },
+            Admin2Apst::RemoveDomain(domain) => {
+                self.domains.remove(&domain);
+                Ok(Apst2Admin::Done)
+            },
+            Admin2Apst::ListDomains(owner) => {
+                Ok(Apst2Admin::Domains(self.domains.list(&owner)))
+            },
+            Admin2Apst::Deploy(owner, app_name) => {
+                self.deploy(&owner, &app_name).map(Apst2Admin::Deployed)
+            }
+        };
+        result.unwrap_or_else(|e| {
+            warn!("Admin request failed: {}", &e);
+            Apst2Admin::Error(e)
+        })
+    }
+    /// Runs owner:app's migrations against its database, returning the schema version it ends up at.
+    fn deploy(&self, owner: &str, app_name: &str) -> Result<u32, String> {
+        let migrations = self.manifests.migrations(owner, app_name)?;
+        if migrations.is_empty() {
+            info!("{}:{} has no migrations to run.", owner, app_name);
+            return Ok(0);
+        }
+        info!("Deploying {}:{} with {} migrations.", owner, app_name, migrations.len());
+        // A fresh socket for every deploy, as a REQ socket that timed out waiting for its reply cannot send again.
+        let socket = self.zmq_ctx.socket(SocketType::REQ).unwrap();
+        socket.set_linger(0).unwrap();
+        socket.set_rcvtimeo(MIGRATION_TIMEOUT_MS).unwrap();
+        socket.connect(&self.sql_endpoint)
+        .map_err(|e| format!("Could not connect to the SQL service at {}: {}", &self.sql_endpoint, e))?;
+        let request: TaggedCore2Sql = (0, Core2Sql::Migrate(owner.to_owned(), app_name.to_owned(), migrations));
+        socket.send(serialize(&request).unwrap(), 0)
+        .map_err(|e| format!("Could not send the migrations to the SQL service: {}", e))?;
+        let reply_bytes = socket.recv_bytes(0)
+        .map_err(|e| format!("The SQL service did not finish the migrations: {}", e))?;
+        match deserialize::<TaggedSql2Core>(&reply_bytes) {
+            Ok((_, Sql2Core::Migrated(version))) => {
+                info!("Deployed {}:{}, whose database is at schema version {}.", owner, app_name, version);
+                Ok(version)
+            },
+            Ok((_, Sql2Core::Error(e))) => Err(format!("Could not migrate the database of {}:{}: {}", owner, app_name, e)),
+            Ok((_, other)) => Err(format!("The SQL service replied to the migrations with {:?}.", other)),
+            Err(_) => Err("The SQL service's reply to the migrations could not be deserialized.".to_owned())
+        }
+    }
+    pub fn run_as_thread(self) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("apst_admin".to_owned())
+            .spawn(move || {
+                let server = self;
+                loop {
+                    server.handle();
+                }
+            })
+            .expect("Could not launch the app store's admin thread!")
+    }
+}
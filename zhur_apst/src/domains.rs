@@ -0,0 +1,73 @@
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::normalize_domain;
+
+/// Maps custom domains to the apps served at them, in a sled tree keyed by normalized domain.
+/// Checking that owners control their domains is up to whoever sets them, such as the portal.
+#[derive(Clone)]
+pub struct Domains {
+    tree: sled::Tree,
+}
+
+impl Domains {
+    pub fn new(db: &sled::Db) -> Self {
+        let tree = db.open_tree("domains").expect("Expected to be able to open the domains tree.");
+        Self { tree }
+    }
+    /// Finds the owner and name of the app served at a domain.
+    pub fn resolve(&self, domain: &str) -> Option<(String, String)> {
+        let value = self.tree.get(normalize_domain(domain).as_bytes()).unwrap()?;
+        match deserialize::<(String, String)>(&value) {
+            Ok(app) => Some(app),
+            Err(_) => {
+                error!("The mapping for the domain {:?} could not be deserialized.", domain);
+                None
+            }
+        }
+    }
+    pub fn set(&self, domain: &str, owner: &str, app_name: &str) -> Result<(), String> {
+        let domain = normalize_domain(domain);
+        if !is_valid_domain(&domain) {
+            return Err(format!("{:?} is not a valid domain name.", &domain));
+        }
+        let value = serialize(&(owner.to_owned(), app_name.to_owned())).unwrap();
+        self.tree.insert(domain.as_bytes(), value).unwrap();
+        self.tree.flush().unwrap();
+        info!("The domain {} now points to {}:{}.", &domain, owner, app_name);
+        Ok(())
+    }
+    pub fn remove(&self, domain: &str) {
+        let domain = normalize_domain(domain);
+        if self.tree.remove(domain.as_bytes()).unwrap().is_some() {
+            self.tree.flush().unwrap();
+            info!("The domain {} no longer points to an app.", &domain);
+        }
+    }
+    /// Lists the domains pointing to an owner's apps, along with the apps. There are few enough domains to go through them all.
+    pub fn list(&self, owner: &str) -> Vec<(String, String, String)> {
+        self.tree.iter()
+            .filter_map(|pair| {
+                let (domain, value) = pair.unwrap();
+                let (app_owner, app_name) = deserialize::<(String, String)>(&value).ok()?;
+                match app_owner == owner {
+                    true => Some((String::from_utf8_lossy(&domain).into_owned(), app_owner, app_name)),
+                    false => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether a normalized domain is made up of valid DNS labels, with at least two of them.
+fn is_valid_domain(domain: &str) -> bool {
+    let labels = domain.split('.').collect::<Vec<_>>();
+    domain.len() <= 253
+    && labels.len() >= 2
+    && labels.iter().all(|label| {
+        !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
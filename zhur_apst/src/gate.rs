@@ -0,0 +1,64 @@
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{Apst2Gate, Gate2Apst};
+use zhur_common::zmq::Socket;
+use crate::domains::Domains;
+use crate::manifest::Manifests;
+
+/// Answers the gateway's requests for what it needs to know about apps, on a socket of its own.
+pub struct GateServer {
+    /// ZMQ rep socket for handling incoming requests.
+    rep_socket: Socket,
+    manifests: Manifests,
+    domains: Domains,
+}
+
+impl GateServer {
+    pub fn new(rep_socket: Socket, manifests: Manifests, domains: Domains) -> Self {
+        Self { rep_socket, manifests, domains }
+    }
+    fn handle(&self) {
+        let request_bytes = self.rep_socket.recv_bytes(0)
+        .expect("Expected to be able to receive a request from the gateway!");
+        let reply = match deserialize::<Gate2Apst>(&request_bytes) {
+            Ok(request) => self.handle_gate2apst(request),
+            Err(_) => {
+                warn!("Got bytes that could not be deserialized to a Gate2Apst.");
+                Apst2Gate::Error("Malformed request.".to_owned())
+            }
+        };
+        let reply_bytes = serialize(&reply)
+        .expect("Expected to serialize into Apst2Gate");
+        self.rep_socket.send(reply_bytes, 0)
+        .expect("Expected to send a reply back to the gateway.");
+    }
+    fn handle_gate2apst(&self, request: Gate2Apst) -> Apst2Gate {
+        match request {
+            Gate2Apst::Manifest(owner, app_name) => {
+                trace!("Got a request for the manifest of {}:{}.", &owner, &app_name);
+                match self.manifests.load(&owner, &app_name) {
+                    Ok(manifest) => Apst2Gate::Manifest(manifest),
+                    Err(e) => {
+                        warn!("{}", &e);
+                        Apst2Gate::Error(e)
+                    }
+                }
+            },
+            Gate2Apst::ResolveDomain(domain) => {
+                trace!("Got a request to resolve the domain {}.", &domain);
+                Apst2Gate::Domain(self.domains.resolve(&domain))
+            }
+        }
+    }
+    pub fn run_as_thread(self) -> std::thread::JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("apst_gate_server".to_owned())
+            .spawn(move || {
+                let server = self;
+                loop {
+                    server.handle();
+                }
+            })
+            .expect("Could not launch the app store's gateway server thread!")
+    }
+}
@@ -1,4 +1,18 @@
 /// Herein lives the `ApstServer`, which responds to requests for apps and issues updates when they are changed.
 mod serve;
 pub use serve::ApstServer;
-pub use zhur_common::msg::core_apst::DEFAULT_APST_ENDPOINT as DEFAULT_ENDPOINT;
\ No newline at end of file
+/// App manifests, read from disk.
+mod manifest;
+pub use manifest::Manifests;
+/// Custom domains of apps.
+mod domains;
+pub use domains::Domains;
+/// Serving of administrative requests.
+mod admin;
+pub use admin::AdminServer;
+/// Serving of the gateway's requests.
+mod gate;
+pub use gate::GateServer;
+pub use zhur_common::msg::core_apst::DEFAULT_APST_ENDPOINT as DEFAULT_ENDPOINT;
+pub use zhur_common::msg::gate_apst::DEFAULT_GATE_APST_ENDPOINT as DEFAULT_GATE_ENDPOINT;
+pub use zhur_common::msg::apst_admin::DEFAULT_APST_ADMIN_ENDPOINT as DEFAULT_ADMIN_ENDPOINT;
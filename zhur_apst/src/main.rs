@@ -1,7 +1,14 @@
-use zhur_apst::ApstServer;
-use zhur_apst::DEFAULT_ENDPOINT;
-use zhur_common::{init_logger, zmq::{Context, SocketType}};
+use std::path::PathBuf;
+use zhur_apst::{AdminServer, ApstServer, Domains, GateServer, Manifests};
+use zhur_apst::{DEFAULT_ADMIN_ENDPOINT, DEFAULT_ENDPOINT, DEFAULT_GATE_ENDPOINT};
+use zhur_common::{init_logger, msg::core_sql::DEFAULT_SQL_ENDPOINT, zmq::{Context, SocketType}};
 use zhur_common::log::*;
+
+/// Where app manifests are read from if `ZHUR_APST_MANIFESTS` is not set, relative to the user's home directory.
+const DEFAULT_MANIFEST_DIR: &str = ".zhur/apps";
+/// Where the app store's database goes if `ZHUR_APST_DB` is not set, relative to the user's home directory.
+const DEFAULT_DB_PATH: &str = ".zhur/apst.sled";
+
 fn main() {
     init_logger();
     let ctx = Context::new();
@@ -16,6 +23,58 @@ fn main() {
     };
     rep_socket.bind(&endpoint)
     .expect("Could not bind REP socket");
+    let manifest_dir = match std::env::var("ZHUR_APST_MANIFESTS") {
+        Ok(d) => PathBuf::from(d),
+        Err(_) => {
+            let home = std::env::var("HOME").expect("Neither ZHUR_APST_MANIFESTS nor HOME is set!");
+            let dir = PathBuf::from(home).join(DEFAULT_MANIFEST_DIR);
+            warn!("ZHUR_APST_MANIFESTS not set. Assuming default of {:?}.", &dir);
+            dir
+        }
+    };
+    let db_path = match std::env::var("ZHUR_APST_DB") {
+        Ok(p) => PathBuf::from(p),
+        Err(_) => {
+            let home = std::env::var("HOME").expect("Neither ZHUR_APST_DB nor HOME is set!");
+            let path = PathBuf::from(home).join(DEFAULT_DB_PATH);
+            warn!("ZHUR_APST_DB not set. Assuming default of {:?}.", &path);
+            path
+        }
+    };
+    let db = sled::open(&db_path).expect("Could not open the app store's database!");
+    let domains = Domains::new(&db);
+    let admin_socket = ctx.socket(SocketType::REP)
+    .expect("Expected to be able to build a REP socket.");
+    let admin_endpoint = match std::env::var("ZHUR_APST_ADMIN_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_APST_ADMIN_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_ADMIN_ENDPOINT);
+            DEFAULT_ADMIN_ENDPOINT.to_string()
+        }
+    };
+    admin_socket.bind(&admin_endpoint)
+    .expect("Could not bind the admin REP socket");
+    let sql_endpoint = match std::env::var("ZHUR_SQL_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_SQL_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_SQL_ENDPOINT);
+            DEFAULT_SQL_ENDPOINT.to_string()
+        }
+    };
+    let manifests = Manifests::new(manifest_dir);
+    AdminServer::new(admin_socket, domains.clone(), manifests.clone(), ctx.clone(), sql_endpoint).run_as_thread();
+    let gate_socket = ctx.socket(SocketType::REP)
+    .expect("Expected to be able to build a REP socket.");
+    let gate_endpoint = match std::env::var("ZHUR_APST_GATE_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_APST_GATE_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_GATE_ENDPOINT);
+            DEFAULT_GATE_ENDPOINT.to_string()
+        }
+    };
+    gate_socket.bind(&gate_endpoint)
+    .expect("Could not bind the gateway REP socket");
+    GateServer::new(gate_socket, manifests, domains).run_as_thread();
     let apst_server = ApstServer::new(rep_socket).run_as_thread();
     apst_server.join().unwrap();
 }
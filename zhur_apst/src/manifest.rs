@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use serde::Deserialize;
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{AppManifest, AppRateLimits, ErrorPageTemplate, PageFormat};
+
+/// An app's `manifest.json` as written by its owner. Error pages are given by status code, or `default`,
+/// and point to template files relative to the manifest. The body size limit is in bytes.
+/// ```json
+/// {
+///     "error_pages": { "404": "errors/404.html", "default": "errors/error.json" },
+///     "max_body_size": 52428800,
+///     "rate_limits": { "app": { "per_second": 100, "burst": 200 }, "per_ip": { "per_second": 5, "burst": 10 } }
+/// }
+/// ```
+#[derive(Deserialize)]
+struct ManifestFile {
+    #[serde(default)]
+    error_pages: BTreeMap<String, String>,
+    #[serde(default)]
+    max_body_size: Option<u64>,
+    #[serde(default)]
+    rate_limits: AppRateLimits,
+}
+
+/// Reads app manifests from a directory holding one `<owner>/<app>/manifest.json` per app.
+#[derive(Clone)]
+pub struct Manifests {
+    dir: PathBuf,
+}
+
+impl Manifests {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+    /// Reads the manifest of owner:app afresh, so owners' changes show up without a restart.
+    /// Apps without a manifest get an empty one.
+    pub fn load(&self, owner: &str, app_name: &str) -> Result<AppManifest, String> {
+        if !is_safe_name(owner) || !is_safe_name(app_name) {
+            return Err(format!("{:?}:{:?} cannot be used to name an app directory.", owner, app_name));
+        }
+        let app_dir = self.dir.join(owner).join(app_name);
+        let path = app_dir.join("manifest.json");
+        let bytes = match std::fs::read(&path) {
+            Ok(b) => b,
+            Err(_) => {
+                trace!("No manifest for {}:{} at {:?}.", owner, app_name, &path);
+                return Ok(AppManifest::default());
+            }
+        };
+        let file = serde_json::from_slice::<ManifestFile>(&bytes)
+        .map_err(|e| format!("The manifest at {:?} is invalid: {}", &path, e))?;
+        let mut manifest = AppManifest {
+            max_body_size: file.max_body_size,
+            rate_limits: file.rate_limits,
+            ..AppManifest::default()
+        };
+        for (scope, limit) in [("app", &mut manifest.rate_limits.app), ("per_ip", &mut manifest.rate_limits.per_ip)] {
+            if let Some(l) = limit {
+                if !l.is_valid() {
+                    warn!("The manifest at {:?} has an invalid {} rate limit of {} per second with a burst of {}. Skipping.", &path, scope, l.per_second, l.burst);
+                    *limit = None;
+                }
+            }
+        }
+        for (status, template_path) in file.error_pages {
+            let status = match status.as_str() {
+                "default" => None,
+                code => match code.parse::<u16>() {
+                    Ok(c) => Some(c),
+                    Err(_) => {
+                        warn!("The manifest at {:?} has an error page for {:?}, which is not a status code. Skipping.", &path, code);
+                        continue;
+                    }
+                },
+            };
+            match load_template(&app_dir, &template_path) {
+                Ok((format, template)) => manifest.error_pages.push(ErrorPageTemplate { status, format, template }),
+                Err(e) => warn!("Skipping an error page of {}:{}: {}", owner, app_name, e),
+            }
+        }
+        Ok(manifest)
+    }
+    /// Reads the SQL migrations of owner:app from the `.sql` files in its `migrations` directory, in file name order,
+    /// so the nth file takes the schema from version n-1 to version n. Apps without the directory have none.
+    pub fn migrations(&self, owner: &str, app_name: &str) -> Result<Vec<String>, String> {
+        if !is_safe_name(owner) || !is_safe_name(app_name) {
+            return Err(format!("{:?}:{:?} cannot be used to name an app directory.", owner, app_name));
+        }
+        let dir = self.dir.join(owner).join(app_name).join("migrations");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(_) => {
+                trace!("No migrations for {}:{} at {:?}.", owner, app_name, &dir);
+                return Ok(Vec::new());
+            }
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| format!("Could not list {:?}: {}", &dir, e))?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("sql") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths.iter()
+        .map(|path| std::fs::read_to_string(path).map_err(|e| format!("Could not read {:?}: {}", path, e)))
+        .collect()
+    }
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+    && !name.starts_with('.')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+/// Reads a template file, which must lie within the app's directory. Its format is told by its extension.
+fn load_template(app_dir: &Path, relative: &str) -> Result<(PageFormat, String), String> {
+    let relative = Path::new(relative);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("{:?} does not point inside the app's directory.", relative));
+    }
+    let format = match relative.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => PageFormat::Html,
+        Some("json") => PageFormat::Json,
+        _ => return Err(format!("{:?} is neither an .html nor a .json file.", relative)),
+    };
+    let path = app_dir.join(relative);
+    let template = std::fs::read_to_string(&path)
+    .map_err(|e| format!("Could not read {:?}: {}", &path, e))?;
+    Ok((format, template))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zhur_common::msg::gate_apst::RateLimit;
+    use zhur_common::testing::TempDir;
+
+    #[test]
+    fn invalid_rate_limits_are_skipped() {
+        let dir = TempDir::new("zhur_apst", "rate_limits");
+        let app_dir = dir.path().join("alice").join("notes");
+        std::fs::create_dir_all(&app_dir).unwrap();
+        let manifest = r#"{ "rate_limits": { "app": { "per_second": 0, "burst": 10 }, "per_ip": { "per_second": 5, "burst": 10 } } }"#;
+        std::fs::write(app_dir.join("manifest.json"), manifest).unwrap();
+        let manifests = Manifests::new(dir.path().to_owned());
+        let rate_limits = manifests.load("alice", "notes").unwrap().rate_limits;
+        assert!(rate_limits.app.is_none());
+        assert_eq!(rate_limits.per_ip, Some(RateLimit { per_second: 5.0, burst: 10 }));
+        let manifest = r#"{ "rate_limits": { "per_ip": { "per_second": -2.5, "burst": 0 } } }"#;
+        std::fs::write(app_dir.join("manifest.json"), manifest).unwrap();
+        assert!(manifests.load("alice", "notes").unwrap().rate_limits.per_ip.is_none());
+    }
+    #[test]
+    fn migrations_come_in_file_name_order() {
+        let dir = TempDir::new("zhur_apst", "migrations");
+        let migrations_dir = dir.path().join("alice").join("notes").join("migrations");
+        std::fs::create_dir_all(&migrations_dir).unwrap();
+        std::fs::write(migrations_dir.join("0002_tags.sql"), "second").unwrap();
+        std::fs::write(migrations_dir.join("0001_notes.sql"), "first").unwrap();
+        std::fs::write(migrations_dir.join("README.md"), "not a migration").unwrap();
+        let manifests = Manifests::new(dir.path().to_owned());
+        assert_eq!(manifests.migrations("alice", "notes").unwrap(), vec!["first", "second"]);
+        assert!(manifests.migrations("alice", "other").unwrap().is_empty());
+        assert!(manifests.migrations("..", "notes").is_err());
+    }
+}
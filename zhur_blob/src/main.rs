@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+use zhur_common::{bincode::{deserialize, serialize}, init_logger, msg::core_blob::{Blob2Core, TaggedBlob2Core, TaggedCore2Blob, DEFAULT_BLOB_ENDPOINT}, zmq::{Context, SocketType}};
+use zhur_common::log::*;
+/// The filesystem-backed blob store.
+mod store;
+use store::BlobStore;
+
+/// Where the blobs go if `ZHUR_BLOB_DIR` is not set, relative to the user's home directory.
+const DEFAULT_BLOB_DIR: &str = ".zhur/blob";
+
+fn main() {
+    init_logger();
+    let dir = match std::env::var("ZHUR_BLOB_DIR") {
+        Ok(d) => PathBuf::from(d),
+        Err(_) => {
+            let home = std::env::var("HOME").expect("Neither ZHUR_BLOB_DIR nor HOME is set!");
+            let dir = PathBuf::from(home).join(DEFAULT_BLOB_DIR);
+            warn!("ZHUR_BLOB_DIR not set. Assuming default of {:?}.", &dir);
+            dir
+        }
+    };
+    let mut store = BlobStore::new(dir);
+    let zmq_ctx = Context::new();
+    let socket = zmq_ctx.socket(SocketType::ROUTER).unwrap();
+    let endpoint = match std::env::var("ZHUR_BLOB_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_BLOB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_BLOB_ENDPOINT);
+            DEFAULT_BLOB_ENDPOINT.to_string()
+        }
+    };
+    socket.bind(&endpoint).expect("Could not bind the blob service socket!");
+    info!("Serving blob requests at {}.", &endpoint);
+    // Requests are tagged, so the core can tell which of the requests it has in flight a reply is for, and give up on ones that take too long.
+    loop {
+        let mut frames = socket.recv_multipart(0).unwrap();
+        let request_bytes = frames.pop().unwrap_or_default();
+        trace!("Got request bytes.");
+        let reply_bytes = match deserialize::<TaggedCore2Blob>(&request_bytes) {
+            Ok((tag, request)) => {
+                let response: TaggedBlob2Core = (tag, store.handle(request));
+                serialize(&response).unwrap()
+            },
+            // Without a readable request there is nothing to do, but a readable tag still lets the core fail the request straight away.
+            Err(_) => match deserialize::<u64>(&request_bytes) {
+                Ok(tag) => {
+                    warn!("Got bytes tagged #{} that could not be deserialized to a TaggedCore2Blob.", tag);
+                    let response: TaggedBlob2Core = (tag, Blob2Core::Error("Malformed request.".to_owned()));
+                    serialize(&response).unwrap()
+                },
+                Err(_) => {
+                    warn!("Got bytes that could not be deserialized to a TaggedCore2Blob, nor even carry a tag.");
+                    Vec::new()
+                }
+            }
+        };
+        // What is left of the frames is the envelope the ROUTER socket needs to send the reply to the right client.
+        frames.push(reply_bytes);
+        socket.send_multipart(frames, 0).unwrap();
+        trace!("Sent reply!");
+    }
+}
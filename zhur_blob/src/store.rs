@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use zhur_common::log::*;
+use zhur_common::msg::core_blob::{Blob2Core, BlobMeta, Core2Blob, BLOB_CHUNK_SIZE};
+
+/// Uploads not finished within this long are thrown away.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// The longest blob path accepted, in bytes.
+const MAX_PATH_LEN: usize = 1024;
+/// The most blobs listed at once.
+const MAX_LIST_LIMIT: u32 = 1000;
+/// The most uploads a single app can have going at once.
+const MAX_UPLOADS_PER_APP: usize = 16;
+/// The most uploads that can be going at once altogether, as every one holds a file open.
+const MAX_UPLOADS: usize = 256;
+
+/// The metadata kept alongside a blob. Its size and modification time come from the file itself.
+#[derive(Deserialize, Serialize)]
+struct StoredMeta {
+    content_type: String,
+    /// Blobs stored before etags were kept have none; theirs is made up from their size and modification time.
+    #[serde(default)]
+    etag: Option<String>,
+}
+
+/// A blob being put, written to a temporary file until it is finished.
+struct Upload {
+    owner: String,
+    app_name: String,
+    path: String,
+    content_type: String,
+    file: File,
+    tmp_path: PathBuf,
+    started: Instant,
+}
+
+/// Keeps blobs as files on the local filesystem.
+/// Each app gets a directory of its own, `<owner>/<app>`, holding the blobs under `data/`, their metadata under `metadata/`
+/// at the same paths, and unfinished uploads under `tmp/`.
+pub struct BlobStore {
+    dir: PathBuf,
+    uploads: HashMap<u64, Upload>,
+    next_upload_id: u64,
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf) -> Self {
+        remove_stale_uploads(&dir);
+        Self {
+            dir,
+            uploads: HashMap::new(),
+            next_upload_id: 0,
+        }
+    }
+    /// Carries out a `Core2Blob` request and produces the reply to it.
+    pub fn handle(&mut self, request: Core2Blob) -> Blob2Core {
+        self.expire_uploads();
+        let result = match request {
+            Core2Blob::BeginPut(owner, app_name, path, content_type) => self.begin_put(owner, app_name, path, content_type),
+            Core2Blob::PutChunk(owner, app_name, id, chunk) => self.put_chunk(&owner, &app_name, id, &chunk),
+            Core2Blob::FinishPut(owner, app_name, id) => self.finish_put(&owner, &app_name, id),
+            Core2Blob::AbortPut(owner, app_name, id) => self.abort_put(&owner, &app_name, id),
+            Core2Blob::GetChunk(owner, app_name, path, offset, len, etag) => self.get_chunk(&owner, &app_name, &path, offset, len, etag.as_deref()),
+            Core2Blob::Stat(owner, app_name, path) => self.stat(&owner, &app_name, &path)
+                .map(|meta| meta.map(Blob2Core::Meta).unwrap_or(Blob2Core::NotFound)),
+            Core2Blob::Delete(owner, app_name, path) => self.delete(&owner, &app_name, &path),
+            Core2Blob::List(owner, app_name, prefix, after, limit) => self.list(&owner, &app_name, &prefix, after.as_deref(), limit)
+                .map(Blob2Core::Metas),
+        };
+        result.unwrap_or_else(|e| {
+            debug!("Blob request failed: {}", &e);
+            Blob2Core::Error(e)
+        })
+    }
+    fn begin_put(&mut self, owner: String, app_name: String, path: String, content_type: String) -> Result<Blob2Core, String> {
+        check_path(&path)?;
+        if self.uploads.len() >= MAX_UPLOADS {
+            return Err("Too many uploads are going on. Try again later.".to_owned());
+        }
+        let app_uploads = self.uploads.values().filter(|u| u.owner == owner && u.app_name == app_name).count();
+        if app_uploads >= MAX_UPLOADS_PER_APP {
+            return Err(format!("An app can only have {} uploads going at once.", MAX_UPLOADS_PER_APP));
+        }
+        let tmp_dir = self.app_dir(&owner, &app_name)?.join("tmp");
+        fs::create_dir_all(&tmp_dir).map_err(|e| format!("Could not create {:?}: {}", &tmp_dir, e))?;
+        self.next_upload_id += 1;
+        let id = self.next_upload_id;
+        let tmp_path = tmp_dir.join(id.to_string());
+        let file = File::create(&tmp_path).map_err(|e| format!("Could not create {:?}: {}", &tmp_path, e))?;
+        trace!("Started upload #{} of {}:{}:{}.", id, &owner, &app_name, &path);
+        self.uploads.insert(id, Upload {
+            owner,
+            app_name,
+            path,
+            content_type,
+            file,
+            tmp_path,
+            started: Instant::now(),
+        });
+        Ok(Blob2Core::Upload(id))
+    }
+    fn put_chunk(&mut self, owner: &str, app_name: &str, id: u64, chunk: &[u8]) -> Result<Blob2Core, String> {
+        if chunk.len() > BLOB_CHUNK_SIZE {
+            return Err(format!("Chunks can be at most {} bytes long.", BLOB_CHUNK_SIZE));
+        }
+        let upload = match self.uploads.get_mut(&id) {
+            Some(u) if u.owner == owner && u.app_name == app_name => u,
+            _ => return Ok(Blob2Core::NotFound),
+        };
+        upload.file.write_all(chunk).map_err(|e| format!("Could not write to upload #{}: {}", id, e))?;
+        Ok(Blob2Core::Done)
+    }
+    fn finish_put(&mut self, owner: &str, app_name: &str, id: u64) -> Result<Blob2Core, String> {
+        let upload = match self.take_upload(owner, app_name, id) {
+            Some(u) => u,
+            None => return Ok(Blob2Core::NotFound),
+        };
+        upload.file.sync_all().map_err(|e| format!("Could not sync upload #{}: {}", id, e))?;
+        let data_path = self.data_path(owner, app_name, &upload.path)?;
+        let meta_path = self.meta_path(owner, app_name, &upload.path)?;
+        create_parent(&data_path)?;
+        create_parent(&meta_path)?;
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let meta = StoredMeta {
+            content_type: upload.content_type,
+            etag: Some(format!("{:x}-{:x}", nanos, id)),
+        };
+        fs::write(&meta_path, serde_json::to_vec(&meta).unwrap())
+        .map_err(|e| format!("Could not write {:?}: {}", &meta_path, e))?;
+        let _ = fs::remove_file(self.legacy_meta_path(owner, app_name, &upload.path)?);
+        // Renaming is atomic, so readers see either the old blob or the new one, never half of one.
+        fs::rename(&upload.tmp_path, &data_path)
+        .map_err(|e| format!("Could not move upload #{} to {:?}: {}", id, &data_path, e))?;
+        debug!("Stored {}:{}:{}.", owner, app_name, &upload.path);
+        match self.stat(owner, app_name, &upload.path)? {
+            Some(meta) => Ok(Blob2Core::Meta(meta)),
+            None => Err(format!("{:?} vanished right after being stored.", &upload.path)),
+        }
+    }
+    fn abort_put(&mut self, owner: &str, app_name: &str, id: u64) -> Result<Blob2Core, String> {
+        match self.take_upload(owner, app_name, id) {
+            Some(upload) => {
+                discard(upload);
+                Ok(Blob2Core::Done)
+            },
+            None => Ok(Blob2Core::NotFound),
+        }
+    }
+    fn get_chunk(&self, owner: &str, app_name: &str, path: &str, offset: u64, len: u32, etag: Option<&str>) -> Result<Blob2Core, String> {
+        // Requests are handled one at a time, so the blob cannot be replaced between this check and the read.
+        if let Some(etag) = etag {
+            match self.stat(owner, app_name, path)? {
+                Some(meta) if meta.etag == etag => (),
+                Some(_) => return Ok(Blob2Core::Modified),
+                None => return Ok(Blob2Core::NotFound),
+            }
+        }
+        let data_path = self.data_path(owner, app_name, path)?;
+        let mut file = match File::open(&data_path) {
+            Ok(f) => f,
+            Err(_) => return Ok(Blob2Core::NotFound),
+        };
+        file.seek(SeekFrom::Start(offset)).map_err(|e| format!("Could not seek in {:?}: {}", &data_path, e))?;
+        let len = (len as usize).min(BLOB_CHUNK_SIZE);
+        let mut chunk = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut chunk).map_err(|e| format!("Could not read {:?}: {}", &data_path, e))?;
+        Ok(Blob2Core::Chunk(chunk))
+    }
+    fn stat(&self, owner: &str, app_name: &str, path: &str) -> Result<Option<BlobMeta>, String> {
+        let data_path = self.data_path(owner, app_name, path)?;
+        let metadata = match fs::metadata(&data_path) {
+            Ok(m) if m.is_file() => m,
+            _ => return Ok(None),
+        };
+        let legacy_meta_path = self.legacy_meta_path(owner, app_name, path)?;
+        let stored = fs::read(self.meta_path(owner, app_name, path)?)
+            .or_else(|_| fs::read(&legacy_meta_path))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<StoredMeta>(&bytes).ok());
+        let modified = metadata.modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (content_type, etag) = match stored {
+            Some(meta) => (meta.content_type, meta.etag),
+            None => ("application/octet-stream".to_owned(), None),
+        };
+        let etag = etag.unwrap_or_else(|| format!("{:x}-{:x}", modified, metadata.len()));
+        Ok(Some(BlobMeta {
+            path: path.to_owned(),
+            size: metadata.len(),
+            content_type,
+            modified,
+            etag,
+        }))
+    }
+    fn delete(&self, owner: &str, app_name: &str, path: &str) -> Result<Blob2Core, String> {
+        let data_path = self.data_path(owner, app_name, path)?;
+        if fs::remove_file(&data_path).is_err() {
+            return Ok(Blob2Core::NotFound);
+        }
+        let _ = fs::remove_file(self.meta_path(owner, app_name, path)?);
+        let _ = fs::remove_file(self.legacy_meta_path(owner, app_name, path)?);
+        debug!("Deleted {}:{}:{}.", owner, app_name, path);
+        Ok(Blob2Core::Done)
+    }
+    fn list(&self, owner: &str, app_name: &str, prefix: &str, after: Option<&str>, limit: u32) -> Result<Vec<BlobMeta>, String> {
+        let data_dir = self.app_dir(owner, app_name)?.join("data");
+        let mut paths = Vec::new();
+        collect_paths(&data_dir, "", prefix, after, limit.min(MAX_LIST_LIMIT) as usize, &mut paths);
+        let mut metas = Vec::with_capacity(paths.len());
+        for path in paths {
+            if let Some(meta) = self.stat(owner, app_name, &path)? {
+                metas.push(meta);
+            }
+        }
+        Ok(metas)
+    }
+    fn take_upload(&mut self, owner: &str, app_name: &str, id: u64) -> Option<Upload> {
+        match self.uploads.get(&id) {
+            Some(u) if u.owner == owner && u.app_name == app_name => self.uploads.remove(&id),
+            _ => None,
+        }
+    }
+    fn expire_uploads(&mut self) {
+        let expired = self.uploads.iter()
+            .filter(|(_, u)| u.started.elapsed() > UPLOAD_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            let upload = self.uploads.remove(&id).unwrap();
+            warn!("Upload #{} of {}:{}:{} timed out.", id, &upload.owner, &upload.app_name, &upload.path);
+            discard(upload);
+        }
+    }
+    fn app_dir(&self, owner: &str, app_name: &str) -> Result<PathBuf, String> {
+        // The names end up in a path, so they must not be able to point anywhere but their own directory.
+        if !is_safe_name(owner) || !is_safe_name(app_name) {
+            return Err(format!("{:?}:{:?} cannot be used to name a blob directory.", owner, app_name));
+        }
+        Ok(self.dir.join(owner).join(app_name))
+    }
+    fn data_path(&self, owner: &str, app_name: &str, path: &str) -> Result<PathBuf, String> {
+        check_path(path)?;
+        Ok(self.app_dir(owner, app_name)?.join("data").join(path))
+    }
+    /// Metadata mirrors the blob's own path, so it can only clash with another blob's where their data would clash too.
+    fn meta_path(&self, owner: &str, app_name: &str, path: &str) -> Result<PathBuf, String> {
+        check_path(path)?;
+        Ok(self.app_dir(owner, app_name)?.join("metadata").join(path))
+    }
+    /// Where metadata used to be kept, `meta/<path>.json`, which clashed with that of blobs under `<path>.json/`.
+    /// Still read for blobs stored back then.
+    fn legacy_meta_path(&self, owner: &str, app_name: &str, path: &str) -> Result<PathBuf, String> {
+        check_path(path)?;
+        Ok(self.app_dir(owner, app_name)?.join("meta").join(format!("{}.json", path)))
+    }
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+    && !name.starts_with('.')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+/// Blob paths are `/`-separated, and none of their segments can be empty or lead out of the app's directory.
+fn check_path(path: &str) -> Result<(), String> {
+    let valid = !path.is_empty()
+        && path.len() <= MAX_PATH_LEN
+        && path.split('/').all(|seg| !seg.is_empty() && seg != "." && seg != ".." && !seg.contains('\\') && !seg.contains('\0'));
+    match valid {
+        true => Ok(()),
+        false => Err(format!("{:?} is not a valid blob path.", path)),
+    }
+}
+fn create_parent(path: &Path) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|e| format!("Could not create {:?}: {}", parent, e)),
+        None => Ok(()),
+    }
+}
+fn discard(upload: Upload) {
+    drop(upload.file);
+    if let Err(e) = fs::remove_file(&upload.tmp_path) {
+        warn!("Could not remove {:?}: {}", &upload.tmp_path, e);
+    }
+}
+/// Removes the temporary files of uploads cut short by a restart, which can never be finished.
+fn remove_stale_uploads(dir: &Path) {
+    let mut removed = 0;
+    for owner in fs::read_dir(dir).into_iter().flatten().flatten() {
+        for app in fs::read_dir(owner.path()).into_iter().flatten().flatten() {
+            for tmp in fs::read_dir(app.path().join("tmp")).into_iter().flatten().flatten() {
+                match fs::remove_file(tmp.path()) {
+                    Ok(_) => removed += 1,
+                    Err(e) => warn!("Could not remove {:?}: {}", tmp.path(), e),
+                }
+            }
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} uploads left unfinished by a restart.", removed);
+    }
+}
+/// Collects up to `limit` paths of the files under `dir` that start with `prefix` and come after `after`, in path order.
+/// Paths are relative to where the walk started and `/`-separated. Directories that cannot hold such paths are not entered,
+/// and the walk stops once it has enough, so later pages do not cost a walk of the whole tree.
+fn collect_paths(dir: &Path, relative: &str, prefix: &str, after: Option<&str>, limit: usize, paths: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    // A directory's paths all start with its own followed by a `/`, so sorting it by that puts it where they go in path order.
+    let mut entries = entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = match relative {
+                "" => name,
+                _ => format!("{}/{}", relative, name),
+            };
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => Some((format!("{}/", path), true, entry.path())),
+                Ok(t) if t.is_file() => Some((path, false, entry.path())),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    for (path, is_dir, full_path) in entries {
+        if paths.len() >= limit {
+            return;
+        }
+        if is_dir {
+            let may_match = path.starts_with(prefix) || prefix.starts_with(path.as_str());
+            let may_come_after = after.map_or(true, |a| path.as_str() > a || a.starts_with(path.as_str()));
+            if may_match && may_come_after {
+                collect_paths(&full_path, path.trim_end_matches('/'), prefix, after, limit, paths);
+            }
+        } else if path.starts_with(prefix) && after.map_or(true, |a| path.as_str() > a) {
+            paths.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zhur_common::testing::TempDir;
+
+    /// The store goes away along with the returned directory.
+    fn temp_store(name: &str) -> (TempDir, BlobStore) {
+        let dir = TempDir::new("zhur_blob", name);
+        let store = BlobStore::new(dir.path().to_owned());
+        (dir, store)
+    }
+    fn put(store: &mut BlobStore, path: &str, bytes: &[u8]) -> BlobMeta {
+        let id = match store.handle(Core2Blob::BeginPut("alice".into(), "app".into(), path.into(), "text/plain".into())) {
+            Blob2Core::Upload(id) => id,
+            other => panic!("Expected an upload, got {:?}", other),
+        };
+        store.handle(Core2Blob::PutChunk("alice".into(), "app".into(), id, bytes.to_vec()));
+        match store.handle(Core2Blob::FinishPut("alice".into(), "app".into(), id)) {
+            Blob2Core::Meta(meta) => meta,
+            other => panic!("Expected metadata, got {:?}", other),
+        }
+    }
+    fn list(store: &mut BlobStore, prefix: &str, after: Option<&str>, limit: u32) -> Vec<String> {
+        match store.handle(Core2Blob::List("alice".into(), "app".into(), prefix.into(), after.map(String::from), limit)) {
+            Blob2Core::Metas(metas) => metas.into_iter().map(|m| m.path).collect(),
+            other => panic!("Expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lists_pages_in_path_order() {
+        let (_dir, mut store) = temp_store("list");
+        for path in &["b", "a/y", "a-c", "a/x", "c/d/e"] {
+            put(&mut store, path, b"data");
+        }
+        assert_eq!(list(&mut store, "", None, 10), vec!["a-c", "a/x", "a/y", "b", "c/d/e"]);
+        assert_eq!(list(&mut store, "", None, 2), vec!["a-c", "a/x"]);
+        assert_eq!(list(&mut store, "", Some("a/x"), 2), vec!["a/y", "b"]);
+        assert_eq!(list(&mut store, "a/", None, 10), vec!["a/x", "a/y"]);
+        assert_eq!(list(&mut store, "c/d", None, 10), vec!["c/d/e"]);
+    }
+    #[test]
+    fn metadata_does_not_clash_with_json_paths() {
+        let (_dir, mut store) = temp_store("clash");
+        put(&mut store, "notes", b"one");
+        put(&mut store, "notes.json/today", b"two");
+        assert_eq!(store.stat("alice", "app", "notes").unwrap().unwrap().content_type, "text/plain");
+        assert_eq!(store.stat("alice", "app", "notes.json/today").unwrap().unwrap().size, 3);
+    }
+    #[test]
+    fn reads_pinned_to_a_replaced_blob_fail() {
+        let (_dir, mut store) = temp_store("etag");
+        let old = put(&mut store, "file", b"old");
+        let read = |store: &mut BlobStore, etag: &str| store.handle(Core2Blob::GetChunk("alice".into(), "app".into(), "file".into(), 0, 10, Some(etag.to_owned())));
+        assert!(matches!(read(&mut store, &old.etag), Blob2Core::Chunk(c) if c == b"old"));
+        let new = put(&mut store, "file", b"new");
+        assert_ne!(old.etag, new.etag);
+        assert!(matches!(read(&mut store, &old.etag), Blob2Core::Modified));
+    }
+    #[test]
+    fn caps_uploads_per_app() {
+        let (_dir, mut store) = temp_store("cap");
+        for _ in 0..MAX_UPLOADS_PER_APP {
+            assert!(matches!(store.handle(Core2Blob::BeginPut("alice".into(), "app".into(), "f".into(), "text/plain".into())), Blob2Core::Upload(_)));
+        }
+        assert!(matches!(store.handle(Core2Blob::BeginPut("alice".into(), "app".into(), "f".into(), "text/plain".into())), Blob2Core::Error(_)));
+        assert!(matches!(store.handle(Core2Blob::BeginPut("alice".into(), "other".into(), "f".into(), "text/plain".into())), Blob2Core::Upload(_)));
+    }
+    #[test]
+    fn removes_unfinished_uploads_on_restart() {
+        let (_dir, mut store) = temp_store("restart");
+        store.handle(Core2Blob::BeginPut("alice".into(), "app".into(), "f".into(), "text/plain".into()));
+        let tmp_dir = store.dir.join("alice").join("app").join("tmp");
+        assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 1);
+        BlobStore::new(store.dir.clone());
+        assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 0);
+    }
+}
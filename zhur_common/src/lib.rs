@@ -8,3 +8,10 @@ pub use zmq;
 
 /// Inter-module messaging types and code.
 pub mod msg;
+/// Hex encoding for binary data in text form.
+pub mod hex;
+/// ZMQ socket setup shared between clients.
+pub mod sockets;
+/// Helpers for tests in other crates.
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -2,5 +2,17 @@
 pub mod chan;
 /// Types used for messaging between the core and the app store.
 pub mod core_apst;
+/// Types used for messaging between the gateway and the app store.
+pub mod gate_apst;
+/// Types used for administering the app store.
+pub mod apst_admin;
 /// Types used for messaging between the core and the K/V store.
-pub mod core_kv;
\ No newline at end of file
+pub mod core_kv;
+/// Types used for administering the K/V store.
+pub mod kv_admin;
+/// Types used for replicating the K/V store.
+pub mod kv_repl;
+/// Types used for messaging between the core and the SQL service.
+pub mod core_sql;
+/// Types used for messaging between the core and the blob service.
+pub mod core_blob;
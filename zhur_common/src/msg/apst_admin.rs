@@ -0,0 +1,30 @@
+use crate::serde::{Deserialize, Serialize};
+
+pub const DEFAULT_APST_ADMIN_ENDPOINT: &str = "tcp://127.0.0.1:8084";
+
+/// This type represents administrative requests made to the app store, such as by the portal.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Admin2Apst {
+    /// Serve owner:app at the domain given first, replacing whatever it pointed to.
+    /// Whoever asks is expected to have checked that the owner controls the domain.
+    SetDomain(String, String, String),
+    /// Stop serving anything at the given domain.
+    RemoveDomain(String),
+    /// List the domains pointing to the given owner's apps.
+    ListDomains(String),
+    /// Get owner:app ready for its new code, by bringing its SQL database schema up to date with the migrations in its directory.
+    /// Send this before the new code is served; if it fails, the old schema is left in place and the code should not go out.
+    Deploy(String, String),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Apst2Admin {
+    /// A `SetDomain` or `RemoveDomain` was carried out.
+    Done,
+    /// Replies to `ListDomains` with domain, owner and app name triples.
+    Domains(Vec<(String, String, String)>),
+    /// Replies to `Deploy` with the schema version the app's database is now at.
+    Deployed(u32),
+    /// The request could not be carried out for the reason given.
+    Error(String),
+}
@@ -0,0 +1,53 @@
+use crate::serde::{Deserialize, Serialize};
+pub use zhur_invk::blob::{BlobMeta, BLOB_CHUNK_SIZE};
+
+pub const DEFAULT_BLOB_ENDPOINT: &str = "tcp://127.0.0.1:8089";
+
+/// This type represents requests made by the core to the blob service. Every app has its own namespace of blob paths, named by owner and app.
+/// Blobs are put in three steps: `BeginPut` starts an upload, `PutChunk` appends to it, and `FinishPut` makes it visible in one go,
+/// replacing any blob at the same path.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Core2Blob {
+    /// Start putting a blob at owner:app:path with the given content type.
+    BeginPut(String, String, String, String),
+    /// Append a chunk of at most `BLOB_CHUNK_SIZE` bytes to owner:app's upload with the given ID.
+    PutChunk(String, String, u64, Vec<u8>),
+    /// Finish owner:app's upload with the given ID, storing the blob.
+    FinishPut(String, String, u64),
+    /// Throw away owner:app's upload with the given ID.
+    AbortPut(String, String, u64),
+    /// Read up to the given number of bytes of owner:app:path, starting at the given offset.
+    /// If an etag is given, the read only goes ahead if the blob still has it, so a blob read in several chunks is never a mix of two versions.
+    GetChunk(String, String, String, u64, u32, Option<String>),
+    /// Get the metadata of owner:app:path.
+    Stat(String, String, String),
+    /// Delete owner:app:path.
+    Delete(String, String, String),
+    /// List up to the given number of owner:app's blobs whose paths start with the given prefix, in path order,
+    /// starting after the given path if any.
+    List(String, String, String, Option<String>, u32),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Blob2Core {
+    /// Replies to `BeginPut` with the ID of the new upload.
+    Upload(u64),
+    /// Replies to `GetChunk`. Shorter than asked for at the end of the blob.
+    Chunk(Vec<u8>),
+    /// Replies to `FinishPut` and `Stat`.
+    Meta(BlobMeta),
+    /// Replies to `List`.
+    Metas(Vec<BlobMeta>),
+    /// The blob or upload asked about does not exist.
+    NotFound,
+    /// A `PutChunk`, `AbortPut` or `Delete` was carried out.
+    Done,
+    /// The request failed for the reason given.
+    Error(String),
+    /// Replies to `GetChunk` when the blob no longer has the etag given, having been put again.
+    Modified,
+}
+/// A `Core2Blob` request tagged with an ID chosen by whoever sends it, so that replies arriving out of order can be matched to their requests.
+pub type TaggedCore2Blob = (u64, Core2Blob);
+/// A `Blob2Core` reply tagged with the ID of the request it answers.
+pub type TaggedBlob2Core = (u64, Blob2Core);
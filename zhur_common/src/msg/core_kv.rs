@@ -1,20 +1,88 @@
 use crate::serde::{Deserialize, Serialize};
+pub use zhur_invk::kv::{Durability, IndexExtractor, KvChange, KvOp};
 
 pub const DEFAULT_KV_ENDPOINT: &str = "tcp://127.0.0.1:8085";
+/// The K/V store publishes a `KvChange` here for every write, with `owner:table:` as the topic frame.
+pub const DEFAULT_KV_PUB_ENDPOINT: &str = "tcp://127.0.0.1:8087";
 
 /// This type represents requests made by the core to the KV store.
+/// Writes carry a `Durability`, saying when the K/V store may acknowledge them.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Core2Kv {
     /// Get owner:table:key.
     KvGet(String, String, String),
-    /// Set owner:table:key:value.
-    KvSet(String, String, String, Vec<u8>),
+    /// Set owner:table:key:value, optionally expiring after the given number of seconds.
+    KvSet(String, String, String, Vec<u8>, Option<u64>, Durability),
     /// Delete owner:table:key.
-    KvDel(String, String, String)
+    KvDel(String, String, String, Durability),
+    /// Get several keys from owner:table in one go.
+    KvGetMany(String, String, Vec<String>),
+    /// Set several key/value pairs in owner:table in one go. They are written atomically and without TTLs.
+    KvSetMany(String, String, Vec<(String, Vec<u8>)>, Durability),
+    /// Define a secondary index named by the third string on owner:table, replacing any index of the same name.
+    KvDefineIndex(String, String, String, IndexExtractor),
+    /// Drop the secondary index owner:table:index.
+    KvDropIndex(String, String, String),
+    /// Find the entries in owner:table whose value for the index named by the third string equals the given bytes.
+    KvQueryIndex(String, String, String, Vec<u8>),
+    /// Atomically increment the counter at owner:table:key, treating a missing one as zero, and return its new value.
+    /// Counters are stored as bincode-encoded `u64`s.
+    KvIncrement(String, String, String, Durability),
+    /// List up to the given number of entries in owner:table in key order, starting after the given key if any.
+    KvScan(String, String, Option<String>, u32),
+    /// Atomically raise the counter at owner:table:key to at least the given value, treating a missing one as zero,
+    /// and return its value afterwards. Counters never go down this way, whatever increments happen alongside.
+    KvRaiseCounter(String, String, String, u64, Durability),
+}
+impl Core2Kv {
+    /// Whether the request changes anything, and so cannot be served by a read-only replica.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Core2Kv::KvGet(..) | Core2Kv::KvGetMany(..) | Core2Kv::KvQueryIndex(..) | Core2Kv::KvScan(..) => false,
+            _ => true,
+        }
+    }
+    /// The first table or index name in the request containing a colon, if any.
+    /// Colons separate the owner, table and index names in the K/V store's keys, so such names could reach into other tables.
+    pub fn invalid_name(&self) -> Option<&str> {
+        let names: Vec<&str> = match self {
+            Core2Kv::KvGet(_, table, _) | Core2Kv::KvSet(_, table, ..) | Core2Kv::KvDel(_, table, ..)
+            | Core2Kv::KvGetMany(_, table, _) | Core2Kv::KvSetMany(_, table, ..)
+            | Core2Kv::KvIncrement(_, table, ..) | Core2Kv::KvScan(_, table, ..)
+            | Core2Kv::KvRaiseCounter(_, table, ..) => vec![table],
+            Core2Kv::KvDefineIndex(_, table, name, _) | Core2Kv::KvDropIndex(_, table, name)
+            | Core2Kv::KvQueryIndex(_, table, name, _) => vec![table, name],
+        };
+        names.into_iter().find(|name| name.contains(':'))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Kv2Core {
     Value(Option<Vec<u8>>),
+    /// Replies to `KvGetMany`, in the order the keys were requested in.
+    Values(Vec<Option<Vec<u8>>>),
+    /// Key/value pairs, replying to `KvQueryIndex` and `KvScan`.
+    Entries(Vec<(String, Vec<u8>)>),
+    /// The new value of a counter, replying to `KvIncrement` and `KvRaiseCounter`.
+    Counter(u64),
     OperationSuccessful,
-}
\ No newline at end of file
+    /// The request was a write, but the K/V store is a read-only replica.
+    ReadOnly,
+    /// The request could not be deserialized. Sent with the request's tag, as long as that much of it could be read.
+    Malformed,
+    /// The request names a table or index with a colon in it, given here.
+    InvalidName(String),
+}
+
+/// A `Core2Kv` request along with the ID of the HTTP request that led to it, if any, so the K/V store's logs can be matched with the gateway's and the core's.
+pub type TracedCore2Kv = (Option<String>, Core2Kv);
+/// A `Core2Kv` request tagged with an ID chosen by the core, so that replies arriving out of order can be matched to their requests,
+/// and traced back to the HTTP request that led to it.
+pub type TaggedCore2Kv = (u64, Option<String>, Core2Kv);
+/// A `Kv2Core` reply tagged with the ID of the request it answers.
+pub type TaggedKv2Core = (u64, Kv2Core);
+/// Produces the topic a change to owner:table is published under, for subscribing to a single owner's or table's changes.
+pub fn change_topic(owner: &str, table: &str) -> String {
+    format!("{}:{}:", owner, table)
+}
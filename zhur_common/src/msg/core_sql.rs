@@ -0,0 +1,33 @@
+use crate::serde::{Deserialize, Serialize};
+pub use zhur_invk::sql::{SqlExecuted, SqlRows, SqlValue};
+
+pub const DEFAULT_SQL_ENDPOINT: &str = "tcp://127.0.0.1:8088";
+
+/// This type represents requests made to the SQL service, by the core for queries and by the app store for migrations.
+/// Every app gets a database of its own, named by owner and app.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Core2Sql {
+    /// Run a statement returning rows against owner:app's database, binding the given values to its parameters in order.
+    Query(String, String, String, Vec<SqlValue>),
+    /// Run a statement not returning rows against owner:app's database, binding the given values to its parameters in order.
+    Execute(String, String, String, Vec<SqlValue>),
+    /// Bring owner:app's database schema up to date. The nth migration takes the schema from version n-1 to version n,
+    /// and only migrations past the database's current version are run, each in a transaction of its own.
+    Migrate(String, String, Vec<String>),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Sql2Core {
+    /// Replies to `Query`.
+    Rows(SqlRows),
+    /// Replies to `Execute`.
+    Executed(SqlExecuted),
+    /// Replies to `Migrate` with the schema version the database is now at.
+    Migrated(u32),
+    /// The request failed, for the reason given by SQLite.
+    Error(String),
+}
+/// A `Core2Sql` request tagged with an ID chosen by whoever sends it, so that replies arriving out of order can be matched to their requests.
+pub type TaggedCore2Sql = (u64, Core2Sql);
+/// A `Sql2Core` reply tagged with the ID of the request it answers.
+pub type TaggedSql2Core = (u64, Sql2Core);
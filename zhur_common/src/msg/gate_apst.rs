@@ -0,0 +1,113 @@
+use crate::serde::{Deserialize, Serialize};
+
+pub const DEFAULT_GATE_APST_ENDPOINT: &str = "tcp://127.0.0.1:8083";
+
+/// The formats an error page template can be written in.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum PageFormat {
+    Html,
+    Json,
+}
+
+/// A template for a page the gateway shows when invoking an app fails.
+/// `{{status}}`, `{{reason}}` and `{{message}}` in it get filled in by the gateway.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorPageTemplate {
+    /// The status code the template is for, or `None` for any status without a template of its own.
+    pub status: Option<u16>,
+    pub format: PageFormat,
+    pub template: String,
+}
+
+/// A token-bucket rate limit: up to `burst` requests at once, refilled at `per_second` requests a second.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Whether the limit lets any requests through at all and refills at a finite rate.
+    pub fn is_valid(&self) -> bool {
+        self.per_second.is_finite() && self.per_second > 0.0 && self.burst > 0
+    }
+}
+
+/// Rate limits an app sets for itself.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AppRateLimits {
+    /// For all of the app's clients together, in place of the gateway's default.
+    #[serde(default)]
+    pub app: Option<RateLimit>,
+    /// For each client of the app.
+    #[serde(default)]
+    pub per_ip: Option<RateLimit>,
+}
+
+/// What the app store knows about an app besides its code, as its owner described it.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AppManifest {
+    /// Branded error pages, used in place of the platform's own.
+    pub error_pages: Vec<ErrorPageTemplate>,
+    /// The largest request body the app accepts, in bytes, in place of the gateway's default.
+    pub max_body_size: Option<u64>,
+    pub rate_limits: AppRateLimits,
+}
+
+/// Brings a hostname into the form custom domains are stored and looked up in: lowercase, without a port or a trailing dot.
+pub fn normalize_domain(host: &str) -> String {
+    let host = match host.rfind(':') {
+        // A colon in a bracketed IPv6 address is not a port separator.
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// This type represents requests made by the gateway to the app store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Gate2Apst {
+    /// Get the manifest of owner:app.
+    Manifest(String, String),
+    /// Find the app a custom domain points to. Domains are matched case-insensitively and without a port.
+    ResolveDomain(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Apst2Gate {
+    /// Replies to `Manifest`. Apps without a manifest of their own get an empty one.
+    Manifest(AppManifest),
+    /// Replies to `ResolveDomain` with the owner and name of the app, if any.
+    Domain(Option<(String, String)>),
+    /// The request could not be carried out for the reason given.
+    Error(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_normalized() {
+        assert_eq!(normalize_domain("Shop.Example.COM"), "shop.example.com");
+        assert_eq!(normalize_domain("shop.example.com:8443"), "shop.example.com");
+        assert_eq!(normalize_domain("shop.example.com."), "shop.example.com");
+        assert_eq!(normalize_domain("shop.example.com.:443"), "shop.example.com");
+        assert_eq!(normalize_domain(""), "");
+    }
+    #[test]
+    fn rate_limits_must_let_requests_through() {
+        assert!(RateLimit { per_second: 0.5, burst: 1 }.is_valid());
+        assert!(!RateLimit { per_second: 0.0, burst: 10 }.is_valid());
+        assert!(!RateLimit { per_second: -1.0, burst: 10 }.is_valid());
+        assert!(!RateLimit { per_second: f64::NAN, burst: 10 }.is_valid());
+        assert!(!RateLimit { per_second: f64::INFINITY, burst: 10 }.is_valid());
+        assert!(!RateLimit { per_second: 10.0, burst: 0 }.is_valid());
+    }
+    #[test]
+    fn ipv6_addresses_keep_their_colons() {
+        assert_eq!(normalize_domain("[::1]"), "[::1]");
+        assert_eq!(normalize_domain("[::1]:8080"), "[::1]");
+        assert_eq!(normalize_domain("[FE80::1]"), "[fe80::1]");
+    }
+}
@@ -0,0 +1,48 @@
+use crate::serde::{Deserialize, Serialize};
+
+pub const DEFAULT_KV_ADMIN_ENDPOINT: &str = "tcp://127.0.0.1:8086";
+
+/// This type represents administrative requests made to the K/V store by operator tooling rather than by the core.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Admin2Kv {
+    /// Export data into a file at the given path, relative to the K/V store's export directory.
+    /// The optional owner and table restrict the export to one owner's data or one of their tables.
+    Export(String, Option<String>, Option<String>),
+    /// Import a file produced by `Export` from the given path, relative to the K/V store's export directory, overwriting entries with the same keys.
+    Import(String),
+    /// List the names of an owner's tables.
+    ListTables(String),
+    /// List up to the given number of keys in owner:table in key order, starting after the given key if any.
+    ListKeys(String, String, Option<String>, u32),
+    /// Get owner:table:key.
+    Get(String, String, String),
+    /// Set owner:table:key:value, without a TTL.
+    Set(String, String, String, Vec<u8>),
+    /// Delete owner:table:key.
+    Del(String, String, String),
+    /// Get the store's replication status.
+    ReplicationStatus,
+    /// Turn a read-only replica into a primary, so it stops following its old primary and starts taking writes.
+    Promote,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Kv2Admin {
+    /// An export finished, having written this many entries.
+    Exported(u64),
+    /// An import finished, having read this many entries.
+    Imported(u64),
+    /// Replies to `ListTables`.
+    Tables(Vec<String>),
+    /// Replies to `ListKeys`.
+    Keys(Vec<String>),
+    /// Replies to `Get`.
+    Value(Option<Vec<u8>>),
+    /// A `Set`, `Del` or `Promote` was carried out.
+    Done,
+    /// Replies to `ReplicationStatus`: whether the store is a read-only replica, the sequence number of the latest write in its own log,
+    /// and, for replicas, how far into the primary's log it has caught up.
+    Replication(bool, u64, Option<u64>),
+    /// The request could not be carried out for the reason given.
+    Error(String),
+}
@@ -0,0 +1,38 @@
+use crate::serde::{Deserialize, Serialize};
+
+pub const DEFAULT_KV_REPL_ENDPOINT: &str = "tcp://127.0.0.1:8090";
+
+/// One write recorded in a K/V store's replication log: the new value of a key in one of its trees, or `None` for a removal.
+/// Trees are named as in the store itself, such as `data` or `expiry`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReplOp {
+    pub tree: String,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// This type represents requests made by a replica K/V store to the primary it follows.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Replica2Primary {
+    /// Get the sequence number of the latest write in the primary's log.
+    Head,
+    /// Get up to the given number of logged writes following the given sequence number.
+    Pull(u64, u32),
+    /// Get up to the given number of pairs from the named tree in key order, starting after the given key if any.
+    /// Used to copy everything over when a replica is new or has fallen too far behind.
+    Snapshot(String, Option<Vec<u8>>, u32),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Primary2Replica {
+    /// Replies to `Head`.
+    Head(u64),
+    /// Replies to `Pull` with writes and their sequence numbers, oldest first. Empty if the replica is up to date.
+    Ops(Vec<(u64, ReplOp)>),
+    /// Replies to `Pull` when the writes asked for are no longer in the log, so the replica has to start over from a snapshot.
+    Behind,
+    /// Replies to `Snapshot`.
+    Pairs(Vec<(Vec<u8>, Vec<u8>)>),
+    /// The request failed for the reason given.
+    Error(String),
+}
@@ -0,0 +1,12 @@
+use zmq::{Context, Socket, SocketType};
+
+/// Creates a REQ socket whose receives give up after `timeout_ms`, for clients that must not hang on a service that is down.
+/// A plain REQ socket that timed out waiting for a reply refuses to send again, so the socket is made relaxed,
+/// and correlated so that a reply turning up late is dropped instead of being taken for the answer to the next request.
+pub fn timed_req_socket(zmq_ctx: &Context, timeout_ms: i32) -> Socket {
+    let socket = zmq_ctx.socket(SocketType::REQ).expect("Could not create a REQ socket!");
+    socket.set_req_relaxed(true).unwrap();
+    socket.set_req_correlate(true).unwrap();
+    socket.set_rcvtimeo(timeout_ms).unwrap();
+    socket
+}
@@ -0,0 +1,26 @@
+use std::path::{Path, PathBuf};
+
+/// A fresh directory for a test to keep files in, removed along with everything in it once dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory for the test `name` of `crate_name`.
+    /// The process ID is part of its name, so that test runs going on at the same time do not trip over each other.
+    pub fn new(crate_name: &str, name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}_test_{}_{}", crate_name, name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("Could not create a directory for a test!");
+        Self { path }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
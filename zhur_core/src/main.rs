@@ -1,6 +1,6 @@
 use zhur_common::{init_logger, log::*, msg::core_apst::DEFAULT_APST_ENDPOINT, zmq::SocketType};
 use zhur_common::{flume::unbounded, zmq::Context};
-use zhur_core::{CoreServer, WasmPool, serve::KvServer};
+use zhur_core::{CoreServer, WasmPool, serve::{EmbeddedKvServer, KvServer, KvWatcher, SqlServer, BlobServer, Watches, EMBEDDED_KV_PUB_ENDPOINT}};
 
 fn main() {
     init_logger();
@@ -16,9 +16,42 @@ fn main() {
     apst_req_socket.connect(&apst_endpoint).unwrap();
     let (invoc_env_tx, invoc_env_rx) = unbounded();
     let (kv_req_tx, kv_req_rx) = unbounded();
-    let kv_server = KvServer::new(&zmq_ctx, kv_req_rx);
-    kv_server.run_as_thread();
-    let _wasm_pool = WasmPool::new(3, invoc_env_rx, apst_req_socket, kv_req_tx).run_as_thread();
+    let watches = Watches::default();
+    let (kv_change_tx, kv_change_rx) = unbounded();
+    let kv_mode = match std::env::var("ZHUR_CORE_KV_MODE") {
+        Ok(m) => m,
+        Err(_) => {
+            warn!("ZHUR_CORE_KV_MODE not set. Assuming default of \"remote\".");
+            "remote".to_string()
+        }
+    };
+    let kv_watcher = match kv_mode.as_str() {
+        "remote" => {
+            KvServer::new(&zmq_ctx, kv_req_rx).run_as_thread();
+            KvWatcher::new(&zmq_ctx, watches.clone(), kv_change_tx)
+        },
+        "embedded" => {
+            let kv_server = match EmbeddedKvServer::new(&zmq_ctx, kv_req_rx) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("{} Exiting.", e);
+                    std::process::exit(1);
+                }
+            };
+            kv_server.run_as_threads();
+            KvWatcher::with_endpoint(&zmq_ctx, EMBEDDED_KV_PUB_ENDPOINT, watches.clone(), kv_change_tx)
+        },
+        other => {
+            error!("ZHUR_CORE_KV_MODE set to invalid value {:?}. Expected \"remote\" or \"embedded\". Exiting.", other);
+            std::process::exit(1);
+        }
+    };
+    kv_watcher.run_as_thread();
+    let (sql_req_tx, sql_req_rx) = unbounded();
+    SqlServer::new(&zmq_ctx, sql_req_rx).run_as_thread();
+    let (blob_req_tx, blob_req_rx) = unbounded();
+    BlobServer::new(&zmq_ctx, blob_req_rx).run_as_thread();
+    let _wasm_pool = WasmPool::new(3, invoc_env_rx, kv_change_rx, apst_req_socket, kv_req_tx, sql_req_tx, blob_req_tx, watches).run_as_thread();
     let server = CoreServer::new(&zmq_ctx, invoc_env_tx);
     loop {
         server.handle();
@@ -1,13 +1,19 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::wasm::InvocEnv;
-use zhur_common::{log::*, msg::{chan::Envelope, core_kv::{Core2Kv, DEFAULT_KV_ENDPOINT, Kv2Core}}};
-use zhur_common::zmq::{Context, Socket, SocketType};
+use crate::wasm::{InvocEnv, KvChangeDelivery};
+use zhur_common::{log::*, msg::{chan::Envelope, core_kv::{DEFAULT_KV_ENDPOINT, DEFAULT_KV_PUB_ENDPOINT, Kv2Core, KvChange, KvOp, TaggedCore2Kv, TaggedKv2Core, TracedCore2Kv}}};
+use zhur_common::msg::core_sql::{Core2Sql, Sql2Core, TaggedCore2Sql, TaggedSql2Core, DEFAULT_SQL_ENDPOINT};
+use zhur_common::msg::core_blob::{Blob2Core, Core2Blob, TaggedBlob2Core, TaggedCore2Blob, DEFAULT_BLOB_ENDPOINT};
+use zhur_common::zmq::{poll, Context, Socket, SocketType, POLLIN};
 use zhur_common::{
     bincode::{deserialize, serialize},
     flume::{unbounded, Receiver, Sender},
 };
 use zhur_invk::{HttpRes, Invocation, InvocationError};
+use zhur_kv::{start_replication, AdminServer, GroupCommitter, KvConfig, KvStore, Publisher, Sweeper, DEFAULT_ADMIN_ENDPOINT};
 /// The ZMQ server that takes invocations incoming from the gateway and sends back bytes.
 pub struct CoreServer {
     rep_socket: Socket,
@@ -71,7 +77,7 @@ impl CoreServer {
                 return serialize(&err).unwrap()
             }
         };
-        trace!("Got an invocation for {}:{}", &inv.owner, &inv.app_name);
+        debug!("[{}] Got an invocation for {}:{}", &inv.request_id, &inv.owner, &inv.app_name);
         let env = (inv, self.reply_tx.clone());
         self.invoc_env_tx
             .send(env)
@@ -83,14 +89,25 @@ impl CoreServer {
     }
 }
 
-/// This ZMQ server handles K/V store requests.
+/// In-process endpoint over which the `KvServer`'s forwarding thread hands requests over to its socket thread.
+const KV_FORWARD_ENDPOINT: &str = "inproc://zhur_core_kv_forward";
+
+/// This ZMQ client relays K/V store requests from every executor over a single DEALER socket.
+/// Requests are tagged with IDs, so any number of them can be in flight at once and a slow one does not hold up the rest.
 pub struct KvServer {
-    req_socket: Socket,
-    kv_req_rx: Receiver<Envelope<Core2Kv, Kv2Core>>
+    /// Talks to the K/V store's ROUTER socket.
+    dealer_socket: Socket,
+    /// Receives serialized requests from the forwarding thread.
+    pull_socket: Socket,
+    /// Used by the forwarding thread, as ZMQ sockets must not be shared between threads.
+    push_socket: Socket,
+    kv_req_rx: Receiver<Envelope<TracedCore2Kv, Kv2Core>>,
+    /// Reply senders for the requests still in flight, by request ID.
+    pending: Arc<Mutex<HashMap<u64, Sender<Kv2Core>>>>,
 }
 impl KvServer {
-    pub fn new(zmq_ctx: &Context, kv_req_rx: Receiver<Envelope<Core2Kv, Kv2Core>>) -> Self {
-        let socket = zmq_ctx.socket(SocketType::REQ).unwrap();
+    pub fn new(zmq_ctx: &Context, kv_req_rx: Receiver<Envelope<TracedCore2Kv, Kv2Core>>) -> Self {
+        let dealer_socket = zmq_ctx.socket(SocketType::DEALER).unwrap();
         let endpoint = match std::env::var("ZHUR_KV_ENDPOINT") {
             Ok(e) => e,
             Err(_) => {
@@ -98,30 +115,662 @@ impl KvServer {
                 DEFAULT_KV_ENDPOINT.to_string()
             }
         };
-        socket.connect(&endpoint).expect("Could not connect to KV server!");
+        dealer_socket.connect(&endpoint).expect("Could not connect to KV server!");
+        let pull_socket = zmq_ctx.socket(SocketType::PULL).unwrap();
+        pull_socket.bind(KV_FORWARD_ENDPOINT).expect("Could not bind the K/V forwarding socket!");
+        let push_socket = zmq_ctx.socket(SocketType::PUSH).unwrap();
+        push_socket.connect(KV_FORWARD_ENDPOINT).expect("Could not connect to the K/V forwarding socket!");
         Self {
-            req_socket: socket,
-            kv_req_rx
+            dealer_socket,
+            pull_socket,
+            push_socket,
+            kv_req_rx,
+            pending: Arc::new(Mutex::new(HashMap::new())),
         }
     }
-    fn handle(&self) {
-        let (request, return_tx) = self.kv_req_rx.recv().unwrap();
-        trace!("Got Core2Kv request.");
-        let req_bytes = serialize(&request).unwrap();
-        self.req_socket.send(&req_bytes, 0).unwrap();
-        trace!("Sent Core2Kv request to K/V store.");
-        let res_bytes = self.req_socket.recv_bytes(0).unwrap();
-        trace!("Got reply from K/V.");
-        let response = deserialize(&res_bytes).unwrap();
-        trace!("Deserialized to Kv2Core, sending back.");
-        return_tx.send(response).unwrap();
+    /// Tags requests coming in from executors with IDs and passes them on to the socket thread.
+    fn forward(push_socket: Socket, kv_req_rx: Receiver<Envelope<TracedCore2Kv, Kv2Core>>, pending: Arc<Mutex<HashMap<u64, Sender<Kv2Core>>>>) {
+        let mut next_id: u64 = 0;
+        loop {
+            let ((request_id, request), return_tx) = kv_req_rx.recv().unwrap();
+            next_id = next_id.wrapping_add(1);
+            trace!("[{}] Got Core2Kv request, tagging it as #{}.", request_id.as_deref().unwrap_or("-"), next_id);
+            pending.lock().unwrap().insert(next_id, return_tx);
+            let tagged: TaggedCore2Kv = (next_id, request_id, request);
+            push_socket.send(serialize(&tagged).unwrap(), 0).unwrap();
+        }
+    }
+    /// Sends forwarded requests out to the K/V store and routes its replies back to whoever made each request.
+    fn relay(dealer_socket: &Socket, pull_socket: &Socket, pending: &Mutex<HashMap<u64, Sender<Kv2Core>>>) {
+        let mut items = [
+            pull_socket.as_poll_item(POLLIN),
+            dealer_socket.as_poll_item(POLLIN),
+        ];
+        poll(&mut items, -1).unwrap();
+        if items[0].is_readable() {
+            let req_bytes = pull_socket.recv_bytes(0).unwrap();
+            // The empty delimiter frame stands in for the envelope a REQ socket would add.
+            dealer_socket.send_multipart(vec![Vec::new(), req_bytes], 0).unwrap();
+            trace!("Sent Core2Kv request to K/V store.");
+        }
+        if items[1].is_readable() {
+            let frames = dealer_socket.recv_multipart(0).unwrap();
+            let res_bytes = frames.last().map(|f| f.as_slice()).unwrap_or_default();
+            let (id, response) = match deserialize::<TaggedKv2Core>(res_bytes) {
+                Ok(r) => r,
+                // If at least the tag is readable, dropping the reply sender tells the executor waiting on it that no reply is coming.
+                Err(_) => match deserialize::<u64>(res_bytes) {
+                    Ok(id) => {
+                        warn!("Got a reply from the K/V store to #{} that could not be deserialized to a TaggedKv2Core.", id);
+                        pending.lock().unwrap().remove(&id);
+                        return;
+                    },
+                    Err(_) => {
+                        warn!("Got a reply from the K/V store that could not be deserialized to a TaggedKv2Core.");
+                        return;
+                    }
+                }
+            };
+            trace!("Got reply to #{} from K/V.", id);
+            match pending.lock().unwrap().remove(&id) {
+                Some(return_tx) => return_tx.send(response).unwrap(),
+                None => warn!("Got a reply from the K/V store to #{}, which is not in flight.", id)
+            }
+        }
     }
     pub fn run_as_thread(self) -> JoinHandle<()> {
-        std::thread::spawn(move || {
-            let server = self;
-            loop {
-                server.handle();
+        let Self { dealer_socket, pull_socket, push_socket, kv_req_rx, pending } = self;
+        let forward_pending = pending.clone();
+        std::thread::Builder::new()
+            .name("kv_forward".to_owned())
+            .spawn(move || Self::forward(push_socket, kv_req_rx, forward_pending))
+            .expect("Could not launch the K/V forwarding thread!");
+        std::thread::Builder::new()
+            .name("kv_relay".to_owned())
+            .spawn(move || {
+                loop {
+                    Self::relay(&dealer_socket, &pull_socket, &pending);
+                }
+            })
+            .expect("Could not launch the K/V relay thread!")
+    }
+}
+
+/// In-process endpoint over which the `SqlServer`'s forwarding thread hands requests over to its socket thread.
+const SQL_FORWARD_ENDPOINT: &str = "inproc://zhur_core_sql_forward";
+/// How long a SQL request may go unanswered before the executor waiting on it is failed.
+/// This is well past the SQL service's own deadline on statements, so it only comes into play when the service is down or swamped.
+const SQL_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the relay thread wakes up to look for requests past `SQL_REPLY_TIMEOUT`, if nothing else wakes it.
+const SQL_EXPIRY_INTERVAL_MS: i64 = 1_000;
+/// Reply senders for the requests still in flight, by request ID, along with when each request was sent.
+type PendingSql = Mutex<HashMap<u64, (Instant, Sender<Sql2Core>)>>;
+
+/// This ZMQ client relays SQL requests from every executor over a single DEALER socket.
+/// Requests are tagged with IDs, so any number of them can be in flight at once, the same as with the `KvServer`.
+pub struct SqlServer {
+    /// Talks to the SQL service's ROUTER socket.
+    dealer_socket: Socket,
+    /// Receives serialized requests from the forwarding thread.
+    pull_socket: Socket,
+    /// Used by the forwarding thread, as ZMQ sockets must not be shared between threads.
+    push_socket: Socket,
+    sql_req_rx: Receiver<Envelope<Core2Sql, Sql2Core>>,
+    /// Reply senders for the requests still in flight.
+    pending: Arc<PendingSql>,
+}
+impl SqlServer {
+    pub fn new(zmq_ctx: &Context, sql_req_rx: Receiver<Envelope<Core2Sql, Sql2Core>>) -> Self {
+        let dealer_socket = zmq_ctx.socket(SocketType::DEALER).unwrap();
+        let endpoint = match std::env::var("ZHUR_SQL_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_SQL_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_SQL_ENDPOINT);
+                DEFAULT_SQL_ENDPOINT.to_string()
+            }
+        };
+        dealer_socket.connect(&endpoint).expect("Could not connect to the SQL service!");
+        let pull_socket = zmq_ctx.socket(SocketType::PULL).unwrap();
+        pull_socket.bind(SQL_FORWARD_ENDPOINT).expect("Could not bind the SQL forwarding socket!");
+        let push_socket = zmq_ctx.socket(SocketType::PUSH).unwrap();
+        push_socket.connect(SQL_FORWARD_ENDPOINT).expect("Could not connect to the SQL forwarding socket!");
+        Self {
+            dealer_socket,
+            pull_socket,
+            push_socket,
+            sql_req_rx,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Tags requests coming in from executors with IDs and passes them on to the socket thread.
+    fn forward(push_socket: Socket, sql_req_rx: Receiver<Envelope<Core2Sql, Sql2Core>>, pending: Arc<PendingSql>) {
+        let mut next_id: u64 = 0;
+        loop {
+            let (request, return_tx) = sql_req_rx.recv().unwrap();
+            next_id = next_id.wrapping_add(1);
+            trace!("Got Core2Sql request, tagging it as #{}.", next_id);
+            pending.lock().unwrap().insert(next_id, (Instant::now(), return_tx));
+            let tagged: TaggedCore2Sql = (next_id, request);
+            push_socket.send(serialize(&tagged).unwrap(), 0).unwrap();
+        }
+    }
+    /// Sends forwarded requests out to the SQL service and routes its replies back to whoever made each request.
+    fn relay(dealer_socket: &Socket, pull_socket: &Socket, pending: &PendingSql) {
+        let mut items = [
+            pull_socket.as_poll_item(POLLIN),
+            dealer_socket.as_poll_item(POLLIN),
+        ];
+        poll(&mut items, SQL_EXPIRY_INTERVAL_MS).unwrap();
+        Self::expire(pending, SQL_REPLY_TIMEOUT);
+        if items[0].is_readable() {
+            let req_bytes = pull_socket.recv_bytes(0).unwrap();
+            // The empty delimiter frame stands in for the envelope a REQ socket would add.
+            dealer_socket.send_multipart(vec![Vec::new(), req_bytes], 0).unwrap();
+            trace!("Sent Core2Sql request to the SQL service.");
+        }
+        if items[1].is_readable() {
+            let frames = dealer_socket.recv_multipart(0).unwrap();
+            let res_bytes = frames.last().map(|f| f.as_slice()).unwrap_or_default();
+            let (id, response) = match deserialize::<TaggedSql2Core>(res_bytes) {
+                Ok(r) => r,
+                Err(_) => match deserialize::<u64>(res_bytes) {
+                    Ok(id) => {
+                        warn!("Got a reply from the SQL service to #{} that could not be deserialized to a TaggedSql2Core.", id);
+                        (id, Sql2Core::Error("Malformed reply from the SQL service.".to_owned()))
+                    },
+                    Err(_) => {
+                        warn!("Got a reply from the SQL service that could not be deserialized to a TaggedSql2Core.");
+                        return;
+                    }
+                }
+            };
+            trace!("Got reply to #{} from SQL.", id);
+            match pending.lock().unwrap().remove(&id) {
+                // The executor may have given up on its invocation in the meantime.
+                Some((_, return_tx)) => { let _ = return_tx.send(response); },
+                None => warn!("Got a reply from the SQL service to #{}, which is not in flight or has timed out.", id)
+            }
+        }
+    }
+    /// Fails every request that has been in flight for longer than `timeout`, so no executor waits on the SQL service forever.
+    fn expire(pending: &PendingSql, timeout: Duration) {
+        pending.lock().unwrap().retain(|id, (sent_at, return_tx)| {
+            if sent_at.elapsed() < timeout {
+                return true;
+            }
+            warn!("The SQL service did not reply to #{} within {:?}.", id, timeout);
+            let _ = return_tx.send(Sql2Core::Error("The SQL service did not reply in time.".to_owned()));
+            false
+        });
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        let Self { dealer_socket, pull_socket, push_socket, sql_req_rx, pending } = self;
+        let forward_pending = pending.clone();
+        std::thread::Builder::new()
+            .name("sql_forward".to_owned())
+            .spawn(move || Self::forward(push_socket, sql_req_rx, forward_pending))
+            .expect("Could not launch the SQL forwarding thread!");
+        std::thread::Builder::new()
+            .name("sql_relay".to_owned())
+            .spawn(move || {
+                loop {
+                    Self::relay(&dealer_socket, &pull_socket, &pending);
+                }
+            })
+            .expect("Could not launch the SQL relay thread!")
+    }
+}
+
+/// In-process endpoint over which the `BlobServer`'s forwarding thread hands requests over to its socket thread.
+const BLOB_FORWARD_ENDPOINT: &str = "inproc://zhur_core_blob_forward";
+/// How long a blob request may go unanswered before the executor waiting on it is failed.
+/// Each request moves at most one chunk, so even a busy blob service answers long before this.
+const BLOB_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the relay thread wakes up to look for requests past `BLOB_REPLY_TIMEOUT`, if nothing else wakes it.
+const BLOB_EXPIRY_INTERVAL_MS: i64 = 1_000;
+/// Reply senders for the blob requests still in flight, by request ID, along with when each request was sent.
+type PendingBlob = Mutex<HashMap<u64, (Instant, Sender<Blob2Core>)>>;
+
+/// This ZMQ client relays blob requests from every executor over a single DEALER socket.
+/// Requests are tagged with IDs, so one executor streaming a large blob in chunks does not hold up the others.
+pub struct BlobServer {
+    /// Talks to the blob service's ROUTER socket.
+    dealer_socket: Socket,
+    /// Receives serialized requests from the forwarding thread.
+    pull_socket: Socket,
+    /// Used by the forwarding thread, as ZMQ sockets must not be shared between threads.
+    push_socket: Socket,
+    blob_req_rx: Receiver<Envelope<Core2Blob, Blob2Core>>,
+    /// Reply senders for the requests still in flight.
+    pending: Arc<PendingBlob>,
+}
+impl BlobServer {
+    pub fn new(zmq_ctx: &Context, blob_req_rx: Receiver<Envelope<Core2Blob, Blob2Core>>) -> Self {
+        let dealer_socket = zmq_ctx.socket(SocketType::DEALER).unwrap();
+        let endpoint = match std::env::var("ZHUR_BLOB_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_BLOB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_BLOB_ENDPOINT);
+                DEFAULT_BLOB_ENDPOINT.to_string()
+            }
+        };
+        dealer_socket.connect(&endpoint).expect("Could not connect to the blob service!");
+        let pull_socket = zmq_ctx.socket(SocketType::PULL).unwrap();
+        pull_socket.bind(BLOB_FORWARD_ENDPOINT).expect("Could not bind the blob forwarding socket!");
+        let push_socket = zmq_ctx.socket(SocketType::PUSH).unwrap();
+        push_socket.connect(BLOB_FORWARD_ENDPOINT).expect("Could not connect to the blob forwarding socket!");
+        Self {
+            dealer_socket,
+            pull_socket,
+            push_socket,
+            blob_req_rx,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Tags requests coming in from executors with IDs and passes them on to the socket thread.
+    fn forward(push_socket: Socket, blob_req_rx: Receiver<Envelope<Core2Blob, Blob2Core>>, pending: Arc<PendingBlob>) {
+        let mut next_id: u64 = 0;
+        loop {
+            let (request, return_tx) = blob_req_rx.recv().unwrap();
+            next_id = next_id.wrapping_add(1);
+            trace!("Got Core2Blob request, tagging it as #{}.", next_id);
+            pending.lock().unwrap().insert(next_id, (Instant::now(), return_tx));
+            let tagged: TaggedCore2Blob = (next_id, request);
+            push_socket.send(serialize(&tagged).unwrap(), 0).unwrap();
+        }
+    }
+    /// Sends forwarded requests out to the blob service and routes its replies back to whoever made each request.
+    fn relay(dealer_socket: &Socket, pull_socket: &Socket, pending: &PendingBlob) {
+        let mut items = [
+            pull_socket.as_poll_item(POLLIN),
+            dealer_socket.as_poll_item(POLLIN),
+        ];
+        poll(&mut items, BLOB_EXPIRY_INTERVAL_MS).unwrap();
+        Self::expire(pending, BLOB_REPLY_TIMEOUT);
+        if items[0].is_readable() {
+            let req_bytes = pull_socket.recv_bytes(0).unwrap();
+            // The empty delimiter frame stands in for the envelope a REQ socket would add.
+            dealer_socket.send_multipart(vec![Vec::new(), req_bytes], 0).unwrap();
+            trace!("Sent Core2Blob request to the blob service.");
+        }
+        if items[1].is_readable() {
+            let frames = dealer_socket.recv_multipart(0).unwrap();
+            let res_bytes = frames.last().map(|f| f.as_slice()).unwrap_or_default();
+            let (id, response) = match deserialize::<TaggedBlob2Core>(res_bytes) {
+                Ok(r) => r,
+                Err(_) => match deserialize::<u64>(res_bytes) {
+                    Ok(id) => {
+                        warn!("Got a reply from the blob service to #{} that could not be deserialized to a TaggedBlob2Core.", id);
+                        (id, Blob2Core::Error("Malformed reply from the blob service.".to_owned()))
+                    },
+                    Err(_) => {
+                        warn!("Got a reply from the blob service that could not be deserialized to a TaggedBlob2Core.");
+                        return;
+                    }
+                }
+            };
+            trace!("Got reply to #{} from the blob service.", id);
+            match pending.lock().unwrap().remove(&id) {
+                Some((_, return_tx)) => { let _ = return_tx.send(response); },
+                None => warn!("Got a reply from the blob service to #{}, which is not in flight or has timed out.", id)
+            }
+        }
+    }
+    /// Fails every request that has been in flight for longer than `timeout`, so an executor reading a blob is not stuck if the blob service goes away.
+    fn expire(pending: &PendingBlob, timeout: Duration) {
+        pending.lock().unwrap().retain(|id, (sent_at, return_tx)| {
+            if sent_at.elapsed() < timeout {
+                return true;
+            }
+            warn!("The blob service did not reply to #{} within {:?}.", id, timeout);
+            let _ = return_tx.send(Blob2Core::Error("The blob service did not reply in time.".to_owned()));
+            false
+        });
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        let Self { dealer_socket, pull_socket, push_socket, blob_req_rx, pending } = self;
+        let forward_pending = pending.clone();
+        std::thread::Builder::new()
+            .name("blob_forward".to_owned())
+            .spawn(move || Self::forward(push_socket, blob_req_rx, forward_pending))
+            .expect("Could not launch the blob forwarding thread!");
+        std::thread::Builder::new()
+            .name("blob_relay".to_owned())
+            .spawn(move || {
+                loop {
+                    Self::relay(&dealer_socket, &pull_socket, &pending);
+                }
+            })
+            .expect("Could not launch the blob relay thread!")
+    }
+}
+
+/// In-process endpoint the embedded K/V store publishes its changes on, for the `KvWatcher`.
+pub const EMBEDDED_KV_PUB_ENDPOINT: &str = "inproc://zhur_core_kv_changes";
+
+/// Runs the K/V store inside the core process, for single-machine deployments where the ZMQ hop to `zhur_kv` is not worth it.
+/// It takes the same `Core2Kv` requests as the `KvServer` and is configured with the same environment variables as `zhur_kv`.
+/// The admin endpoint and change publisher are served from here too, so the portal and `zhur_kv_admin` keep working.
+pub struct EmbeddedKvServer {
+    store: KvStore,
+    workers: usize,
+    kv_req_rx: Receiver<Envelope<TracedCore2Kv, Kv2Core>>,
+}
+impl EmbeddedKvServer {
+    /// Opens the K/V database and starts its background threads, returning a description of the problem if that fails.
+    pub fn new(zmq_ctx: &Context, kv_req_rx: Receiver<Envelope<TracedCore2Kv, Kv2Core>>) -> Result<Self, String> {
+        let config = KvConfig::from_env()
+        .map_err(|e| format!("Invalid K/V store configuration: {}", e))?;
+        let store = KvStore::new(config.open()?, config.default_durability);
+        // The sockets are bound before any thread starts, so a taken port fails startup without leaving half the store running.
+        let admin_socket = zmq_ctx.socket(SocketType::REP).unwrap();
+        let admin_endpoint = match std::env::var("ZHUR_KV_ADMIN_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_KV_ADMIN_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_ADMIN_ENDPOINT);
+                DEFAULT_ADMIN_ENDPOINT.to_string()
+            }
+        };
+        admin_socket.bind(&admin_endpoint)
+        .map_err(|e| format!("Could not bind the K/V admin socket to {}: {}.", &admin_endpoint, e))?;
+        let pub_socket = zmq_ctx.socket(SocketType::PUB).unwrap();
+        let pub_endpoint = match std::env::var("ZHUR_KV_PUB_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_KV_PUB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_PUB_ENDPOINT);
+                DEFAULT_KV_PUB_ENDPOINT.to_string()
             }
+        };
+        pub_socket.bind(EMBEDDED_KV_PUB_ENDPOINT)
+        .map_err(|e| format!("Could not bind the embedded K/V change publishing socket: {}.", e))?;
+        pub_socket.bind(&pub_endpoint)
+        .map_err(|e| format!("Could not bind the K/V change publishing socket to {}: {}.", &pub_endpoint, e))?;
+        Sweeper::new(store.clone(), config.sweep_interval).run_as_thread();
+        GroupCommitter::new(store.clone(), config.group_commit_interval).run_as_thread();
+        start_replication(zmq_ctx, &config, &store);
+        AdminServer::new(admin_socket, store.clone(), config.export_dir.clone()).run_as_thread();
+        Publisher::new(pub_socket, store.clone()).run_as_thread();
+        Ok(Self {
+            store,
+            workers: config.workers,
+            kv_req_rx,
+        })
+    }
+    /// Carries out requests from executors on `ZHUR_KV_WORKERS` threads, all taking from the same channel.
+    pub fn run_as_threads(self) -> Vec<JoinHandle<()>> {
+        info!("Serving K/V requests in-process with {} workers.", self.workers);
+        (0..self.workers).map(|id| {
+            let store = self.store.clone();
+            let kv_req_rx = self.kv_req_rx.clone();
+            std::thread::Builder::new()
+                .name(format!("kv_embedded_worker_{}", id))
+                .spawn(move || {
+                    for ((request_id, request), return_tx) in kv_req_rx.iter() {
+                        trace!("[{}] Embedded K/V worker #{} got a request.", request_id.as_deref().unwrap_or("-"), id);
+                        // The executor may have gone away in the meantime, in which case nobody needs the reply.
+                        let _ = return_tx.send(store.handle(request));
+                    }
+                })
+                .expect("Could not launch an embedded K/V worker thread!")
         })
+        .collect()
+    }
+}
+
+/// The K/V tables apps have asked to watch, shared between the executors and the `KvWatcher`.
+pub type Watches = Arc<Mutex<WatchList>>;
+
+/// How long a change an app made while handling a change is waited for, so it can be kept from reaching the app, before it is forgotten.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A change an app handling a change is about to make, so it can be told apart when the K/V store publishes it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Echo {
+    /// Exactly this change.
+    Op(KvOp),
+    /// The entry being set to a value not known until the K/V store replies, as with counter increments.
+    /// Noted before the write is sent, so the change cannot arrive before it.
+    AnySet,
+}
+impl Echo {
+    fn matches(&self, op: &KvOp) -> bool {
+        match self {
+            Self::Op(o) => o == op,
+            Self::AnySet => matches!(op, KvOp::Set(_)),
+        }
+    }
+}
+
+/// The K/V tables apps have asked to watch, as (owner, app name, table) triples.
+/// Apps can only watch their owner's tables, and watches last until the core restarts or the app unwatches.
+#[derive(Default)]
+pub struct WatchList {
+    tables: HashSet<(String, String, String)>,
+    /// Changes apps made to tables they watch while handling a change, by (owner, app name, table, key), with when they were made.
+    /// These are not delivered back to the app that made them, or a handler writing to a table it watches would keep calling itself.
+    echoes: HashMap<(String, String, String, String), Vec<(Instant, Echo)>>,
+}
+impl WatchList {
+    pub fn watch(&mut self, owner: &str, app_name: &str, table: &str) {
+        self.tables.insert((owner.to_owned(), app_name.to_owned(), table.to_owned()));
+    }
+    pub fn unwatch(&mut self, owner: &str, app_name: &str, table: &str) {
+        self.tables.remove(&(owner.to_owned(), app_name.to_owned(), table.to_owned()));
+        self.echoes.retain(|(o, a, t, _), _| !(o == owner && a == app_name && t == table));
     }
-}
\ No newline at end of file
+    /// Notes that an app handling a change is about to make another, so it does not get delivered back to the app.
+    /// Only matters for tables the app watches.
+    pub fn expect_echo(&mut self, owner: &str, app_name: &str, table: &str, key: &str, echo: Echo) {
+        self.forget_old_echoes();
+        if !self.tables.contains(&(owner.to_owned(), app_name.to_owned(), table.to_owned())) {
+            return;
+        }
+        self.echoes.entry((owner.to_owned(), app_name.to_owned(), table.to_owned(), key.to_owned()))
+        .or_default()
+        .push((Instant::now(), echo));
+    }
+    /// Takes back an echo noted with `expect_echo`, for a change that ended up not being made.
+    pub fn withdraw_echo(&mut self, owner: &str, app_name: &str, table: &str, key: &str, echo: &Echo) {
+        self.take_echo(&(owner.to_owned(), app_name.to_owned(), table.to_owned(), key.to_owned()), |e| e == echo);
+    }
+    /// Lists the apps, as (owner, app name) pairs, a change should be delivered to, leaving out any app the change is an echo of.
+    pub fn recipients(&mut self, change: &KvChange) -> Vec<(String, String)> {
+        self.forget_old_echoes();
+        let watchers = self.tables.iter()
+        .filter(|(owner, _, table)| owner == &change.owner && table == &change.table)
+        .map(|(owner, app_name, _)| (owner.clone(), app_name.clone()))
+        .collect::<Vec<_>>();
+        watchers.into_iter()
+        .filter(|(owner, app_name)| {
+            let echo_key = (owner.clone(), app_name.clone(), change.table.clone(), change.key.clone());
+            if self.take_echo(&echo_key, |e| e.matches(&change.op)) {
+                trace!("Not delivering a change to {}:{}:{} back to {}:{}, which made it.", &change.owner, &change.table, &change.key, owner, app_name);
+                return false;
+            }
+            true
+        })
+        .collect()
+    }
+    /// Removes the oldest echo noted under `echo_key` that `pred` picks, returning whether there was one.
+    fn take_echo<F: Fn(&Echo) -> bool>(&mut self, echo_key: &(String, String, String, String), pred: F) -> bool {
+        let echoes = match self.echoes.get_mut(echo_key) {
+            Some(e) => e,
+            None => return false,
+        };
+        let found = match echoes.iter().position(|(_, e)| pred(e)) {
+            Some(i) => {
+                echoes.remove(i);
+                true
+            },
+            None => false,
+        };
+        if echoes.is_empty() {
+            self.echoes.remove(echo_key);
+        }
+        found
+    }
+    /// Drops echoes that never arrived, e.g. because the write did not actually change anything the K/V store reports.
+    fn forget_old_echoes(&mut self) {
+        self.echoes.retain(|_, echoes| {
+            echoes.retain(|(at, _)| at.elapsed() < ECHO_TIMEOUT);
+            !echoes.is_empty()
+        });
+    }
+}
+
+/// This ZMQ subscriber receives changes published by the K/V store and passes them on to the apps watching the tables they were made in.
+pub struct KvWatcher {
+    sub_socket: Socket,
+    watches: Watches,
+    delivery_tx: Sender<KvChangeDelivery>,
+}
+impl KvWatcher {
+    /// Subscribes to the out-of-process K/V store's changes.
+    pub fn new(zmq_ctx: &Context, watches: Watches, delivery_tx: Sender<KvChangeDelivery>) -> Self {
+        let endpoint = match std::env::var("ZHUR_KV_PUB_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_KV_PUB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_PUB_ENDPOINT);
+                DEFAULT_KV_PUB_ENDPOINT.to_string()
+            }
+        };
+        Self::with_endpoint(zmq_ctx, &endpoint, watches, delivery_tx)
+    }
+    /// Subscribes to the changes published at the given endpoint, such as `EMBEDDED_KV_PUB_ENDPOINT`.
+    pub fn with_endpoint(zmq_ctx: &Context, endpoint: &str, watches: Watches, delivery_tx: Sender<KvChangeDelivery>) -> Self {
+        let socket = zmq_ctx.socket(SocketType::SUB).unwrap();
+        socket.connect(endpoint).expect("Could not connect to the K/V change publisher!");
+        // Which apps watch what changes at runtime, so we take in everything and filter here rather than juggle subscriptions.
+        socket.set_subscribe(b"").unwrap();
+        Self {
+            sub_socket: socket,
+            watches,
+            delivery_tx,
+        }
+    }
+    fn handle(&self) {
+        let frames = self.sub_socket.recv_multipart(0).unwrap();
+        let change_bytes = match frames.last() {
+            Some(b) => b,
+            None => return,
+        };
+        let change = match deserialize::<KvChange>(change_bytes) {
+            Ok(c) => c,
+            Err(_) => {
+                warn!("Got a message from the K/V publisher that could not be deserialized to a KvChange.");
+                return;
+            }
+        };
+        let watchers = self.watches.lock().unwrap().recipients(&change);
+        for (owner, app_name) in watchers {
+            trace!("Delivering a change to {}:{}:{} to {}:{}.", &change.owner, &change.table, &change.key, &owner, &app_name);
+            self.delivery_tx.send((owner, app_name, change_bytes.clone()))
+            .expect("Expected to be able to pass a K/V change on to the WasmPool.");
+        }
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_watcher".to_owned())
+            .spawn(move || {
+                let watcher = self;
+                loop {
+                    watcher.handle();
+                }
+            })
+            .expect("Could not launch the K/V watcher thread!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zhur_common::msg::core_kv::{KvChange, KvOp};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+    use zhur_common::flume::unbounded;
+    use zhur_common::msg::core_sql::Sql2Core;
+    use zhur_common::msg::core_blob::Blob2Core;
+    use super::{BlobServer, Echo, SqlServer, WatchList};
+
+    fn change(key: &str, op: KvOp) -> KvChange {
+        KvChange {
+            owner: "alice".to_owned(),
+            table: "todos".to_owned(),
+            key: key.to_owned(),
+            op,
+        }
+    }
+
+    #[test]
+    fn changes_go_to_every_watcher_of_the_table() {
+        let mut watches = WatchList::default();
+        watches.watch("alice", "app", "todos");
+        watches.watch("alice", "other", "todos");
+        watches.watch("alice", "app", "users");
+        watches.watch("bob", "app", "todos");
+        let mut recipients = watches.recipients(&change("k", KvOp::Del));
+        recipients.sort();
+        assert_eq!(recipients, vec![("alice".to_owned(), "app".to_owned()), ("alice".to_owned(), "other".to_owned())]);
+    }
+
+    #[test]
+    fn echoes_skip_only_the_app_that_made_them_once() {
+        let mut watches = WatchList::default();
+        watches.watch("alice", "app", "todos");
+        watches.watch("alice", "other", "todos");
+        watches.expect_echo("alice", "app", "todos", "k", Echo::Op(KvOp::Set(b"v".to_vec())));
+        // A different change to the same key is not the echo.
+        assert_eq!(watches.recipients(&change("k", KvOp::Del)).len(), 2);
+        assert_eq!(watches.recipients(&change("k", KvOp::Set(b"v".to_vec()))), vec![("alice".to_owned(), "other".to_owned())]);
+        assert_eq!(watches.recipients(&change("k", KvOp::Set(b"v".to_vec()))).len(), 2);
+    }
+
+    #[test]
+    fn withdrawn_and_unwatched_echoes_are_forgotten() {
+        let mut watches = WatchList::default();
+        watches.watch("alice", "app", "todos");
+        watches.expect_echo("alice", "app", "todos", "k", Echo::Op(KvOp::Del));
+        watches.withdraw_echo("alice", "app", "todos", "k", &Echo::Op(KvOp::Del));
+        assert_eq!(watches.recipients(&change("k", KvOp::Del)).len(), 1);
+        watches.expect_echo("alice", "app", "todos", "k", Echo::Op(KvOp::Del));
+        watches.unwatch("alice", "app", "todos");
+        watches.watch("alice", "app", "todos");
+        assert_eq!(watches.recipients(&change("k", KvOp::Del)).len(), 1);
+        // Tables the app does not watch never produce echoes.
+        watches.expect_echo("alice", "app", "users", "k", Echo::Op(KvOp::Del));
+        watches.watch("alice", "app", "users");
+        assert_eq!(watches.recipients(&KvChange { table: "users".to_owned(), ..change("k", KvOp::Del) }).len(), 1);
+    }
+
+    #[test]
+    fn increments_are_kept_from_the_app_whatever_the_new_value() {
+        let mut watches = WatchList::default();
+        watches.watch("alice", "app", "counters");
+        let counter = |op| KvChange { table: "counters".to_owned(), ..change("hits", op) };
+        watches.expect_echo("alice", "app", "counters", "hits", Echo::AnySet);
+        assert_eq!(watches.recipients(&counter(KvOp::Del)).len(), 1);
+        assert!(watches.recipients(&counter(KvOp::Set(7u64.to_le_bytes().to_vec()))).is_empty());
+        assert_eq!(watches.recipients(&counter(KvOp::Set(8u64.to_le_bytes().to_vec()))).len(), 1);
+    }
+    #[test]
+    fn sql_requests_time_out() {
+        let (old_tx, old_rx) = unbounded();
+        let (new_tx, new_rx) = unbounded();
+        let pending = Mutex::new(HashMap::new());
+        pending.lock().unwrap().insert(1, (Instant::now() - Duration::from_secs(60), old_tx));
+        pending.lock().unwrap().insert(2, (Instant::now(), new_tx));
+        SqlServer::expire(&pending, Duration::from_secs(30));
+        assert!(matches!(old_rx.try_recv(), Ok(Sql2Core::Error(_))));
+        assert!(new_rx.try_recv().is_err());
+        assert_eq!(pending.lock().unwrap().len(), 1);
+    }
+    #[test]
+    fn blob_requests_time_out() {
+        let (tx, rx) = unbounded();
+        let pending = Mutex::new(HashMap::new());
+        pending.lock().unwrap().insert(1, (Instant::now() - Duration::from_secs(60), tx));
+        BlobServer::expire(&pending, Duration::from_secs(30));
+        assert!(matches!(rx.try_recv(), Ok(Blob2Core::Error(_))));
+        assert!(pending.lock().unwrap().is_empty());
+    }
+}
@@ -7,3 +7,5 @@ pub mod pool;
 
 pub type InvocEnv = Envelope<Invocation, Vec<u8>>;
 pub type PayloadEnv = Envelope<Vec<u8>, Vec<u8>>;
+/// A K/V change for an app watching the table it happened in: the app's owner and name, then the serialized `KvChange`.
+pub type KvChangeDelivery = (String, String, Vec<u8>);
@@ -1,9 +1,10 @@
 use std::thread::JoinHandle;
 
 use inner::{Metadata, InnerExecutor};
-use zhur_common::{flume::{unbounded, Receiver, Sender}, log::*, msg::{chan::Envelope, core_kv::{Core2Kv, Kv2Core}}};
+use zhur_common::{flume::{unbounded, Receiver, Sender}, log::*, msg::{chan::Envelope, core_kv::{Durability, Kv2Core, TracedCore2Kv}, core_sql::{Core2Sql, Sql2Core}, core_blob::{Blob2Core, Core2Blob}}};
 
 use super::PayloadEnv;
+use crate::serve::Watches;
 /// The inner execution logic.
 mod inner;
 /// Service logic.
@@ -31,7 +32,10 @@ pub enum ExecutorMsg {
     /// Issued on app renames.
     Rename(String),
     /// Self-explanatory. We're only passing in a payload and expecting a serialized reply which the core won't need to deserialize, thus we use `Vec<u8>` rather than complex types.
-    Invoke(PayloadEnv),
+    /// The request ID comes first, so it can be passed along with any K/V requests the app makes.
+    Invoke(String, PayloadEnv),
+    /// Pass a serialized `KvChange` to the app's `handle_kv_change` function. Nobody waits for a reply.
+    KvChange(Vec<u8>),
     /// Shut the inner thread down.
     Shutdown,
 }
@@ -48,12 +52,18 @@ impl Executor {
             .send(ExecutorMsg::LoadCode(owner, app_name, code))
             .expect("Could not pass WASM code down to inner executor.");
     }
-    pub fn invoke(&mut self, envelope: PayloadEnv) {
+    pub fn invoke(&mut self, request_id: String, envelope: PayloadEnv) {
         self.free = false;
         self.msg_tx
-            .send(ExecutorMsg::Invoke(envelope))
+            .send(ExecutorMsg::Invoke(request_id, envelope))
             .expect("Could not pass invocation down to inner executor.");
     }
+    pub fn deliver_kv_change(&mut self, change: Vec<u8>) {
+        self.free = false;
+        self.msg_tx
+            .send(ExecutorMsg::KvChange(change))
+            .expect("Could not pass K/V change down to inner executor.");
+    }
     pub fn rename(&mut self, app_name: String) {
         self.app_name = app_name.clone();
         self.msg_tx
@@ -81,15 +91,19 @@ impl Executor {
             }
         }
     }
-    pub fn new(id: usize, owner: String, app_name: String, initial_code: Vec<u8>, kv_req_tx: Sender<Envelope<Core2Kv, Kv2Core>>) -> Self {
+    pub fn new(id: usize, owner: String, app_name: String, initial_code: Vec<u8>, kv_req_tx: Sender<Envelope<TracedCore2Kv, Kv2Core>>, sql_req_tx: Sender<Envelope<Core2Sql, Sql2Core>>, blob_req_tx: Sender<Envelope<Core2Blob, Blob2Core>>, watches: Watches) -> Self {
         let (msg_tx, msg_rx) = unbounded();
         let (done_tx, done_rx) = unbounded();
         let meta = Metadata {
             owner: owner.clone(),
             app_name: app_name.clone(),
-            id
+            id,
+            durability: Durability::Default,
+            request_id: None,
+            handling_change: false,
+            body_blob: None,
         };
-        let inner = InnerExecutor::new(meta, msg_rx, done_tx, initial_code, kv_req_tx);
+        let inner = InnerExecutor::new(meta, msg_rx, done_tx, initial_code, kv_req_tx, sql_req_tx, blob_req_tx, watches);
         Self {
             inner_thread: inner,
             owner,
@@ -1,8 +1,9 @@
 use std::{sync::{Arc, Mutex}, thread::JoinHandle};
 use super::{ExecutorMsg};
-use zhur_common::{bincode::{deserialize, serialize}, flume::{Receiver, Sender, unbounded}, msg::{chan::Envelope, core_kv::{Core2Kv, Kv2Core}}};
+use zhur_common::{bincode::{deserialize, serialize}, flume::{Receiver, Sender, unbounded}, msg::{chan::Envelope, core_kv::{Core2Kv, Durability, IndexExtractor, Kv2Core, KvOp, TracedCore2Kv}, core_sql::{Core2Sql, Sql2Core, SqlExecuted, SqlRows, SqlValue}, core_blob::{Blob2Core, BlobMeta, Core2Blob, BLOB_CHUNK_SIZE}}};
 use zhur_common::log::*;
-use zhur_invk::InvocationError;
+use zhur_invk::{HttpRes, InvocationError};
+use crate::serve::{Echo, Watches};
 use wapc::WapcHost;
 use wasm3_provider::Wasm3EngineProvider;
 use chrono::Utc;
@@ -13,8 +14,20 @@ pub struct Metadata {
     pub app_name: String,
     /// Inner executor's numeral ID. Inherited from the outer `Executor` struct.
     pub id: usize,
+    /// The durability the app asked for its K/V writes to have. Reset before every invocation and K/V change, and whenever new code is loaded.
+    pub durability: Durability,
+    /// The ID of the HTTP request being handled, if any. Passed along with K/V requests and shown to the app.
+    pub request_id: Option<String>,
+    /// Whether the app is handling a K/V change, in which case the changes it makes itself are not delivered back to it.
+    pub handling_change: bool,
+    /// The path of the blob the app asked for its response body to be, if any, which the core reads in after the app is done.
+    pub body_blob: Option<String>,
 }
 
+/// The largest blob an app may respond with, in bytes.
+/// The core reads the blob into memory and sends it to the gate as part of a single reply, so it must stay well clear of what either can hold.
+const MAX_BODY_BLOB_SIZE: u64 = 64 * 1024 * 1024;
+
 /// This struct contains the actual code engine used to run user-provided apps.
 pub struct InnerExecutor {
     metadata: Arc<Mutex<Metadata>>, // This is so the host's closure can refer to the executor's metadata.
@@ -22,21 +35,23 @@ pub struct InnerExecutor {
     msg_rx: Receiver<ExecutorMsg>,
     done_tx: Sender<()>,
     host: WapcHost,
+    /// Used to read in blobs apps respond with.
+    blob_req_tx: Sender<Envelope<Core2Blob, Blob2Core>>,
 }
 impl InnerExecutor {
     /// Creates an InnerExecutor in a thread. We can't first construct one and then run it as a thread because `WapcHost`s can't be moved between threads,
     /// so everything needs to be created in the new thread in one go.
-    pub fn new(meta: Metadata, msg_rx: Receiver<ExecutorMsg>, done_tx: Sender<()>, initial_code: Vec<u8>, kv_req_tx: Sender<Envelope<Core2Kv, Kv2Core>>) -> JoinHandle<()> {
+    pub fn new(meta: Metadata, msg_rx: Receiver<ExecutorMsg>, done_tx: Sender<()>, initial_code: Vec<u8>, kv_req_tx: Sender<Envelope<TracedCore2Kv, Kv2Core>>, sql_req_tx: Sender<Envelope<Core2Sql, Sql2Core>>, blob_req_tx: Sender<Envelope<Core2Blob, Blob2Core>>, watches: Watches) -> JoinHandle<()> {
         std::thread::Builder::new()
         .name(format!("inner_executor_{}", meta.id))
         .spawn(move || {
             let meta_arc = Arc::new(Mutex::new(meta));
             let callback_meta = meta_arc.clone();
+            let callback_sql_tx = sql_req_tx;
+            let body_blob_tx = blob_req_tx.clone();
             trace!("Creating a new wasm engine...");
             let host = WapcHost::new(Box::new(Wasm3EngineProvider::new(&initial_code)),
             move |_id, _bd, _ns, op, payload| {
-                let kv_tx = kv_req_tx.clone();
-                let (kv_rep_tx, kv_rep_rx) = unbounded();
                 trace!("Inner executor got host call for {:?}", op);
                 match op {
                     "whoami" => {
@@ -45,14 +60,17 @@ impl InnerExecutor {
                         let bytes = serialize(&strings).unwrap();
                         Ok(bytes)
                     },
+                    "request_id" => {
+                        let meta = callback_meta.lock().unwrap();
+                        Ok(serialize(&meta.request_id).unwrap())
+                    },
                     "kv_get" => {
                         let meta = callback_meta.lock().unwrap();
                         let owner = meta.owner.clone();
                         let (table, key) = deserialize::<(String, String)>(payload).unwrap();
                         let req = Core2Kv::KvGet(owner, table, key);
                         trace!("Requesting KvGet..");
-                        kv_tx.send((req, kv_rep_tx)).unwrap();
-                        let res = kv_rep_rx.recv().unwrap();
+                        let res = kv_call(&kv_req_tx, meta.request_id.clone(), req)?;
                         trace!("Requested KvGet.");
                         match res {
                             Kv2Core::Value(opt) => Ok(serialize(&opt).unwrap()),
@@ -63,20 +81,250 @@ impl InnerExecutor {
                         let meta = callback_meta.lock().unwrap();
                         let owner = meta.owner.clone();
                         let (table, key, value) = deserialize::<(String, String, Vec<u8>)>(payload).unwrap();
-                        let req = Core2Kv::KvSet(owner, table, key, value);
-                        kv_tx.send((req, kv_rep_tx)).unwrap();
-                        let _res = kv_rep_rx.recv().unwrap();
-                        dbg!(_res);
-                        Ok(Vec::new())
+                        let req = Core2Kv::KvSet(owner, table, key, value, None, meta.durability);
+                        kv_write_result("KvSet", kv_write(&kv_req_tx, &watches, &meta, req)?)
+                    },
+                    "kv_set_ttl" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, key, value, ttl) = deserialize::<(String, String, Vec<u8>, u64)>(payload).unwrap();
+                        let req = Core2Kv::KvSet(owner, table, key, value, Some(ttl), meta.durability);
+                        kv_write_result("KvSet", kv_write(&kv_req_tx, &watches, &meta, req)?)
                     },
                     "kv_del" => {
                         let meta = callback_meta.lock().unwrap();
                         let owner = meta.owner.clone();
                         let (table, key) = deserialize::<(String, String)>(payload).unwrap();
-                        let req = Core2Kv::KvDel(owner, table, key);
-                        kv_tx.send((req, kv_rep_tx)).unwrap();
-                        let _res = kv_rep_rx.recv().unwrap();
-                        dbg!(_res);
+                        let req = Core2Kv::KvDel(owner, table, key, meta.durability);
+                        kv_write_result("KvDel", kv_write(&kv_req_tx, &watches, &meta, req)?)
+                    },
+                    "kv_get_many" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, keys) = deserialize::<(String, Vec<String>)>(payload).unwrap();
+                        let req = Core2Kv::KvGetMany(owner, table, keys);
+                        trace!("Requesting KvGetMany..");
+                        let res = kv_call(&kv_req_tx, meta.request_id.clone(), req)?;
+                        trace!("Requested KvGetMany.");
+                        match res {
+                            Kv2Core::Values(values) => Ok(serialize(&values).unwrap()),
+                            _ => panic!("A KvGetMany returned something other than a list of values!")
+                        }
+                    },
+                    "kv_set_many" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, pairs) = deserialize::<(String, Vec<(String, Vec<u8>)>)>(payload).unwrap();
+                        let req = Core2Kv::KvSetMany(owner, table, pairs, meta.durability);
+                        kv_write_result("KvSetMany", kv_write(&kv_req_tx, &watches, &meta, req)?)
+                    },
+                    "kv_define_index" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, name, extractor) = deserialize::<(String, String, IndexExtractor)>(payload).unwrap();
+                        let req = Core2Kv::KvDefineIndex(owner, table, name, extractor);
+                        kv_write_result("KvDefineIndex", kv_call(&kv_req_tx, meta.request_id.clone(), req)?)
+                    },
+                    "kv_drop_index" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, name) = deserialize::<(String, String)>(payload).unwrap();
+                        let req = Core2Kv::KvDropIndex(owner, table, name);
+                        kv_write_result("KvDropIndex", kv_call(&kv_req_tx, meta.request_id.clone(), req)?)
+                    },
+                    "kv_query_index" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, name, value) = deserialize::<(String, String, Vec<u8>)>(payload).unwrap();
+                        let req = Core2Kv::KvQueryIndex(owner, table, name, value);
+                        trace!("Requesting KvQueryIndex..");
+                        let res = kv_call(&kv_req_tx, meta.request_id.clone(), req)?;
+                        trace!("Requested KvQueryIndex.");
+                        match res {
+                            Kv2Core::Entries(entries) => Ok(serialize(&entries).unwrap()),
+                            _ => panic!("A KvQueryIndex returned something other than a list of entries!")
+                        }
+                    },
+                    "kv_increment" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, key) = deserialize::<(String, String)>(payload).unwrap();
+                        let req = Core2Kv::KvIncrement(owner, table, key, meta.durability);
+                        match kv_write(&kv_req_tx, &watches, &meta, req)? {
+                            Kv2Core::Counter(n) => Ok(serialize(&n).unwrap()),
+                            Kv2Core::ReadOnly => Err("The K/V store is a read-only replica.".into()),
+                            _ => panic!("A KvIncrement returned something other than a counter!")
+                        }
+                    },
+                    "kv_raise_counter" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, key, at_least) = deserialize::<(String, String, u64)>(payload).unwrap();
+                        let req = Core2Kv::KvRaiseCounter(owner, table, key, at_least, meta.durability);
+                        match kv_write(&kv_req_tx, &watches, &meta, req)? {
+                            Kv2Core::Counter(n) => Ok(serialize(&n).unwrap()),
+                            Kv2Core::ReadOnly => Err("The K/V store is a read-only replica.".into()),
+                            _ => panic!("A KvRaiseCounter returned something other than a counter!")
+                        }
+                    },
+                    "kv_scan" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let owner = meta.owner.clone();
+                        let (table, after, limit) = deserialize::<(String, Option<String>, u32)>(payload).unwrap();
+                        let req = Core2Kv::KvScan(owner, table, after, limit);
+                        trace!("Requesting KvScan..");
+                        let res = kv_call(&kv_req_tx, meta.request_id.clone(), req)?;
+                        trace!("Requested KvScan.");
+                        match res {
+                            Kv2Core::Entries(entries) => Ok(serialize(&entries).unwrap()),
+                            _ => panic!("A KvScan returned something other than a list of entries!")
+                        }
+                    },
+                    "sql_query" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (sql, params) = deserialize::<(String, Vec<SqlValue>)>(payload).unwrap();
+                        let req = Core2Sql::Query(meta.owner.clone(), meta.app_name.clone(), sql, params);
+                        let (sql_rep_tx, sql_rep_rx) = unbounded();
+                        callback_sql_tx.send((req, sql_rep_tx)).unwrap();
+                        let result: Result<SqlRows, String> = match sql_rep_rx.recv().unwrap() {
+                            Sql2Core::Rows(rows) => Ok(rows),
+                            Sql2Core::Error(e) => Err(e),
+                            _ => panic!("A SQL query returned something other than rows or an error!")
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "sql_execute" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (sql, params) = deserialize::<(String, Vec<SqlValue>)>(payload).unwrap();
+                        let req = Core2Sql::Execute(meta.owner.clone(), meta.app_name.clone(), sql, params);
+                        let (sql_rep_tx, sql_rep_rx) = unbounded();
+                        callback_sql_tx.send((req, sql_rep_tx)).unwrap();
+                        let result: Result<SqlExecuted, String> = match sql_rep_rx.recv().unwrap() {
+                            Sql2Core::Executed(executed) => Ok(executed),
+                            Sql2Core::Error(e) => Err(e),
+                            _ => panic!("A SQL statement returned something other than its outcome or an error!")
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_begin_put" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (path, content_type) = deserialize::<(String, String)>(payload).unwrap();
+                        let req = Core2Blob::BeginPut(meta.owner.clone(), meta.app_name.clone(), path, content_type);
+                        let result: Result<u64, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Upload(id) => Ok(id),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a BeginPut with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_put_chunk" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (id, chunk) = deserialize::<(u64, Vec<u8>)>(payload).unwrap();
+                        let req = Core2Blob::PutChunk(meta.owner.clone(), meta.app_name.clone(), id, chunk);
+                        let result: Result<(), String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Done => Ok(()),
+                            Blob2Core::NotFound => Err(format!("Upload #{} does not exist.", id)),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a PutChunk with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_finish_put" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let id = deserialize::<u64>(payload).unwrap();
+                        let req = Core2Blob::FinishPut(meta.owner.clone(), meta.app_name.clone(), id);
+                        let result: Result<BlobMeta, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Meta(blob_meta) => Ok(blob_meta),
+                            Blob2Core::NotFound => Err(format!("Upload #{} does not exist.", id)),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a FinishPut with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_abort_put" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let id = deserialize::<u64>(payload).unwrap();
+                        let req = Core2Blob::AbortPut(meta.owner.clone(), meta.app_name.clone(), id);
+                        let result: Result<(), String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Done | Blob2Core::NotFound => Ok(()),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to an AbortPut with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_get_chunk" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (path, offset, len, etag) = deserialize::<(String, u64, u32, Option<String>)>(payload).unwrap();
+                        let req = Core2Blob::GetChunk(meta.owner.clone(), meta.app_name.clone(), path.clone(), offset, len, etag);
+                        let result: Result<Option<Vec<u8>>, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Chunk(chunk) => Ok(Some(chunk)),
+                            Blob2Core::NotFound => Ok(None),
+                            Blob2Core::Modified => Err(format!("{:?} was replaced while being read.", &path)),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a GetChunk with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_stat" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let path = deserialize::<String>(payload).unwrap();
+                        let req = Core2Blob::Stat(meta.owner.clone(), meta.app_name.clone(), path);
+                        let result: Result<Option<BlobMeta>, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Meta(blob_meta) => Ok(Some(blob_meta)),
+                            Blob2Core::NotFound => Ok(None),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a Stat with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_delete" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let path = deserialize::<String>(payload).unwrap();
+                        let req = Core2Blob::Delete(meta.owner.clone(), meta.app_name.clone(), path);
+                        let result: Result<bool, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Done => Ok(true),
+                            Blob2Core::NotFound => Ok(false),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a Delete with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "blob_list" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let (prefix, after, limit) = deserialize::<(String, Option<String>, u32)>(payload).unwrap();
+                        let req = Core2Blob::List(meta.owner.clone(), meta.app_name.clone(), prefix, after, limit);
+                        let result: Result<Vec<BlobMeta>, String> = match blob_call(&blob_req_tx, req) {
+                            Blob2Core::Metas(metas) => Ok(metas),
+                            Blob2Core::Error(e) => Err(e),
+                            other => Err(format!("The blob service replied to a List with {:?}.", other)),
+                        };
+                        Ok(serialize(&result).unwrap())
+                    },
+                    "http_body_blob" => {
+                        let mut meta = callback_meta.lock().unwrap();
+                        // Only the path is taken from the app. Everything else is looked up again once the app is done.
+                        let blob = deserialize::<BlobMeta>(payload)
+                        .map_err(|e| format!("The blob to respond with could not be deserialized: {}", e))?;
+                        meta.body_blob = Some(blob.path);
+                        Ok(Vec::new())
+                    },
+                    "kv_durability" => {
+                        let mut meta = callback_meta.lock().unwrap();
+                        meta.durability = deserialize::<Durability>(payload).unwrap();
+                        trace!("{}:{} set the durability of its K/V writes to {:?}.", &meta.owner, &meta.app_name, meta.durability);
+                        Ok(Vec::new())
+                    },
+                    "kv_watch" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let table = deserialize::<String>(payload).unwrap();
+                        trace!("{}:{} is now watching the table {}.", &meta.owner, &meta.app_name, &table);
+                        watches.lock().unwrap().watch(&meta.owner, &meta.app_name, &table);
+                        Ok(Vec::new())
+                    },
+                    "kv_unwatch" => {
+                        let meta = callback_meta.lock().unwrap();
+                        let table = deserialize::<String>(payload).unwrap();
+                        watches.lock().unwrap().unwatch(&meta.owner, &meta.app_name, &table);
                         Ok(Vec::new())
                     },
                     "datetime" => {
@@ -96,6 +344,7 @@ impl InnerExecutor {
                 msg_rx,
                 done_tx,
                 host,
+                blob_req_tx: body_blob_tx,
             };
 
             loop {
@@ -115,6 +364,7 @@ impl InnerExecutor {
         );
         meta.owner = owner;
         meta.app_name = app_name;
+        meta.durability = Durability::Default;
         match self.host.replace_module(&code) {
             Ok(_) => info!("Executor #{} successfully loaded the code for {}:{}.", meta.id, meta.owner, meta.app_name),
             Err(_e) => warn!("Executor #{} could not properly load the code for {}:{}!", meta.id, meta.owner, meta.app_name)
@@ -138,21 +388,38 @@ impl InnerExecutor {
             ExecutorMsg::LoadCode(o, a, c) => {
                 self.load_code(o, a, c);
             }
-            ExecutorMsg::Invoke(env) => {
+            ExecutorMsg::Invoke(request_id, env) => {
                 let meta = {
-                    let lock = self.metadata.lock().unwrap();
+                    let mut lock = self.metadata.lock().unwrap();
+                    lock.request_id = Some(request_id.clone());
+                    lock.durability = Durability::Default;
+                    lock.body_blob = None;
                     lock.clone()
                 };
-                trace!("Inner WASM executor #{} received an invocation.", meta.id);
+                debug!("[{}] Inner WASM executor #{} received an invocation for {}:{}.", &request_id, meta.id, meta.owner, meta.app_name);
                 let output = self.host.call("handle_http", &env.0)
                 .map_err(|e| {
+                    warn!("[{}] {}:{} failed to handle the request: {}", &request_id, meta.owner, meta.app_name, e);
                     InvocationError::WapcError(e.to_string())
                 }); // the Ok value should be a serialized HttpRes
+                let body_blob = {
+                    let mut lock = self.metadata.lock().unwrap();
+                    lock.request_id = None;
+                    lock.body_blob.take()
+                };
+                let output = match (output, body_blob) {
+                    (Ok(res_bytes), Some(path)) => read_body_blob(&self.blob_req_tx, &meta, res_bytes, &path)
+                    .map_err(|e| {
+                        warn!("[{}] {}:{} responded with a blob that could not be read: {}", &request_id, meta.owner, meta.app_name, e);
+                        InvocationError::OtherInternal
+                    }),
+                    (output, _) => output,
+                };
                 let bytes = serialize(&output).expect("Serialization error in InnerExecutor");
                 // Send output back.
                 match env.1.send(bytes) {
                     Ok(_) => {
-                        trace!("Inner WASM executor #{} responded!", meta.id);
+                        trace!("[{}] Inner WASM executor #{} responded!", &request_id, meta.id);
                     }
                     Err(_) => {
                         let text = format!(
@@ -178,6 +445,31 @@ impl InnerExecutor {
                     }
                 }
             },
+            ExecutorMsg::KvChange(change) => {
+                trace!("Inner WASM executor #{} received a K/V change.", meta.id);
+                {
+                    let mut lock = self.metadata.lock().unwrap();
+                    lock.handling_change = true;
+                    lock.durability = Durability::Default;
+                }
+                if let Err(e) = self.host.call("handle_kv_change", &change) {
+                    warn!("Executor #{} could not handle a K/V change for {}:{}: {}", meta.id, meta.owner, meta.app_name, e);
+                }
+                self.metadata.lock().unwrap().handling_change = false;
+                match self.done_tx.send(()) {
+                    Ok(_) => {
+                        trace!("Inner WASM executor #{} done!", meta.id);
+                    }
+                    Err(_) => {
+                        let text = format!(
+                            "Inner WASM executor #{} could not report a successful execution!",
+                            meta.id
+                        );
+                        error!("{}", &text);
+                        panic!("{}", &text);
+                    }
+                }
+            },
             ExecutorMsg::Rename(a) => {
                 let mut meta = self.metadata.lock().unwrap();
                 meta.app_name = a;
@@ -190,4 +482,86 @@ impl InnerExecutor {
         true
     }
     
-}
\ No newline at end of file
+}
+/// Sends a request to the K/V store and waits for its reply, failing if the store could not make sense of the request or never answered it.
+fn kv_call(kv_req_tx: &Sender<Envelope<TracedCore2Kv, Kv2Core>>, request_id: Option<String>, request: Core2Kv) -> Result<Kv2Core, Box<dyn std::error::Error + Sync + Send>> {
+    let (kv_rep_tx, kv_rep_rx) = unbounded();
+    kv_req_tx.send(((request_id, request), kv_rep_tx)).unwrap();
+    match kv_rep_rx.recv() {
+        Ok(Kv2Core::Malformed) => Err("The K/V store could not make sense of the request.".into()),
+        Ok(Kv2Core::InvalidName(name)) => Err(format!("{:?} cannot name a table or index, as it contains a colon.", name).into()),
+        Ok(reply) => Ok(reply),
+        Err(_) => Err("The K/V store's reply to the request was lost.".into()),
+    }
+}
+/// Sends a write to the K/V store like `kv_call`. If the app is handling a change, the changes the write makes are kept from being delivered back to it.
+fn kv_write(kv_req_tx: &Sender<Envelope<TracedCore2Kv, Kv2Core>>, watches: &Watches, meta: &Metadata, request: Core2Kv) -> Result<Kv2Core, Box<dyn std::error::Error + Sync + Send>> {
+    let echoes = match (meta.handling_change, &request) {
+        (true, Core2Kv::KvSet(_, table, key, value, _, _)) => vec![(table.clone(), key.clone(), Echo::Op(KvOp::Set(value.clone())))],
+        (true, Core2Kv::KvDel(_, table, key, _)) => vec![(table.clone(), key.clone(), Echo::Op(KvOp::Del))],
+        (true, Core2Kv::KvSetMany(_, table, pairs, _)) => pairs.iter()
+            .map(|(key, value)| (table.clone(), key.clone(), Echo::Op(KvOp::Set(value.clone()))))
+            .collect(),
+        // The new value is only known once the K/V store replies, which may be after it publishes the change.
+        (true, Core2Kv::KvIncrement(_, table, key, _)) | (true, Core2Kv::KvRaiseCounter(_, table, key, ..)) => vec![(table.clone(), key.clone(), Echo::AnySet)],
+        _ => Vec::new(),
+    };
+    if !echoes.is_empty() {
+        let mut watches = watches.lock().unwrap();
+        for (table, key, echo) in &echoes {
+            watches.expect_echo(&meta.owner, &meta.app_name, table, key, echo.clone());
+        }
+    }
+    let reply = kv_call(kv_req_tx, meta.request_id.clone(), request);
+    if !echoes.is_empty() && !matches!(reply, Ok(Kv2Core::OperationSuccessful) | Ok(Kv2Core::Counter(_))) {
+        let mut watches = watches.lock().unwrap();
+        for (table, key, echo) in &echoes {
+            watches.withdraw_echo(&meta.owner, &meta.app_name, table, key, echo);
+        }
+    }
+    reply
+}
+/// Turns the K/V store's reply to a write the app expects nothing back from into the result of the host call, failing if the write did not happen.
+fn kv_write_result(op: &str, reply: Kv2Core) -> Result<Vec<u8>, Box<dyn std::error::Error + Sync + Send>> {
+    match reply {
+        Kv2Core::OperationSuccessful => Ok(Vec::new()),
+        Kv2Core::ReadOnly => Err("The K/V store is a read-only replica.".into()),
+        other => Err(format!("A {} returned {:?} instead of confirming the write.", op, other).into()),
+    }
+}
+/// Fills in the body of a serialized `HttpRes` with a blob, read from the blob service chunk by chunk.
+/// Every chunk is pinned to the blob's etag, so a blob put again mid-read fails the response rather than mixing two versions.
+fn read_body_blob(blob_req_tx: &Sender<Envelope<Core2Blob, Blob2Core>>, meta: &Metadata, res_bytes: Vec<u8>, path: &str) -> Result<Vec<u8>, String> {
+    let mut res = deserialize::<HttpRes>(&res_bytes).map_err(|e| format!("The response could not be deserialized: {}", e))?;
+    let blob = match blob_call(blob_req_tx, Core2Blob::Stat(meta.owner.clone(), meta.app_name.clone(), path.to_owned())) {
+        Blob2Core::Meta(blob) => blob,
+        Blob2Core::NotFound => return Err(format!("{:?} does not exist.", path)),
+        Blob2Core::Error(e) => return Err(e),
+        other => return Err(format!("The blob service replied to a Stat with {:?}.", other)),
+    };
+    if blob.size > MAX_BODY_BLOB_SIZE {
+        return Err(format!("{:?} is {} bytes, past the limit of {} on blobs responded with.", path, blob.size, MAX_BODY_BLOB_SIZE));
+    }
+    let mut body = Vec::with_capacity(blob.size as usize);
+    while (body.len() as u64) < blob.size {
+        let req = Core2Blob::GetChunk(meta.owner.clone(), meta.app_name.clone(), blob.path.clone(), body.len() as u64, BLOB_CHUNK_SIZE as u32, Some(blob.etag.clone()));
+        match blob_call(blob_req_tx, req) {
+            Blob2Core::Chunk(chunk) if chunk.is_empty() => return Err(format!("{:?} ended after {} of {} bytes.", &blob.path, body.len(), blob.size)),
+            Blob2Core::Chunk(chunk) if body.len() + chunk.len() > blob.size as usize => return Err(format!("{:?} is longer than the {} bytes it was said to be.", &blob.path, blob.size)),
+            Blob2Core::Chunk(chunk) => body.extend_from_slice(&chunk),
+            Blob2Core::NotFound => return Err(format!("{:?} was deleted while being read.", &blob.path)),
+            Blob2Core::Modified => return Err(format!("{:?} was replaced while being read.", &blob.path)),
+            Blob2Core::Error(e) => return Err(e),
+            other => return Err(format!("The blob service replied to a GetChunk with {:?}.", other)),
+        }
+    }
+    res.headers.insert("Content-Type".to_owned(), blob.content_type);
+    res.body = body;
+    Ok(serialize(&res).unwrap())
+}
+/// Sends a request to the blob service and waits for its reply.
+fn blob_call(blob_req_tx: &Sender<Envelope<Core2Blob, Blob2Core>>, request: Core2Blob) -> Blob2Core {
+    let (blob_rep_tx, blob_rep_rx) = unbounded();
+    blob_req_tx.send((request, blob_rep_tx)).unwrap();
+    blob_rep_rx.recv().unwrap_or_else(|_| Blob2Core::Error("The blob service's reply to the request was lost.".to_owned()))
+}
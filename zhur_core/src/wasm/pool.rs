@@ -1,25 +1,74 @@
 use std::thread::JoinHandle;
 
-use zhur_common::{bincode::{deserialize, serialize}, flume::{Receiver, Sender}, msg::{chan::Envelope, core_apst::{Core2ApstRep, Core2ApstReq}, core_kv::{Core2Kv, Kv2Core}}, zmq::Socket};
+use zhur_common::{bincode::{deserialize, serialize}, flume::{Receiver, Selector, Sender}, msg::{chan::Envelope, core_apst::{Core2ApstRep, Core2ApstReq}, core_kv::{Kv2Core, TracedCore2Kv}, core_sql::{Core2Sql, Sql2Core}, core_blob::{Blob2Core, Core2Blob}}, zmq::Socket};
 use zhur_common::log::*;
-use zhur_invk::{Invocation, InvocationError};
+use zhur_invk::InvocationError;
 
-use crate::wasm::InvocEnv;
+use crate::serve::Watches;
+use crate::wasm::{InvocEnv, KvChangeDelivery};
 
 use super::executor::Executor;
 /// The "WASM executor pool" keeps track of WASM executors and distributes invocations among them.
 pub struct WasmPool {
     /// This receiver handles incoming invocations to be passed out to executors.
     invoc_env_rx: Receiver<InvocEnv>,
+    /// This receiver handles K/V changes to be delivered to the apps watching them.
+    kv_change_rx: Receiver<KvChangeDelivery>,
     /// This is where invocations that can't be handled right away get put.
-    outstanding_invocations: Vec<InvocEnv>,
+    outstanding_invocations: Vec<Job>,
     /// How many executors can be running at one time.
     max_executors: usize,
     /// The actual executors.
     executors: Vec<Executor>,
     /// The ZMQ socket used for requesting apps.
     apst_req_socket: Socket,
-    kv_req_tx: Sender<Envelope<Core2Kv, Kv2Core>>
+    kv_req_tx: Sender<Envelope<TracedCore2Kv, Kv2Core>>,
+    sql_req_tx: Sender<Envelope<Core2Sql, Sql2Core>>,
+    blob_req_tx: Sender<Envelope<Core2Blob, Blob2Core>>,
+    /// The K/V tables watched by apps, shared with every executor.
+    watches: Watches,
+}
+/// Everything the `WasmPool` can hand out to an executor.
+enum Job {
+    /// An HTTP invocation from the gateway, answered with a serialized result.
+    Http(InvocEnv),
+    /// A K/V change for an app watching its table. Nothing is sent back.
+    KvChange(KvChangeDelivery),
+}
+impl Job {
+    fn owner(&self) -> &str {
+        match self {
+            Job::Http(env) => &env.0.owner,
+            Job::KvChange((owner, _, _)) => owner,
+        }
+    }
+    fn app_name(&self) -> &str {
+        match self {
+            Job::Http(env) => &env.0.app_name,
+            Job::KvChange((_, app_name, _)) => app_name,
+        }
+    }
+    /// Reports an error instead of running the job. HTTP invocations get it sent back; K/V changes are dropped.
+    fn fail(self, e: InvocationError) {
+        match self {
+            Job::Http(env) => {
+                let e: Result<Vec<u8>, InvocationError> = Err(e);
+                let e_bytes = serialize(&e).unwrap();
+                warn!("[{}] Could not load code, sending back error.", &env.0.request_id);
+                env.1.send(e_bytes).unwrap();
+            },
+            Job::KvChange((owner, app_name, _)) => {
+                warn!("Could not load code, dropping a K/V change meant for {}:{}.", owner, app_name);
+            }
+        }
+    }
+    /// Passes the job on to an executor already holding the right code.
+    fn run_on(self, executor: &mut Executor) {
+        match self {
+            Job::Http(env) => executor.invoke(env.0.request_id, (env.0.payload, env.1)),
+            Job::KvChange((_, _, change)) => executor.deliver_kv_change(change),
+        }
+    }
 }
 /// These are the possible decisions the `WasmPool` can make when receiving an invocation.
 enum RunDecision {
@@ -56,11 +105,11 @@ impl WasmPool {
             }
         }
     }
-    /// Decides what to do with an incoming `Invocation`.
-    fn decide(&self, i: &Invocation) -> RunDecision {
+    /// Decides what to do with an incoming job for the given app.
+    fn decide(&self, owner: &str, app_name: &str) -> RunDecision {
         // Do we have a free executor with the necessary app?
         for (index, each) in self.executors.iter().enumerate() {
-            if each.owner == i.owner && each.app_name == i.app_name && each.free {
+            if each.owner == owner && each.app_name == app_name && each.free {
                 return RunDecision::Forward(index);
             }
         }
@@ -77,77 +126,74 @@ impl WasmPool {
         // If there are no free executors and we can't spawn more, we can only wait.
         RunDecision::PutAway
     }
-    /// Handles an incoming `Invocation`.
-    fn handle(&mut self, env: InvocEnv) {
+    /// Handles an incoming `Job`.
+    fn handle(&mut self, job: Job) {
         // Check if any executors have become free.
         for each in self.executors.iter_mut() {
             each.check_status();
         }
-        match self.decide(&env.0) {
+        match self.decide(job.owner(), job.app_name()) {
             RunDecision::PutAway => {
                 trace!("WasmPool could not handle invocation right away, putting away.");
-                self.outstanding_invocations.push(env);
+                self.outstanding_invocations.push(job);
             }
             RunDecision::Forward(i) => {
                 trace!("WasmPool found a free executor at #{}, invoking.", i);
-                self.executors[i].invoke((env.0.payload, env.1));
+                job.run_on(&mut self.executors[i]);
             }
             RunDecision::SpawnNew => {
                 trace!(
                     "WasmPool decided to spawn a new executor #{} for the current invocation.",
                     self.executors.len()
                 );
-                warn!("Not actually doing anything, zhur_apst hasn't been implemented yet.");
-                let code = match self.get_code(&env.0.owner, &env.0.app_name) {
+                let code = match self.get_code(job.owner(), job.app_name()) {
                     Ok(code) => code,
                     Err(e) => {
-                        let e: Result<Vec<u8>, InvocationError> = Err(e);
-                        let e_bytes = serialize(&e).unwrap();
-                        warn!("Could not load code, sending back error.");
-                        env.1.send(e_bytes).unwrap(); // Send back error result and return early.
+                        job.fail(e); // Send back error result and return early.
                         return;
                     }
                 };
                 self.executors.push(Executor::new(
                     self.executors.len(),
-                    env.0.owner.clone(),
-                    env.0.app_name.clone(),
+                    job.owner().to_owned(),
+                    job.app_name().to_owned(),
                     code,
-                    self.kv_req_tx.clone()
+                    self.kv_req_tx.clone(),
+                    self.sql_req_tx.clone(),
+                    self.blob_req_tx.clone(),
+                    self.watches.clone()
                 ));
-                self.executors
-                    .last_mut()
-                    .unwrap()
-                    .invoke((env.0.payload, env.1));
+                job.run_on(self.executors.last_mut().unwrap());
             }
             RunDecision::Replace(i) => {
                 trace!("WasmPool decided to replace the code in executor #{} before using it to handle an invocation.", i);
-                warn!("Not actually doing anything, zhur_apst hasn't been implemented yet.");
-                let code = match self.get_code(&env.0.owner, &env.0.app_name) {
+                let code = match self.get_code(job.owner(), job.app_name()) {
                     Ok(code) => code,
                     Err(e) => {
-                        let e: Result<Vec<u8>, InvocationError> = Err(e);
-                        let e_bytes = serialize(&e).unwrap();
-                        warn!("Could not load code, sending back error.");
-                        env.1.send(e_bytes).unwrap(); // Send back error result and return early.
+                        job.fail(e); // Send back error result and return early.
                         return;
                     }
                 };
-                self.executors[i].load_code(env.0.owner.clone(), env.0.app_name.clone(), code);
-                self.executors[i].owner = env.0.owner;
-                self.executors[i].app_name = env.0.app_name;
-                self.executors[i].invoke((env.0.payload, env.1));
+                let (owner, app_name) = (job.owner().to_owned(), job.app_name().to_owned());
+                self.executors[i].load_code(owner.clone(), app_name.clone(), code);
+                self.executors[i].owner = owner;
+                self.executors[i].app_name = app_name;
+                job.run_on(&mut self.executors[i]);
             }
         }
     }
-    pub fn new(max_executors: usize, invoc_env_rx: Receiver<InvocEnv>, apst_req_socket: Socket, kv_req_tx: Sender<Envelope<Core2Kv, Kv2Core>>) -> Self {
+    pub fn new(max_executors: usize, invoc_env_rx: Receiver<InvocEnv>, kv_change_rx: Receiver<KvChangeDelivery>, apst_req_socket: Socket, kv_req_tx: Sender<Envelope<TracedCore2Kv, Kv2Core>>, sql_req_tx: Sender<Envelope<Core2Sql, Sql2Core>>, blob_req_tx: Sender<Envelope<Core2Blob, Blob2Core>>, watches: Watches) -> Self {
         Self {
             max_executors,
             invoc_env_rx,
+            kv_change_rx,
             outstanding_invocations: Vec::new(),
             executors: Vec::new(),
             apst_req_socket,
-            kv_req_tx
+            kv_req_tx,
+            sql_req_tx,
+            blob_req_tx,
+            watches
         }
     }
     /// Runs the `WasmPool` in a background thread.
@@ -157,8 +203,12 @@ impl WasmPool {
             .spawn(move || {
                 let mut pool = self;
                 loop {
-                    let env = match pool.invoc_env_rx.recv() {
-                        Ok(env) => env,
+                    let job = Selector::new()
+                        .recv(&pool.invoc_env_rx, |r| r.map(Job::Http))
+                        .recv(&pool.kv_change_rx, |r| r.map(Job::KvChange))
+                        .wait();
+                    let job = match job {
+                        Ok(job) => job,
                         Err(_) => {
                             let text = "WasmPool could not receive incoming invocation envelope!";
                             error!("{}", text);
@@ -168,7 +218,7 @@ impl WasmPool {
                             panic!("{}", text);
                         }
                     };
-                    pool.handle(env);
+                    pool.handle(job);
                 }
             })
             .expect("Could not launch WasmPool thread!")
@@ -0,0 +1,283 @@
+use chrono::{DateTime, Utc};
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use zhur_common::flume::{self, Receiver, Sender, TrySendError};
+use zhur_common::log::*;
+use crate::handle::AppId;
+
+/// How large the access log file gets before being rotated if `ZHUR_GATE_ACCESS_LOG_MAX_SIZE` is not set, in bytes.
+const DEFAULT_MAX_SIZE: u64 = 100 * 1024 * 1024;
+/// How many rotated access log files are kept if `ZHUR_GATE_ACCESS_LOG_KEEP` is not set.
+const DEFAULT_KEEP: u32 = 5;
+/// How many entries can wait to be written. Past this, new entries are dropped rather than piling up in memory.
+const MAX_PENDING_ENTRIES: usize = 10_000;
+
+/// One line of the access log.
+#[derive(Debug, Serialize)]
+pub struct AccessEntry {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    pub client_ip: String,
+    pub host: Option<String>,
+    pub owner: Option<String>,
+    pub app: Option<String>,
+    pub method: String,
+    /// The path along with the query string, as requested.
+    pub path: String,
+    pub protocol: String,
+    pub status: u16,
+    /// The size of the response body in bytes, if known up front.
+    pub size: Option<u64>,
+    /// How long the gateway took to answer, in milliseconds.
+    pub latency_ms: f64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// How entries are written out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// One JSON object per line, with every field.
+    Json,
+    /// The Common Log Format.
+    Common,
+    /// The Combined Log Format, which adds the referer and user agent to the common one.
+    Combined,
+}
+
+impl AccessEntry {
+    /// Notes down what is known about a request before it is handled.
+    pub fn start(req: &Request<Body>, client_ip: String, request_id: String) -> Self {
+        let header = |name: hyper::header::HeaderName| req.headers().get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        Self {
+            timestamp: Utc::now(),
+            request_id,
+            client_ip,
+            host: header(hyper::header::HOST),
+            owner: None,
+            app: None,
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map(|p| p.as_str().to_owned()).unwrap_or_else(|| "/".to_owned()),
+            protocol: format!("{:?}", req.version()),
+            status: 0,
+            size: None,
+            latency_ms: 0.0,
+            referer: header(hyper::header::REFERER),
+            user_agent: header(hyper::header::USER_AGENT),
+        }
+    }
+    /// Fills in the outcome of a request that started being handled at `started`.
+    pub fn finish(mut self, res: &Response<Body>, started: Instant) -> Self {
+        if let Some(AppId(owner, app_name)) = res.extensions().get::<AppId>() {
+            self.owner = Some(owner.clone());
+            self.app = Some(app_name.clone());
+        }
+        self.status = res.status().as_u16();
+        self.size = res.body().size_hint().exact();
+        self.latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        self
+    }
+    fn format(&self, format: Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string(self).unwrap(),
+            Format::Common | Format::Combined => {
+                let mut line = format!(
+                    "{} - - [{}] \"{} {} {}\" {} {}",
+                    &self.client_ip,
+                    self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+                    &self.method,
+                    &self.path,
+                    &self.protocol,
+                    self.status,
+                    self.size.map(|s| s.to_string()).unwrap_or_else(|| "-".to_owned()),
+                );
+                if format == Format::Combined {
+                    let quoted = |v: &Option<String>| match v {
+                        Some(v) => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+                        None => "\"-\"".to_owned(),
+                    };
+                    line.push_str(&format!(" {} {}", quoted(&self.referer), quoted(&self.user_agent)));
+                }
+                line
+            }
+        }
+    }
+}
+
+/// Where entries go.
+enum Output {
+    Stdout,
+    /// A file, rotated by renaming it to `<path>.1`, the old `<path>.1` to `<path>.2` and so on, once it reaches the size limit.
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+        max_size: u64,
+        keep: u32,
+    },
+}
+
+impl Output {
+    fn open(path: &PathBuf) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Self::Stdout => {
+                let stdout = io::stdout();
+                let mut lock = stdout.lock();
+                writeln!(lock, "{}", line)
+            },
+            Self::File { path, file, size, max_size, keep } => {
+                if *size > 0 && *size + line.len() as u64 + 1 > *max_size {
+                    rotate(path, *keep)?;
+                    let (new_file, new_size) = Self::open(path)?;
+                    *file = new_file;
+                    *size = new_size;
+                }
+                writeln!(file, "{}", line)?;
+                *size += line.len() as u64 + 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shifts the rotated files along, dropping the oldest, and moves the current file to `<path>.1`.
+fn rotate(path: &PathBuf, keep: u32) -> io::Result<()> {
+    let numbered = |n: u32| {
+        let mut p = path.clone().into_os_string();
+        p.push(format!(".{}", n));
+        PathBuf::from(p)
+    };
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    let _ = std::fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let _ = std::fs::rename(numbered(n), numbered(n + 1));
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+/// Writes access log entries on a thread of its own, so requests never wait for the disk.
+/// If the disk cannot keep up, entries are dropped and counted, and the count is logged once writing catches up.
+#[derive(Clone)]
+pub struct AccessLog {
+    tx: Sender<AccessEntry>,
+    /// Entries dropped since the writer last reported it.
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// Starts the access log if `ZHUR_GATE_ACCESS_LOG` is set, to `stdout` or a file path.
+    pub fn from_env() -> Option<Self> {
+        let target = match std::env::var("ZHUR_GATE_ACCESS_LOG") {
+            Ok(t) => t,
+            Err(_) => {
+                info!("ZHUR_GATE_ACCESS_LOG not set. Not keeping an access log.");
+                return None;
+            }
+        };
+        let format = match std::env::var("ZHUR_GATE_ACCESS_LOG_FORMAT") {
+            Ok(f) => match f.as_str() {
+                "json" => Format::Json,
+                "common" => Format::Common,
+                "combined" => Format::Combined,
+                other => {
+                    warn!("ZHUR_GATE_ACCESS_LOG_FORMAT set to invalid value \"{}\". Assuming \"json\".", other);
+                    Format::Json
+                }
+            },
+            Err(_) => Format::Json,
+        };
+        let output = match target.as_str() {
+            "stdout" | "-" => Output::Stdout,
+            path => {
+                let max_size = match std::env::var("ZHUR_GATE_ACCESS_LOG_MAX_SIZE") {
+                    Ok(v) => match v.parse::<u64>() {
+                        Ok(n) if n > 0 => n,
+                        _ => {
+                            warn!("ZHUR_GATE_ACCESS_LOG_MAX_SIZE set to invalid value \"{}\". Assuming {} bytes.", &v, DEFAULT_MAX_SIZE);
+                            DEFAULT_MAX_SIZE
+                        }
+                    },
+                    Err(_) => DEFAULT_MAX_SIZE,
+                };
+                let keep = match std::env::var("ZHUR_GATE_ACCESS_LOG_KEEP") {
+                    Ok(v) => match v.parse::<u32>() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            warn!("ZHUR_GATE_ACCESS_LOG_KEEP set to invalid value \"{}\". Assuming {}.", &v, DEFAULT_KEEP);
+                            DEFAULT_KEEP
+                        }
+                    },
+                    Err(_) => DEFAULT_KEEP,
+                };
+                let path = PathBuf::from(path);
+                let (file, size) = match Output::open(&path) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("Could not open the access log {:?}: {}. Not keeping an access log.", &path, e);
+                        return None;
+                    }
+                };
+                Output::File { path, file, size, max_size, keep }
+            }
+        };
+        let (tx, rx) = flume::bounded(MAX_PENDING_ENTRIES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = dropped.clone();
+        std::thread::Builder::new()
+            .name("gate_access_log".to_owned())
+            .spawn(move || write_entries(rx, output, format, writer_dropped))
+            .expect("Could not launch the access log thread!");
+        Some(Self { tx, dropped })
+    }
+    pub fn log(&self, entry: AccessEntry) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(entry) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn write_entries(rx: Receiver<AccessEntry>, mut output: Output, format: Format, dropped: Arc<AtomicU64>) {
+    for entry in rx.iter() {
+        if let Err(e) = output.write_line(&entry.format(format)) {
+            error!("Could not write to the access log: {}", e);
+        }
+        let count = dropped.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            warn!("Dropped {} access log entries, as the access log could not keep up.", count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_past_the_limit_are_dropped_and_counted() {
+        let (tx, rx) = flume::bounded(2);
+        let log = AccessLog { tx, dropped: Arc::new(AtomicU64::new(0)) };
+        let req = Request::builder().uri("/todos").body(Body::empty()).unwrap();
+        for n in 0..5 {
+            log.log(AccessEntry::start(&req, "192.0.2.1".to_owned(), n.to_string()));
+        }
+        assert_eq!(rx.len(), 2);
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(rx.recv().unwrap().request_id, "0");
+    }
+}
@@ -1,5 +1,7 @@
 use bincode::deserialize;
-use zhur_common::{bincode, log::*, msg::chan::*, zmq};
+use std::sync::{Arc, Mutex};
+use zhur_common::{bincode, log::*, msg::chan::*, sockets::timed_req_socket, zmq};
+use zhur_common::msg::gate_apst::{Apst2Gate, Gate2Apst, DEFAULT_GATE_APST_ENDPOINT};
 use zhur_invk::{HttpRes, Invocation, InvocationError};
 use zmq::{Context, Socket, SocketType};
 /// Struct responsible for relaying requests from the gateway to the core.
@@ -66,3 +68,52 @@ impl HandleRequest<Invocation, Result<HttpRes, InvocationError>> for Gate2CoreSe
         }
     }
 }
+
+/// A client for the app store, shared by everything in the gateway that needs one.
+/// `ChannelClient`s need to be mutable to make requests, and clones of one would share its replies, hence the `Mutex`.
+pub type SharedApstClient = Arc<Mutex<ChannelClient<Gate2Apst, Apst2Gate>>>;
+
+/// How long to wait for the app store to answer, in milliseconds.
+const APST_TIMEOUT: i32 = 2000;
+
+/// Struct responsible for relaying requests from the gateway to the app store.
+pub struct Gate2ApstServer {
+    /// ZMQ REQ socket.
+    req_socket: Socket,
+}
+impl Gate2ApstServer {
+    pub fn new(zmq_ctx: &Context) -> Self {
+        let sck = timed_req_socket(zmq_ctx, APST_TIMEOUT);
+        let endpoint = match std::env::var("ZHUR_APST_GATE_ENDPOINT") {
+            Ok(s) => s,
+            Err(_) => {
+                warn!("ZHUR_APST_GATE_ENDPOINT not set - assuming default value of {}!", DEFAULT_GATE_APST_ENDPOINT);
+                DEFAULT_GATE_APST_ENDPOINT.to_owned()
+            }
+        };
+        sck.connect(&endpoint).expect(
+            "Expected to be able to connect a REQ socket from the gateway to the app store.",
+        );
+        Self { req_socket: sck }
+    }
+}
+
+impl HandleRequest<Gate2Apst, Apst2Gate> for Gate2ApstServer {
+    fn handle(&mut self, msg: Gate2Apst) -> Apst2Gate {
+        trace!("Sending a request to the app store...");
+        if self.req_socket.send(bincode::serialize(&msg).unwrap(), 0).is_err() {
+            return Apst2Gate::Error("Could not send a request to the app store.".to_owned());
+        }
+        let response_bytes = match self.req_socket.recv_bytes(0) {
+            Ok(b) => b,
+            Err(_) => return Apst2Gate::Error("The app store did not answer in time.".to_owned()),
+        };
+        match deserialize::<Apst2Gate>(&response_bytes) {
+            Ok(r) => r,
+            Err(_) => {
+                warn!("Bytes received back from the app store were not a proper Apst2Gate");
+                Apst2Gate::Error("Malformed reply from the app store.".to_owned())
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{normalize_domain, Apst2Gate, Gate2Apst};
+use zhur_invk::InvocationError;
+use crate::comms::SharedApstClient;
+
+/// How long custom domain mappings are cached for if `ZHUR_GATE_DOMAIN_TTL` is not set, in seconds.
+const DEFAULT_DOMAIN_TTL: u64 = 60;
+/// How long a domain that maps to no app is remembered as such, at most, in seconds.
+/// This is short so that newly added domains start working soon.
+const DOMAIN_MISS_TTL: u64 = 5;
+/// The most domain lookups kept at once. Past this, expired ones are dropped, and then the ones expiring soonest.
+const MAX_CACHED_DOMAINS: usize = 10_000;
+
+type Cache = HashMap<String, (Instant, Option<(String, String)>)>;
+
+/// Works out which app a request is for from its Host header.
+/// Hosts under `ZHUR_GATE_BASE_DOMAIN` name the app directly, as in `app.owner.<base domain>`.
+/// Any other host is looked up among the custom domains in the app store.
+/// If no base domain is set, hosts that are not custom domains have their first two labels read as `app.owner`.
+pub struct HostResolver {
+    /// The domain apps get subdomains of, lowercase and without a leading dot.
+    base_domain: Option<String>,
+    client: SharedApstClient,
+    /// Custom domain lookups by normalized domain, along with when they expire. Misses are cached only briefly.
+    cache: Mutex<Cache>,
+    ttl: Duration,
+    miss_ttl: Duration,
+}
+
+impl HostResolver {
+    pub fn new(client: SharedApstClient) -> Self {
+        let base_domain = match std::env::var("ZHUR_GATE_BASE_DOMAIN") {
+            Ok(d) => Some(normalize_domain(d.trim_start_matches('.'))),
+            Err(_) => {
+                warn!("ZHUR_GATE_BASE_DOMAIN not set. App IDs will be read from the first two labels of hosts that are not custom domains.");
+                None
+            }
+        };
+        let ttl = match std::env::var("ZHUR_GATE_DOMAIN_TTL") {
+            Ok(v) => match v.parse::<u64>() {
+                Ok(n) => n,
+                Err(_) => {
+                    warn!("ZHUR_GATE_DOMAIN_TTL set to invalid value \"{}\". Assuming {} seconds.", &v, DEFAULT_DOMAIN_TTL);
+                    DEFAULT_DOMAIN_TTL
+                }
+            },
+            Err(_) => DEFAULT_DOMAIN_TTL,
+        };
+        Self {
+            base_domain,
+            client,
+            cache: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl),
+            miss_ttl: Duration::from_secs(ttl.min(DOMAIN_MISS_TTL)),
+        }
+    }
+    /// Gets the owner and name of the app a host points to.
+    pub async fn resolve(self: &Arc<Self>, host: &str) -> Result<(String, String), InvocationError> {
+        let domain = normalize_domain(host);
+        if domain.is_empty() {
+            warn!("Received an HTTP request with an empty Host header, returning a no ID error.");
+            return Err(InvocationError::NoId);
+        }
+        if let Some(base) = &self.base_domain {
+            if let Some(sub) = domain.strip_suffix(base.as_str()).and_then(|s| s.strip_suffix('.')) {
+                return match sub.split('.').collect::<Vec<_>>().as_slice() {
+                    [app_name, owner] => Ok((owner.to_string(), app_name.to_string())),
+                    _ => {
+                        warn!("Received an HTTP request for \"{}\", which is not of the form app.owner.{}", host, base);
+                        Err(InvocationError::MalformedId(host.into()))
+                    }
+                };
+            }
+        }
+        if let Some(app) = self.lookup(&domain).await {
+            return Ok(app);
+        }
+        let segments = domain.split('.').collect::<Vec<_>>();
+        if self.base_domain.is_some() || segments.len() < 2 {
+            warn!("Received an HTTP request with a Host header that could not be transformed into an app ID: \"{}\"", host);
+            return Err(InvocationError::MalformedId(host.into()));
+        }
+        Ok((segments[1].to_owned(), segments[0].to_owned()))
+    }
+    /// Finds the app a custom domain points to, asking the app store if the answer is not cached.
+    /// The app store is asked from a blocking thread, so that waiting for it does not hold up other requests.
+    async fn lookup(self: &Arc<Self>, domain: &str) -> Option<(String, String)> {
+        if let Some((expires, app)) = self.cache.lock().unwrap().get(domain) {
+            if Instant::now() < *expires {
+                return app.clone();
+            }
+        }
+        let resolver = self.clone();
+        let domain = domain.to_owned();
+        match tokio::task::spawn_blocking(move || resolver.fetch(domain)).await {
+            Ok(app) => app,
+            Err(e) => {
+                error!("Could not look up a custom domain: {}", e);
+                None
+            }
+        }
+    }
+    /// Asks the app store which app a custom domain points to and caches the answer.
+    /// If the app store cannot answer, the domain is treated as unmapped for the time being.
+    fn fetch(&self, domain: String) -> Option<(String, String)> {
+        let reply = self.client.lock().unwrap().request(Gate2Apst::ResolveDomain(domain.to_owned()));
+        let app = match reply {
+            Apst2Gate::Domain(app) => app,
+            Apst2Gate::Error(e) => {
+                warn!("Could not resolve the domain {}: {}", domain, e);
+                None
+            },
+            other => {
+                warn!("Got an unexpected reply to a domain lookup: {:?}", other);
+                None
+            }
+        };
+        let ttl = match app {
+            Some(_) => self.ttl,
+            None => self.miss_ttl,
+        };
+        insert_bounded(&mut self.cache.lock().unwrap(), domain, app.clone(), Instant::now() + ttl, MAX_CACHED_DOMAINS);
+        app
+    }
+}
+
+/// Caches a domain lookup until `expires`, making room for it first if the cache holds `max` already.
+fn insert_bounded(cache: &mut Cache, domain: String, app: Option<(String, String)>, expires: Instant, max: usize) {
+    if cache.len() >= max && !cache.contains_key(&domain) {
+        let now = Instant::now();
+        cache.retain(|_, (expires, _)| now < *expires);
+        while cache.len() >= max {
+            let soonest = cache.iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(d, _)| d.clone());
+            match soonest {
+                Some(d) => cache.remove(&d),
+                None => break,
+            };
+        }
+    }
+    cache.insert(domain, (expires, app));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use zhur_common::msg::chan::{client_server, HandleRequest};
+
+    /// Stands in for the app store, knowing one custom domain and counting the lookups it gets.
+    struct FakeApst(Arc<AtomicUsize>);
+
+    impl HandleRequest<Gate2Apst, Apst2Gate> for FakeApst {
+        fn handle(&mut self, msg: Gate2Apst) -> Apst2Gate {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match msg {
+                Gate2Apst::ResolveDomain(d) if d == "shop.example.com" => Apst2Gate::Domain(Some(("alice".into(), "shop".into()))),
+                Gate2Apst::ResolveDomain(_) => Apst2Gate::Domain(None),
+                Gate2Apst::Manifest(..) => Apst2Gate::Error("Not a domain lookup.".into()),
+            }
+        }
+    }
+
+    fn resolver(base_domain: Option<&str>, miss_ttl: u64) -> (Arc<HostResolver>, Arc<AtomicUsize>) {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let (client, mut server) = client_server(FakeApst(lookups.clone()));
+        std::thread::spawn(move || loop {
+            server.handle();
+        });
+        let resolver = HostResolver {
+            base_domain: base_domain.map(String::from),
+            client: Arc::new(Mutex::new(client)),
+            cache: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(60),
+            miss_ttl: Duration::from_secs(miss_ttl),
+        };
+        (Arc::new(resolver), lookups)
+    }
+
+    #[tokio::test]
+    async fn hosts_are_normalized_before_resolving() {
+        let (resolver, _) = resolver(Some("zhur.app"), 5);
+        let expected = ("bob".to_owned(), "blog".to_owned());
+        for host in &["blog.bob.zhur.app", "Blog.BOB.Zhur.App", "blog.bob.zhur.app:8080", "blog.bob.zhur.app."] {
+            assert_eq!(resolver.resolve(host).await.unwrap(), expected, "{}", host);
+        }
+        for host in &["SHOP.example.com", "shop.example.com.:443"] {
+            assert_eq!(resolver.resolve(host).await.unwrap(), ("alice".to_owned(), "shop".to_owned()), "{}", host);
+        }
+        assert!(resolver.resolve("bob.zhur.app").await.is_err());
+        assert!(resolver.resolve(":8080").await.is_err());
+    }
+    #[tokio::test]
+    async fn hosts_without_a_base_domain_are_read_as_app_and_owner() {
+        let (resolver, _) = resolver(None, 5);
+        assert_eq!(resolver.resolve("Blog.Bob:8080").await.unwrap(), ("bob".to_owned(), "blog".to_owned()));
+        assert!(resolver.resolve("localhost").await.is_err());
+    }
+    #[tokio::test]
+    async fn hits_are_cached_and_misses_are_not_kept() {
+        let (resolver, lookups) = resolver(Some("zhur.app"), 0);
+        resolver.resolve("shop.example.com").await.unwrap();
+        resolver.resolve("shop.example.com").await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert!(resolver.resolve("other.example.com").await.is_err());
+        assert!(resolver.resolve("other.example.com").await.is_err());
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+    }
+    #[test]
+    fn the_cache_stays_within_its_bound() {
+        let mut cache = Cache::new();
+        let now = Instant::now();
+        insert_bounded(&mut cache, "a.com".into(), None, now + Duration::from_secs(1), 2);
+        insert_bounded(&mut cache, "b.com".into(), None, now + Duration::from_secs(3), 2);
+        insert_bounded(&mut cache, "c.com".into(), None, now + Duration::from_secs(2), 2);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key("a.com"));
+    }
+}
@@ -1,26 +1,82 @@
 use conversions::realize_response;
 use hyper::{Body, Request, Response};
 use std::convert::Infallible;
+use std::sync::Arc;
 use zhur_common::{log::*, msg::chan::ChannelClient};
 use zhur_invk::*;
+use crate::domains::HostResolver;
+use crate::manifests::ManifestCache;
+use crate::ratelimit::RateLimiter;
+use crate::routing::Routing;
 mod conversions;
+/// Limits on the size of requests.
+mod limits;
+pub use limits::Limits;
+/// Pages shown when an invocation fails.
+mod error_pages;
+pub use error_pages::ErrorPages;
 /// The info we need to produce a Zhur invocation from an HTTP request.
 pub struct FullRequest {
     /// The request itself.
     pub req: Request<Body>,
     /// The IP address the request is coming from, represented as a string.
     pub ip: String,
+    /// The ID of the request, passed on to the core and echoed back to the client.
+    pub request_id: String,
 }
 
+/// The app a response came from, or was meant to, attached to responses as an extension for the access log.
+#[derive(Clone, Debug)]
+pub struct AppId(pub String, pub String);
+
 /// Transforms an HTTP request into an HTTP response.
 pub async fn handle_req(
     req: FullRequest,
     mut client: ChannelClient<Invocation, Result<HttpRes, InvocationError>>,
+    error_pages: Arc<ErrorPages>,
+    manifests: Arc<ManifestCache>,
+    limits: Arc<Limits>,
+    rate_limiter: Arc<RateLimiter>,
+    routing: Routing,
+    resolver: Arc<HostResolver>,
 ) -> Result<Response<Body>, Infallible> {
-    let invocation = match req.into_invoc().await {
+    use hyper::body::HttpBody;
+    let request_id = req.request_id.clone();
+    let accept = req.req.headers()
+        .get(hyper::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let client_ip = rate_limiter.client_ip(&req.req, &req.ip);
+    let checked = match rate_limiter.check_client(client_ip).and_then(|_| limits.check_head(&req.req)) {
+        Ok(_) => req.target(routing, &resolver).await,
+        Err(e) => Err(e),
+    };
+    let target = match checked {
+        Ok(t) => t,
+        Err(e) => {
+            warn!("[{}] Got an invocation error: {}", &request_id, e);
+            return Ok(error_pages.render(&e, accept.as_deref(), None));
+        }
+    };
+    let (owner, app_name) = (target.0.clone(), target.1.clone());
+    let tag = |mut res: Response<Body>| {
+        res.extensions_mut().insert(AppId(owner.clone(), app_name.clone()));
+        Ok(res)
+    };
+    let manifest = manifests.get(&owner, &app_name).await;
+    if let Err(e) = rate_limiter.check_app(client_ip, &owner, &app_name, &manifest) {
+        warn!("[{}] Got an invocation error: {}", &request_id, e);
+        return tag(error_pages.render(&e, accept.as_deref(), Some(&*manifest)));
+    }
+    let body_limit = match req.req.body().is_end_stream() {
+        true => 0,
+        false => limits.body_limit(&manifest),
+    };
+    let invocation = match req.into_invoc(target, body_limit).await {
         Ok(i) => {
             let text = format!(
-                "Got an OK invocation for {}:{} of length {}",
+                "[{}] Got an OK invocation for {}:{} of length {}",
+                &request_id,
                 &i.owner,
                 &i.app_name,
                 &i.payload.len()
@@ -29,24 +85,27 @@ pub async fn handle_req(
             i
         }
         Err(e) => {
-            let text = format!("Got an invocation error: {}", e);
-            warn!("{}", &text);
-            return Ok(Response::new(text.into())); // TODO: Error pages
+            warn!("[{}] Got an invocation error: {}", &request_id, e);
+            return tag(error_pages.render(&e, accept.as_deref(), None));
         }
     };
     let reply = client.request(invocation);
+    if let Ok(_) | Err(InvocationError::WapcError(_)) = &reply {
+        rate_limiter.app_resolved(&owner, &app_name);
+    }
     match reply {
         Ok(res) => {
-            info!("Got a well-formed HttpRes as an invocation result!");
-            return Ok(
-                realize_response(res)
-            );
+            info!("[{}] Got a well-formed HttpRes as an invocation result!", &request_id);
+            tag(realize_response(res))
         }
         Err(e) => {
-            let text = format!("Got an invocation error: {}", e);
-            warn!("{}", &text);
-            return Ok(Response::new(text.into())); // TODO: Error pages
-                                                   // TODO: Not brazen copypaste
+            warn!("[{}] Got an invocation error: {}", &request_id, e);
+            // Errors down to the app itself get its own pages, if it has any.
+            let manifest = match e {
+                InvocationError::NoSuchApp(..) | InvocationError::TimedOut | InvocationError::WapcError(_) => Some(&*manifest),
+                _ => None,
+            };
+            tag(error_pages.render(&e, accept.as_deref(), manifest))
         }
     }
 }
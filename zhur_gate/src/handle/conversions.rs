@@ -1,15 +1,23 @@
 use http::{HeaderValue, header::HeaderName};
 use hyper::{Body, Response};
 use super::{FullRequest, HttpReq, InvocationError};
+use super::limits::read_body;
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use zhur_common::log::*;
+use crate::domains::HostResolver;
+use crate::routing::{split_app_path, Routing};
 use zhur_invk::{HttpRes, Invocation};
-/// Simplifies a `FullRequest` into an `HttpReq` of ours.
-pub async fn simplify_req(req: FullRequest) -> Result<HttpReq, InvocationError> {
+/// Simplifies a `FullRequest` into an `HttpReq` of ours, cutting the prefix that selected the app off its path.
+/// Bodies larger than the limit, in bytes, are rejected.
+pub async fn simplify_req(req: FullRequest, path_prefix: String, body_limit: u64) -> Result<HttpReq, InvocationError> {
     use http::header::COOKIE;
     let method = req.req.method().to_string();
     let uri = req.req.uri();
-    let path = uri.path().to_owned();
+    let path = match &uri.path()[path_prefix.len()..] {
+        "" => "/".to_owned(),
+        rest => rest.to_owned(),
+    };
     let query_params = match uri.query() {
         Some(s) => parse_query_string(s),
         None => BTreeMap::new(),
@@ -44,10 +52,9 @@ pub async fn simplify_req(req: FullRequest) -> Result<HttpReq, InvocationError>
             }
         }
     }
-    let body = match hyper::body::to_bytes(req.req.into_body()).await {
-        Ok(b) => b.to_vec(),
-        Err(_) => return Err(InvocationError::MalformedRequest),
-    };
+    let body = read_body(req.req.into_body(), body_limit).await?;
+    // Apps see the request ID the gateway settled on, whether or not the client sent one.
+    headers.insert("x-request-id".to_owned(), req.request_id.clone());
     Ok(HttpReq {
         body,
         path,
@@ -56,44 +63,51 @@ pub async fn simplify_req(req: FullRequest) -> Result<HttpReq, InvocationError>
         headers,
         query_params,
         ip_addr: req.ip.clone(),
+        path_prefix,
     })
 }
 
 impl FullRequest {
-    /// Turns a `FullRequest` into an `Invocation`.
-    pub async fn into_invoc(self) -> Result<Invocation, InvocationError> {
+    /// Finds out which app a request is for as the routing mode says, returning its owner, its name and the path prefix that selected it.
+    pub async fn target(&self, routing: Routing, resolver: &Arc<HostResolver>) -> Result<(String, String, String), InvocationError> {
         use http::header::HOST;
-        use zhur_common::bincode::serialize;
-        let host = match self.req.headers().get(HOST) {
-            Some(s) => match s.to_str() {
-                Ok(s) => s,
-                Err(_) => {
-                    warn!("Received an HTTP request with a non-UTF-text Host header, returning malformed ID error.");
-                    return Err(InvocationError::MalformedId("(not valid UTF-8 text)".into()))
-                }
-            },
-            None => {
-                warn!("Received an HTTP request with no Host header, returning a no ID error.");
-                return Err(InvocationError::NoId)
+        match routing {
+            Routing::Path => split_app_path(self.req.uri().path()),
+            Routing::Host => {
+                let host = match self.req.headers().get(HOST) {
+                    Some(s) => match s.to_str() {
+                        Ok(s) => s,
+                        Err(_) => {
+                            warn!("Received an HTTP request with a non-UTF-text Host header, returning malformed ID error.");
+                            return Err(InvocationError::MalformedId("(not valid UTF-8 text)".into()))
+                        }
+                    },
+                    None => {
+                        warn!("Received an HTTP request with no Host header, returning a no ID error.");
+                        return Err(InvocationError::NoId)
+                    }
+                }.to_owned();
+                let (owner, app_name) = resolver.resolve(&host).await?;
+                Ok((owner, app_name, String::new()))
             }
-        }.to_owned();
-        let segments = host.split('.').collect::<Vec<_>>();
-        if segments.is_empty() {
-            warn!("Received an HTTP request with an empty Host header, returning a no ID error.");
-            return Err(InvocationError::NoId);
-        } else if segments.len() < 2 {
-            warn!("Received an HTTP request with a Host header that could not be transformed into an app ID: \"{}\"", host);
-            return Err(InvocationError::MalformedId(host.into()));
         }
-        let req_simple = simplify_req(self).await?;
+    }
+    /// Turns a `FullRequest` into an `Invocation` of the app it is for, as found by `target`.
+    /// The body is read up to the limit, in bytes.
+    pub async fn into_invoc(self, target: (String, String, String), body_limit: u64) -> Result<Invocation, InvocationError> {
+        use zhur_common::bincode::serialize;
+        let (owner, app_name, prefix) = target;
+        let request_id = self.request_id.clone();
+        let req_simple = simplify_req(self, prefix, body_limit).await?;
         let req_bytes = match serialize(&req_simple) {
             Ok(b) => b,
             Err(_) => return Err(InvocationError::MalformedRequest),
         };
         let result = Invocation {
-            owner: segments[1].to_owned(),
-            app_name: segments[0].to_owned(),
+            owner,
+            app_name,
             payload: req_bytes,
+            request_id,
         };
         Ok(result)
     }
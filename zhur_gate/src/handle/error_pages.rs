@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::path::Path;
+use hyper::{Body, Response, StatusCode};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{AppManifest, PageFormat};
+use zhur_invk::InvocationError;
+
+/// The HTML error page used unless overridden. `{{status}}`, `{{reason}}` and `{{message}}` get filled in.
+const DEFAULT_HTML_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>{{status}} {{reason}}</title></head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>{{message}}</p>
+<hr>
+<p>Zhur</p>
+</body>
+</html>
+";
+/// The JSON error body used unless overridden. The placeholders are the same as for HTML.
+const DEFAULT_JSON_PAGE: &str = "{\"status\": {{status}}, \"error\": \"{{reason}}\", \"message\": \"{{message}}\"}\n";
+
+fn content_type(format: PageFormat) -> &'static str {
+    match format {
+        PageFormat::Html => "text/html; charset=utf-8",
+        PageFormat::Json => "application/json",
+    }
+}
+/// Makes text safe to put into a page of the given format.
+fn escape(format: PageFormat, text: &str) -> String {
+    match format {
+        PageFormat::Html => text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;"),
+        // Serializing a string produces it quoted and escaped. The template supplies its own quotes.
+        PageFormat::Json => {
+            let quoted = serde_json::to_string(text).unwrap();
+            quoted[1..quoted.len() - 1].to_owned()
+        }
+    }
+}
+
+/// Templates for the pages the gateway answers with when an invocation fails, in HTML and JSON.
+/// Custom templates are read at startup from the directory `ZHUR_GATE_ERROR_PAGES` points to, if set.
+/// A template named after a status code, such as `404.html`, applies to that status only; `default.html` and `default.json` to any other.
+pub struct ErrorPages {
+    /// Templates by status code (`None` for the default) and format.
+    templates: HashMap<(Option<u16>, PageFormat), String>,
+}
+
+impl ErrorPages {
+    /// Loads the templates, falling back to the built-in ones for any that are missing or unreadable.
+    pub fn from_env() -> Self {
+        let mut templates = HashMap::new();
+        templates.insert((None, PageFormat::Html), DEFAULT_HTML_PAGE.to_owned());
+        templates.insert((None, PageFormat::Json), DEFAULT_JSON_PAGE.to_owned());
+        let dir = match std::env::var("ZHUR_GATE_ERROR_PAGES") {
+            Ok(d) => d,
+            Err(_) => {
+                info!("ZHUR_GATE_ERROR_PAGES not set. Using the built-in error pages.");
+                return Self { templates };
+            }
+        };
+        let entries = match std::fs::read_dir(Path::new(&dir)) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Could not read the error page directory {:?}: {}. Using the built-in error pages.", &dir, e);
+                return Self { templates };
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let (stem, ext) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) {
+                (Some(s), Some(e)) => (s, e),
+                _ => continue,
+            };
+            let format = match ext {
+                "html" => PageFormat::Html,
+                "json" => PageFormat::Json,
+                _ => continue,
+            };
+            let status = match stem {
+                "default" => None,
+                code => match code.parse::<u16>() {
+                    Ok(c) => Some(c),
+                    Err(_) => continue,
+                },
+            };
+            match std::fs::read_to_string(&path) {
+                Ok(template) => {
+                    debug!("Loaded the error page template {:?}.", &path);
+                    templates.insert((status, format), template);
+                },
+                Err(e) => warn!("Could not read the error page template {:?}: {}", &path, e),
+            }
+        }
+        Self { templates }
+    }
+    /// Produces the response to a failed invocation, as JSON if the `Accept` header prefers it over HTML.
+    /// Templates from the app's manifest, if given, take precedence over the platform's own.
+    pub fn render(&self, err: &InvocationError, accept: Option<&str>, manifest: Option<&AppManifest>) -> Response<Body> {
+        let status = StatusCode::from_u16(err.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let format = match accept {
+            Some(a) if prefers_json(a) => PageFormat::Json,
+            _ => PageFormat::Html,
+        };
+        let app_template = |status: Option<u16>| manifest.and_then(|m| m.error_pages.iter()
+            .find(|page| page.status == status && page.format == format)
+            .map(|page| &page.template));
+        let template = app_template(Some(status.as_u16()))
+            .or_else(|| app_template(None))
+            .or_else(|| self.templates.get(&(Some(status.as_u16()), format)))
+            .or_else(|| self.templates.get(&(None, format)))
+            .unwrap();
+        let body = template
+            .replace("{{status}}", status.as_str())
+            .replace("{{reason}}", &escape(format, status.canonical_reason().unwrap_or("Error")))
+            .replace("{{message}}", &escape(format, &public_message(err)));
+        let mut builder = Response::builder()
+            .status(status)
+            .header("Content-Type", content_type(format));
+        if let InvocationError::RateLimited(secs) = err {
+            builder = builder.header("Retry-After", secs.to_string());
+        }
+        builder
+            .body(body.into())
+            .unwrap()
+    }
+}
+
+/// What visitors get told about an error. Problems inside the core are only described in the logs.
+fn public_message(err: &InvocationError) -> String {
+    match err {
+        InvocationError::WapcError(_) | InvocationError::OtherInternal | InvocationError::SerializeErr => {
+            "The app could not handle this request because of an internal error.".to_owned()
+        },
+        other => other.to_string(),
+    }
+}
+/// Whether an `Accept` header ranks JSON above HTML. Ties go to HTML, as do headers mentioning neither.
+fn prefers_json(accept: &str) -> bool {
+    let mut html = 0.0f32;
+    let mut json = 0.0f32;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| {
+                let p = p.trim();
+                p.strip_prefix("q=").and_then(|v| v.parse::<f32>().ok())
+            })
+            .next()
+            .unwrap_or(1.0);
+        match media_type.as_str() {
+            "text/html" | "text/*" => html = html.max(q),
+            "application/json" | "application/*" => json = json.max(q),
+            "*/*" => {
+                html = html.max(q);
+                json = json.max(q);
+            },
+            _ => (),
+        }
+    }
+    json > html
+}
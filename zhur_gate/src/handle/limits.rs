@@ -0,0 +1,182 @@
+use hyper::body::HttpBody;
+use hyper::{Body, Request};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::AppManifest;
+use zhur_invk::InvocationError;
+
+/// The largest request body accepted if neither the app nor `ZHUR_GATE_MAX_BODY_SIZE` say otherwise, in bytes.
+const DEFAULT_MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
+/// The most apps can raise their body size limit to if `ZHUR_GATE_MAX_APP_BODY_SIZE` is not set, in bytes.
+const DEFAULT_MAX_APP_BODY_SIZE: u64 = 64 * 1024 * 1024;
+/// The most headers a request can have if `ZHUR_GATE_MAX_HEADERS` is not set.
+const DEFAULT_MAX_HEADERS: u64 = 100;
+/// The most bytes a request's header names and values can add up to if `ZHUR_GATE_MAX_HEADER_SIZE` is not set.
+const DEFAULT_MAX_HEADER_SIZE: u64 = 16 * 1024;
+/// The longest a request's path and query can be if `ZHUR_GATE_MAX_URI_LENGTH` is not set, in bytes.
+const DEFAULT_MAX_URI_LENGTH: u64 = 8 * 1024;
+/// Room in hyper's read buffer for the method, the HTTP version and the line breaks and colons around headers, in bytes.
+const HEAD_SLACK: u64 = 1024;
+/// The smallest read buffer hyper accepts, in bytes.
+const MIN_BUF_SIZE: u64 = 8 * 1024;
+
+fn env_limit(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(v) => match v.parse::<u64>() {
+            Ok(n) => n,
+            Err(_) => {
+                warn!("{} set to invalid value \"{}\". Assuming {}.", name, &v, default);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// How large requests are allowed to be, so no client can make the gateway hold more than it should.
+pub struct Limits {
+    max_body_size: u64,
+    max_app_body_size: u64,
+    max_headers: u64,
+    max_header_size: u64,
+    max_uri_length: u64,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        Self {
+            max_body_size: env_limit("ZHUR_GATE_MAX_BODY_SIZE", DEFAULT_MAX_BODY_SIZE),
+            max_app_body_size: env_limit("ZHUR_GATE_MAX_APP_BODY_SIZE", DEFAULT_MAX_APP_BODY_SIZE),
+            max_headers: env_limit("ZHUR_GATE_MAX_HEADERS", DEFAULT_MAX_HEADERS),
+            max_header_size: env_limit("ZHUR_GATE_MAX_HEADER_SIZE", DEFAULT_MAX_HEADER_SIZE),
+            max_uri_length: env_limit("ZHUR_GATE_MAX_URI_LENGTH", DEFAULT_MAX_URI_LENGTH),
+        }
+    }
+    /// Checks everything about a request that is known before its body is read.
+    pub fn check_head(&self, req: &Request<Body>) -> Result<(), InvocationError> {
+        let uri_length = req.uri().path_and_query().map(|p| p.as_str().len()).unwrap_or(0);
+        if uri_length as u64 > self.max_uri_length {
+            warn!("Rejecting a request with a URI of {} bytes.", uri_length);
+            return Err(InvocationError::UriTooLong(self.max_uri_length as usize));
+        }
+        let headers = req.headers();
+        let header_size = headers.iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum::<usize>();
+        if headers.len() as u64 > self.max_headers || header_size as u64 > self.max_header_size {
+            warn!("Rejecting a request with {} headers, totalling {} bytes.", headers.len(), header_size);
+            return Err(InvocationError::HeadersTooLarge);
+        }
+        Ok(())
+    }
+    /// How large hyper's read buffer may grow, so that a request head well over the limits is cut off while it is being read,
+    /// rather than read in full and only then turned away by `check_head`.
+    pub fn head_buf_size(&self) -> usize {
+        let head = self.max_uri_length + self.max_header_size + 4 * self.max_headers + HEAD_SLACK;
+        head.max(MIN_BUF_SIZE) as usize
+    }
+    /// The body size limit for an app: its own if its manifest sets one, up to the ceiling for apps, or the default.
+    pub fn body_limit(&self, manifest: &AppManifest) -> u64 {
+        match manifest.max_body_size {
+            Some(n) => n.min(self.max_app_body_size),
+            None => self.max_body_size,
+        }
+    }
+}
+
+/// Reads a request body, giving up as soon as it is known to exceed the limit.
+/// Bodies announcing their length are turned away before any of them is read.
+pub async fn read_body(mut body: Body, limit: u64) -> Result<Vec<u8>, InvocationError> {
+    if let Some(length) = body.size_hint().exact() {
+        if length > limit {
+            warn!("Rejecting a request body of {} bytes, over the limit of {}.", length, limit);
+            return Err(InvocationError::BodyTooLarge(limit));
+        }
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| InvocationError::MalformedRequest)?;
+        if (bytes.len() + chunk.len()) as u64 > limit {
+            warn!("Rejecting a streamed request body over the limit of {} bytes.", limit);
+            return Err(InvocationError::BodyTooLarge(limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            max_body_size: 100,
+            max_app_body_size: 1000,
+            max_headers: 3,
+            max_header_size: 64,
+            max_uri_length: 32,
+        }
+    }
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn heads_within_the_limits_pass() {
+        let req = request("/todos?page=2", &[("host", "example.com"), ("accept", "text/html")]);
+        assert!(limits().check_head(&req).is_ok());
+    }
+    #[test]
+    fn long_uris_are_refused() {
+        let req = request(&format!("/{}", "a".repeat(32)), &[]);
+        assert!(matches!(limits().check_head(&req), Err(InvocationError::UriTooLong(32))));
+    }
+    #[test]
+    fn too_many_or_too_large_headers_are_refused() {
+        let req = request("/", &[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")]);
+        assert!(matches!(limits().check_head(&req), Err(InvocationError::HeadersTooLarge)));
+        let long = "x".repeat(64);
+        let req = request("/", &[("cookie", &long)]);
+        assert!(matches!(limits().check_head(&req), Err(InvocationError::HeadersTooLarge)));
+    }
+    #[test]
+    fn apps_can_raise_the_body_limit_up_to_the_ceiling() {
+        let mut manifest = AppManifest::default();
+        assert_eq!(limits().body_limit(&manifest), 100);
+        manifest.max_body_size = Some(500);
+        assert_eq!(limits().body_limit(&manifest), 500);
+        manifest.max_body_size = Some(5000);
+        assert_eq!(limits().body_limit(&manifest), 1000);
+    }
+    #[test]
+    fn the_head_buffer_fits_the_limits_and_hyper() {
+        assert_eq!(limits().head_buf_size(), 8 * 1024);
+        let large = Limits {
+            max_header_size: 64 * 1024,
+            ..limits()
+        };
+        assert!(large.head_buf_size() > 64 * 1024);
+    }
+    #[tokio::test]
+    async fn bodies_within_the_limit_are_read() {
+        let body = read_body(Body::from("hello"), 5).await.unwrap();
+        assert_eq!(body, b"hello");
+    }
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_refused() {
+        assert!(matches!(read_body(Body::from("hello!"), 5).await, Err(InvocationError::BodyTooLarge(5))));
+        let (mut sender, streamed) = Body::channel();
+        tokio::spawn(async move {
+            for chunk in &["hel", "lo!"] {
+                let _ = sender.send_data(hyper::body::Bytes::from_static(chunk.as_bytes())).await;
+            }
+        });
+        assert!(streamed.size_hint().exact().is_none());
+        assert!(matches!(read_body(streamed, 5).await, Err(InvocationError::BodyTooLarge(5))));
+    }
+}
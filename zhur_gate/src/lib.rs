@@ -1,54 +1,191 @@
-use hyper::{
-    server::conn::AddrStream,
-    service::{make_service_fn, service_fn},
-    Server,
-};
+use hyper::{Body, Request, Response};
 use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use zhur_common::log::*;
 
 /// HTTP request handling code.
 mod handle;
-use handle::{handle_req, FullRequest};
+use handle::{handle_req, ErrorPages, FullRequest, Limits};
+/// The structured log of every request served.
+mod access_log;
+use access_log::{AccessEntry, AccessLog};
+/// IDs telling requests apart.
+mod request_id;
 /// Communication with the core module.
 pub mod comms;
+/// Caching of app manifests from the app store.
+mod manifests;
+use manifests::ManifestCache;
+/// Resolution of hosts, custom domains included, to apps.
+mod domains;
+use domains::HostResolver;
+/// Choice between host- and path-based routing to apps.
+mod routing;
+use routing::Routing;
+/// HTTPS with certificates picked by SNI.
+mod tls;
+use tls::{https_redirect, serve_tls, TlsSettings};
+/// The addresses and sockets the gateway listens on.
+mod listen;
+use listen::{serve_http, serve_metrics, Bound, Listener};
+#[cfg(unix)]
+use listen::serve_unix;
+/// Rate limiting of clients, apps and owners.
+mod ratelimit;
+use ratelimit::RateLimiter;
 use zhur_common::msg::chan::*;
+use zhur_common::msg::gate_apst::{Apst2Gate, Gate2Apst};
 use zhur_invk::{HttpRes, Invocation, InvocationError};
 
-/// Runs a Hyper HTTP server.
-pub async fn start_server(client: ChannelClient<Invocation, Result<HttpRes, InvocationError>>) {
-    let port = match std::env::var("ZHUR_GATE_PORT") {
-        Ok(v) => match v.parse::<u16>() {
-            Ok(n) => n,
-            _ => {
-                error!("ZHUR_GATE_PORT set to invalid value \"{}\", exiting.", &v);
-                return;
+/// Everything requests are handled with, shared between connections and listeners.
+#[derive(Clone)]
+pub(crate) struct Shared {
+    client: ChannelClient<Invocation, Result<HttpRes, InvocationError>>,
+    error_pages: Arc<ErrorPages>,
+    manifests: Arc<ManifestCache>,
+    limits: Arc<Limits>,
+    rate_limiter: Arc<RateLimiter>,
+    routing: Routing,
+    resolver: Arc<HostResolver>,
+    access_log: Option<AccessLog>,
+}
+
+impl Shared {
+    /// Handles a request coming from the given address.
+    pub(crate) async fn serve(self, req: Request<Body>, ip: String) -> Result<Response<Body>, Infallible> {
+        let started = Instant::now();
+        let request_id = request_id::for_request(&req);
+        let entry = self.access_entry(&req, &ip, &request_id);
+        let access_log = self.access_log;
+        let res = handle_req(
+            FullRequest {
+                req,
+                ip,
+                request_id: request_id.clone(),
+            },
+            self.client, // TODO: remove this horrific hack
+            self.error_pages,
+            self.manifests,
+            self.limits,
+            self.rate_limiter,
+            self.routing,
+            self.resolver,
+        ).await;
+        let res = res.map(|res| with_request_id(res, &request_id));
+        if let (Some(log), Some(entry), Ok(res)) = (&access_log, entry, &res) {
+            log.log(entry.finish(res, started));
+        }
+        res
+    }
+    /// Sends a plain HTTP request to the same place over HTTPS on the given port, logging it like any other.
+    pub(crate) async fn redirect(self, req: Request<Body>, ip: String, tls_port: u16) -> Result<Response<Body>, Infallible> {
+        let started = Instant::now();
+        let request_id = request_id::for_request(&req);
+        let entry = self.access_entry(&req, &ip, &request_id);
+        let res = with_request_id(https_redirect(&req, tls_port), &request_id);
+        if let (Some(log), Some(entry)) = (&self.access_log, entry) {
+            log.log(entry.finish(&res, started));
+        }
+        Ok(res)
+    }
+    /// Starts an access log entry for a request, if an access log is kept.
+    fn access_entry(&self, req: &Request<Body>, ip: &str, request_id: &str) -> Option<AccessEntry> {
+        self.access_log.as_ref().map(|_| {
+            let client_ip = self.rate_limiter.client_ip(req, ip)
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| ip.to_owned());
+            AccessEntry::start(req, client_ip, request_id.to_owned())
+        })
+    }
+}
+
+/// Tells the client which ID its request went by.
+fn with_request_id(mut res: Response<Body>, request_id: &str) -> Response<Body> {
+    if let Ok(value) = hyper::header::HeaderValue::from_str(request_id) {
+        res.headers_mut().insert(request_id::HEADER, value);
+    }
+    res
+}
+
+/// Runs the gateway's HTTP and HTTPS servers on every configured listener. Exits the process if any of them cannot be set up, or once they have all stopped.
+pub async fn start_server(
+    client: ChannelClient<Invocation, Result<HttpRes, InvocationError>>,
+    apst_client: ChannelClient<Gate2Apst, Apst2Gate>,
+) {
+    let tls_settings = TlsSettings::from_env();
+    let listeners = match Listener::from_env(tls_settings.as_ref().map(|t| t.port)) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("{} Exiting.", e);
+            std::process::exit(1);
+        }
+    };
+    let apst_client = Arc::new(Mutex::new(apst_client));
+    let shared = Shared {
+        client,
+        error_pages: Arc::new(ErrorPages::from_env()),
+        manifests: Arc::new(ManifestCache::new(apst_client.clone())),
+        limits: Arc::new(Limits::from_env()),
+        rate_limiter: Arc::new(RateLimiter::from_env()),
+        routing: Routing::from_env(),
+        resolver: Arc::new(HostResolver::new(apst_client)),
+        access_log: AccessLog::from_env(),
+    };
+    let https_port = listeners.iter().find_map(|l| match l {
+        Listener::Https(addr) => Some(addr.port()),
+        _ => None,
+    });
+    // Everything that can fail is set up before anything is served, so a gateway that starts serves all it was told to.
+    let tls_config = match (&tls_settings, https_port) {
+        (Some(settings), Some(_)) => match tls::server_config(settings) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!("Could not set up TLS: {} Exiting.", e);
+                std::process::exit(1);
             }
         },
-        _ => {
-            warn!("ZHUR_GATE_PORT env var not set. Assuming port 8080.");
-            8080
+        (None, Some(_)) => {
+            error!("HTTPS listeners need ZHUR_GATE_TLS_CERTS to point to usable certificates. Exiting.");
+            std::process::exit(1);
+        },
+        _ => None,
+    };
+    let mut bound = Vec::new();
+    for listener in listeners {
+        match listener.bind().await {
+            Ok(b) => bound.push(b),
+            Err(e) => {
+                error!("{} Exiting.", e);
+                std::process::exit(1);
+            }
         }
+    }
+    // Plain HTTP requests only get redirected if there is an HTTPS listener to take them.
+    let redirect_to = match &tls_settings {
+        Some(settings) if settings.redirect => https_port,
+        _ => None,
     };
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    let make_svc = make_service_fn(move |conn: &AddrStream| {
-        let ip = conn.remote_addr().to_string();
-        let client = client.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handle_req(
-                    FullRequest {
-                        req,
-                        ip: ip.clone(),
-                    },
-                    client.clone(), // TODO: remove this horrific hack
-                )
-            }))
+    let mut servers = Vec::new();
+    for listener in bound {
+        match listener {
+            Bound::Http(builder, addr) => servers.push(tokio::spawn(serve_http(builder, addr, shared.clone(), redirect_to))),
+            Bound::Https(tcp, addr) => {
+                let config = tls_config.clone().expect("HTTPS listeners are only bound once TLS is set up.");
+                servers.push(tokio::spawn(serve_tls(tcp, addr, config, shared.clone())));
+            },
+            #[cfg(unix)]
+            Bound::Unix(unix, path) => servers.push(tokio::spawn(serve_unix(unix, path, shared.clone()))),
+            Bound::Metrics(builder, addr) => servers.push(tokio::spawn(serve_metrics(builder, addr, shared.rate_limiter.clone()))),
+        }
+    }
+    for server in servers {
+        if let Err(e) = server.await {
+            error!("A listener task failed: {}", e);
         }
-    });
-    let server = Server::bind(&addr).serve(make_svc);
-    if let Err(e) = server.await {
-        error!("HTTP server error: {}", e);
     }
+    // The servers only return on errors, so getting here means the gateway no longer serves anything.
+    error!("Every listener has stopped. Exiting.");
+    std::process::exit(1);
 }
@@ -0,0 +1,230 @@
+use hyper::server::Builder;
+use hyper::server::conn::{AddrIncoming, AddrStream, Http};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use std::sync::Arc;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use zhur_common::log::*;
+use crate::Shared;
+use crate::ratelimit::RateLimiter;
+
+/// Something the gateway accepts connections on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listener {
+    /// Plain HTTP on a TCP address, written `http://0.0.0.0:8080`.
+    Http(SocketAddr),
+    /// HTTPS on a TCP address, written `https://[::]:8443`.
+    Https(SocketAddr),
+    /// Plain HTTP on a Unix socket, written `unix:/run/zhur/gate.sock`, for a reverse proxy on the same machine.
+    Unix(PathBuf),
+    /// The gateway's own counters in the Prometheus text format, written `metrics://127.0.0.1:9180`.
+    Metrics(SocketAddr),
+}
+
+impl Listener {
+    fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let addr = |a: &str| a.parse::<SocketAddr>().map_err(|_| format!("\"{}\" is not a valid address and port.", a));
+        if let Some(a) = s.strip_prefix("http://") {
+            Ok(Self::Http(addr(a)?))
+        } else if let Some(a) = s.strip_prefix("https://") {
+            Ok(Self::Https(addr(a)?))
+        } else if let Some(a) = s.strip_prefix("metrics://") {
+            Ok(Self::Metrics(addr(a)?))
+        } else if let Some(p) = s.strip_prefix("unix:") {
+            match p.is_empty() {
+                true => Err("Unix socket listeners need a path.".to_owned()),
+                false => Ok(Self::Unix(PathBuf::from(p))),
+            }
+        } else {
+            Err(format!("\"{}\" is not of the form http://address:port, https://address:port, metrics://address:port or unix:path.", s))
+        }
+    }
+    /// Reads comma-separated listeners, failing if any of them is invalid or there are none.
+    fn parse_list(v: &str) -> Result<Vec<Self>, String> {
+        let listeners = v.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(Self::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("ZHUR_GATE_LISTEN has an invalid listener: {}", e))?;
+        match listeners.is_empty() {
+            true => Err(format!("ZHUR_GATE_LISTEN set to \"{}\", which has no listeners.", v)),
+            false => Ok(listeners),
+        }
+    }
+    /// Reads the comma-separated listeners in `ZHUR_GATE_LISTEN`, failing if any of them is invalid.
+    /// If it is not set, the gateway listens on localhost only: on `ZHUR_GATE_PORT`, and on the TLS port if given.
+    pub fn from_env(tls_port: Option<u16>) -> Result<Vec<Self>, String> {
+        if let Ok(v) = std::env::var("ZHUR_GATE_LISTEN") {
+            return Self::parse_list(&v);
+        }
+        let port = match std::env::var("ZHUR_GATE_PORT") {
+            Ok(v) => match v.parse::<u16>() {
+                Ok(n) => n,
+                _ => return Err(format!("ZHUR_GATE_PORT set to invalid value \"{}\".", &v)),
+            },
+            _ => {
+                warn!("ZHUR_GATE_PORT env var not set. Assuming port 8080.");
+                8080
+            }
+        };
+        let mut listeners = vec![Self::Http(SocketAddr::from(([127, 0, 0, 1], port)))];
+        if let Some(tls_port) = tls_port {
+            listeners.push(Self::Https(SocketAddr::from(([127, 0, 0, 1], tls_port))));
+        }
+        Ok(listeners)
+    }
+    /// Binds the listener to its address or path, failing if it is taken or cannot be listened on.
+    pub(crate) async fn bind(self) -> Result<Bound, String> {
+        match self {
+            Self::Http(addr) => Server::try_bind(&addr)
+                .map(|b| Bound::Http(b, addr))
+                .map_err(|e| format!("Could not bind the HTTP listener to {}: {}.", &addr, e)),
+            Self::Https(addr) => TcpListener::bind(&addr).await
+                .map(|l| Bound::Https(l, addr))
+                .map_err(|e| format!("Could not bind the HTTPS listener to {}: {}.", &addr, e)),
+            Self::Metrics(addr) => Server::try_bind(&addr)
+                .map(|b| Bound::Metrics(b, addr))
+                .map_err(|e| format!("Could not bind the metrics listener to {}: {}.", &addr, e)),
+            Self::Unix(path) => bind_unix(path),
+        }
+    }
+}
+
+/// A listener bound to its address, so that nothing gets served until every listener could be bound.
+pub(crate) enum Bound {
+    Http(Builder<AddrIncoming>, SocketAddr),
+    Https(TcpListener, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+    Metrics(Builder<AddrIncoming>, SocketAddr),
+}
+
+/// Binds a Unix socket, replacing any socket left behind at its path.
+#[cfg(unix)]
+fn bind_unix(path: PathBuf) -> Result<Bound, String> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        if meta.file_type().is_socket() {
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+    UnixListener::bind(&path)
+        .map(|l| Bound::Unix(l, path.clone()))
+        .map_err(|e| format!("Could not bind the Unix socket listener to {:?}: {}.", &path, e))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: PathBuf) -> Result<Bound, String> {
+    Err(format!("Cannot listen on the Unix socket {:?}: Unix sockets are not supported on this platform.", &path))
+}
+
+/// Serves plain HTTP on a bound address, or redirects every request to HTTPS on the given port.
+pub(crate) async fn serve_http(builder: Builder<AddrIncoming>, addr: SocketAddr, shared: Shared, redirect_to: Option<u16>) {
+    let head_buf_size = shared.limits.head_buf_size();
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let ip = conn.remote_addr().to_string();
+        let shared = shared.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let shared = shared.clone();
+                let ip = ip.clone();
+                async move {
+                    match redirect_to {
+                        Some(tls_port) => shared.redirect(req, ip, tls_port).await,
+                        None => shared.serve(req, ip).await,
+                    }
+                }
+            }))
+        }
+    });
+    match redirect_to {
+        Some(port) => info!("Redirecting HTTP requests at {} to HTTPS on port {}.", &addr, port),
+        None => info!("Serving HTTP at {}.", &addr),
+    }
+    if let Err(e) = builder.http1_max_buf_size(head_buf_size).serve(make_svc).await {
+        error!("HTTP server error: {}", e);
+    }
+}
+
+/// Serves the gateway's counters to monitoring on a bound address, whatever the path.
+pub(crate) async fn serve_metrics(builder: Builder<AddrIncoming>, addr: SocketAddr, rate_limiter: Arc<RateLimiter>) {
+    let make_svc = make_service_fn(move |_conn: &AddrStream| {
+        let rate_limiter = rate_limiter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let body = rate_limiter.metrics();
+                async move {
+                    Ok::<_, Infallible>(Response::builder()
+                        .header("Content-Type", "text/plain; version=0.0.4")
+                        .body(Body::from(body))
+                        .unwrap())
+                }
+            }))
+        }
+    });
+    info!("Serving metrics at {}.", &addr);
+    if let Err(e) = builder.serve(make_svc).await {
+        error!("Metrics server error: {}", e);
+    }
+}
+
+/// Serves plain HTTP on a bound Unix socket.
+/// There is no remote address to speak of, so requests get `unix` as their IP address.
+#[cfg(unix)]
+pub(crate) async fn serve_unix(listener: UnixListener, path: PathBuf, shared: Shared) {
+    info!("Serving HTTP at the Unix socket {:?}.", &path);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((s, _)) => s,
+            Err(e) => {
+                warn!("Could not accept a Unix socket connection: {}", e);
+                continue;
+            }
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let head_buf_size = shared.limits.head_buf_size();
+            let service = service_fn(move |req| shared.clone().serve(req, "unix".to_owned()));
+            if let Err(e) = Http::new().max_buf_size(head_buf_size).serve_connection(stream, service).await {
+                debug!("Unix socket connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listeners_are_parsed() {
+        let listeners = Listener::parse_list("http://0.0.0.0:8080, https://[::]:8443,unix:/run/zhur/gate.sock,metrics://127.0.0.1:9180,").unwrap();
+        assert_eq!(listeners, vec![
+            Listener::Http(SocketAddr::from(([0, 0, 0, 0], 8080))),
+            Listener::Https("[::]:8443".parse().unwrap()),
+            Listener::Unix(PathBuf::from("/run/zhur/gate.sock")),
+            Listener::Metrics(SocketAddr::from(([127, 0, 0, 1], 9180))),
+        ]);
+    }
+    #[test]
+    fn one_invalid_listener_fails_them_all() {
+        assert!(Listener::parse_list("http://0.0.0.0:8080,http://0.0.0.0").is_err());
+        assert!(Listener::parse_list("http://0.0.0.0:8080,ftp://0.0.0.0:21").is_err());
+        assert!(Listener::parse_list("unix:").is_err());
+        assert!(Listener::parse_list(" , ").is_err());
+    }
+    #[tokio::test]
+    async fn taken_addresses_fail_to_bind() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        assert!(Listener::Http(addr).bind().await.is_err());
+        assert!(Listener::Https(addr).bind().await.is_err());
+        assert!(Listener::Metrics(addr).bind().await.is_err());
+    }
+}
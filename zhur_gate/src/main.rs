@@ -1,5 +1,5 @@
 use zhur_common::{init_logger, msg::chan::client_server, zmq};
-use zhur_gate::comms::Gate2CoreServer;
+use zhur_gate::comms::{Gate2ApstServer, Gate2CoreServer};
 use zhur_gate::start_server;
 #[tokio::main]
 async fn main() {
@@ -12,6 +12,14 @@ async fn main() {
             server.handle()
         }
     });
+    let gate_apst_server = Gate2ApstServer::new(&zmq_ctx);
+    let (apst_client, apst_server) = client_server(gate_apst_server);
+    std::thread::spawn(move || {
+        let mut server = apst_server;
+        loop {
+            server.handle()
+        }
+    });
     init_logger();
-    start_server(client).await;
+    start_server(client, apst_client).await;
 }
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{AppManifest, Apst2Gate, Gate2Apst};
+use crate::comms::SharedApstClient;
+
+/// How long app manifests are cached for if `ZHUR_GATE_MANIFEST_TTL` is not set, in seconds.
+const DEFAULT_MANIFEST_TTL: u64 = 60;
+/// The most manifests kept at once. Past this, expired ones are dropped, and then the ones fetched longest ago.
+const MAX_CACHED_MANIFESTS: usize = 10_000;
+
+type Cache = HashMap<(String, String), (Instant, Arc<AppManifest>)>;
+
+/// Fetches app manifests from the app store, keeping them around for a while so errors do not each cost a round trip.
+pub struct ManifestCache {
+    client: SharedApstClient,
+    /// Manifests by owner and app name, along with when they were fetched.
+    cache: Mutex<Cache>,
+    ttl: Duration,
+}
+
+impl ManifestCache {
+    pub fn new(client: SharedApstClient) -> Self {
+        let ttl = match std::env::var("ZHUR_GATE_MANIFEST_TTL") {
+            Ok(v) => match v.parse::<u64>() {
+                Ok(n) => n,
+                Err(_) => {
+                    warn!("ZHUR_GATE_MANIFEST_TTL set to invalid value \"{}\". Assuming {} seconds.", &v, DEFAULT_MANIFEST_TTL);
+                    DEFAULT_MANIFEST_TTL
+                }
+            },
+            Err(_) => DEFAULT_MANIFEST_TTL,
+        };
+        Self {
+            client,
+            cache: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+    /// Gets the manifest of owner:app, asking the app store if it is not cached.
+    /// The app store client is shared with the `HostResolver` and blocks while waiting, so it is only ever used from a blocking thread.
+    pub async fn get(self: &Arc<Self>, owner: &str, app_name: &str) -> Arc<AppManifest> {
+        let key = (owner.to_owned(), app_name.to_owned());
+        if let Some((fetched, manifest)) = self.cache.lock().unwrap().get(&key) {
+            if fetched.elapsed() < self.ttl {
+                return manifest.clone();
+            }
+        }
+        let cache = self.clone();
+        match tokio::task::spawn_blocking(move || cache.fetch(key)).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("Could not get the manifest of {}:{}: {}", owner, app_name, e);
+                Arc::new(AppManifest::default())
+            }
+        }
+    }
+    /// Asks the app store for the manifest of owner:app and caches it. If the app store cannot provide it,
+    /// an empty one is cached in its place, so the platform's defaults get used until it is asked again.
+    fn fetch(&self, key: (String, String)) -> Arc<AppManifest> {
+        let (owner, app_name) = (&key.0, &key.1);
+        let reply = self.client.lock().unwrap().request(Gate2Apst::Manifest(owner.clone(), app_name.clone()));
+        let manifest = match reply {
+            Apst2Gate::Manifest(m) => Arc::new(m),
+            Apst2Gate::Error(e) => {
+                warn!("Could not get the manifest of {}:{}: {}", owner, app_name, e);
+                Arc::new(AppManifest::default())
+            },
+            other => {
+                warn!("Got an unexpected reply to a manifest request: {:?}", other);
+                Arc::new(AppManifest::default())
+            }
+        };
+        insert_bounded(&mut self.cache.lock().unwrap(), key, manifest.clone(), self.ttl, MAX_CACHED_MANIFESTS);
+        manifest
+    }
+}
+
+/// Caches a manifest, making room for it first if the cache holds `max` already.
+fn insert_bounded(cache: &mut Cache, key: (String, String), manifest: Arc<AppManifest>, ttl: Duration, max: usize) {
+    if cache.len() >= max && !cache.contains_key(&key) {
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < ttl);
+        while cache.len() >= max {
+            let oldest = cache.iter()
+                .min_by_key(|(_, (fetched, _))| *fetched)
+                .map(|(k, _)| k.clone());
+            match oldest {
+                Some(k) => cache.remove(&k),
+                None => break,
+            };
+        }
+    }
+    cache.insert(key, (Instant::now(), manifest));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use zhur_common::msg::chan::{client_server, HandleRequest};
+
+    /// Stands in for the app store, knowing the manifest of one app, taking its time over another and counting the requests it gets.
+    struct FakeApst(Arc<AtomicUsize>);
+
+    impl HandleRequest<Gate2Apst, Apst2Gate> for FakeApst {
+        fn handle(&mut self, msg: Gate2Apst) -> Apst2Gate {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match msg {
+                Gate2Apst::Manifest(_, app_name) if app_name == "slow" => {
+                    std::thread::sleep(Duration::from_millis(300));
+                    Apst2Gate::Manifest(AppManifest::default())
+                },
+                Gate2Apst::Manifest(_, app_name) if app_name == "notes" => Apst2Gate::Manifest(AppManifest { max_body_size: Some(5), ..AppManifest::default() }),
+                Gate2Apst::Manifest(..) => Apst2Gate::Error("No such app.".into()),
+                Gate2Apst::ResolveDomain(_) => Apst2Gate::Error("Not a manifest request.".into()),
+            }
+        }
+    }
+
+    fn cache_with_ttl(ttl: u64) -> (Arc<ManifestCache>, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (client, mut server) = client_server(FakeApst(requests.clone()));
+        std::thread::spawn(move || loop {
+            server.handle();
+        });
+        let manifests = ManifestCache {
+            client: Arc::new(Mutex::new(client)),
+            cache: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl),
+        };
+        (Arc::new(manifests), requests)
+    }
+    fn key(app_name: &str) -> (String, String) {
+        ("alice".to_owned(), app_name.to_owned())
+    }
+
+    #[tokio::test]
+    async fn manifests_are_cached_until_they_expire() {
+        let (manifests, requests) = cache_with_ttl(60);
+        assert_eq!(manifests.get("alice", "notes").await.max_body_size, Some(5));
+        assert_eq!(manifests.get("alice", "notes").await.max_body_size, Some(5));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        // Apps the app store cannot answer for fall back to the defaults.
+        assert!(manifests.get("alice", "other").await.max_body_size.is_none());
+        let (manifests, requests) = cache_with_ttl(0);
+        manifests.get("alice", "notes").await;
+        manifests.get("alice", "notes").await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn the_cache_stays_within_its_bound() {
+        let mut cache = Cache::new();
+        let ttl = Duration::from_secs(60);
+        for app_name in &["a", "b", "c", "d"] {
+            insert_bounded(&mut cache, key(app_name), Arc::new(AppManifest::default()), ttl, 2);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains_key(&key("c")));
+        assert!(cache.contains_key(&key("d")));
+    }
+    #[test]
+    fn expired_manifests_make_room_first() {
+        let mut cache = Cache::new();
+        insert_bounded(&mut cache, key("a"), Arc::new(AppManifest::default()), Duration::from_secs(60), 2);
+        insert_bounded(&mut cache, key("b"), Arc::new(AppManifest::default()), Duration::from_secs(60), 2);
+        // With no time to live, everything already cached counts as expired.
+        insert_bounded(&mut cache, key("c"), Arc::new(AppManifest::default()), Duration::from_secs(0), 2);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key(&key("c")));
+    }
+    #[tokio::test]
+    async fn waiting_on_the_app_store_does_not_block_the_runtime() {
+        let (manifests, requests) = cache_with_ttl(60);
+        // The test runtime has a single thread, so a fetch made on it would finish before the timeout got a chance to fire.
+        let waited = tokio::time::timeout(Duration::from_millis(50), manifests.get("alice", "slow")).await;
+        assert!(waited.is_err());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        manifests.get("alice", "slow").await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
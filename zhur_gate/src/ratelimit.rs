@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use hyper::{Body, Request};
+use zhur_common::log::*;
+use zhur_common::msg::gate_apst::{AppManifest, RateLimit};
+use zhur_invk::InvocationError;
+
+/// How often buckets that have filled back up are forgotten.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Reads a rate limit of the form `<requests per second>/<burst>`, such as `10/20`, from an environment variable.
+fn env_rate_limit(name: &str) -> Option<RateLimit> {
+    let v = std::env::var(name).ok()?;
+    let mut parts = v.splitn(2, '/');
+    let per_second = parts.next().and_then(|p| p.trim().parse::<f64>().ok());
+    let burst = parts.next().and_then(|b| b.trim().parse::<u32>().ok());
+    match (per_second, burst) {
+        (Some(per_second), Some(burst)) if (RateLimit { per_second, burst }).is_valid() => Some(RateLimit { per_second, burst }),
+        _ => {
+            warn!("{} set to invalid value \"{}\", which is not of the form <requests per second>/<burst>. Not limiting.", name, &v);
+            None
+        }
+    }
+}
+
+/// What a bucket is counting requests by.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Key {
+    /// A client, across every app.
+    Ip(IpAddr),
+    /// A client, for one app.
+    AppIp(String, String, IpAddr),
+    /// An app, across all of its clients.
+    App(String, String),
+    /// An owner, across all of their apps.
+    Owner(String),
+}
+
+impl Key {
+    fn scope(&self) -> &'static str {
+        match self {
+            Self::Ip(_) => "ip",
+            Self::AppIp(..) => "app_ip",
+            Self::App(..) => "app",
+            Self::Owner(_) => "owner",
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The limit the bucket was last used with, to tell when it has filled back up.
+    limit: RateLimit,
+}
+
+/// Buckets along with counts of the requests they turned away.
+#[derive(Default)]
+struct State {
+    buckets: HashMap<Key, Bucket>,
+    /// Rejections by scope.
+    by_scope: BTreeMap<&'static str, u64>,
+    /// Rejections by owner and app, whatever the scope. Requests turned away before their app was known are not counted here,
+    /// and neither are those for apps the core has not served, so made-up app IDs do not each get a counter.
+    by_app: BTreeMap<(String, String), u64>,
+    /// The owners and names of apps the core has served.
+    resolved_apps: HashSet<(String, String)>,
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Token-bucket rate limiting of requests, by client IP, app and owner.
+/// Every client, app and owner gets a bucket of `burst` tokens, refilled at `per_second` tokens a second, and each request takes one.
+/// Requests finding a bucket empty are rejected until it refills.
+pub struct RateLimiter {
+    /// Per client IP, across every app. From `ZHUR_GATE_RATE_LIMIT_IP`.
+    ip: Option<RateLimit>,
+    /// Per app, unless its manifest sets its own. From `ZHUR_GATE_RATE_LIMIT_APP`.
+    app: Option<RateLimit>,
+    /// Per owner, across all of their apps. From `ZHUR_GATE_RATE_LIMIT_OWNER`.
+    owner: Option<RateLimit>,
+    /// Proxies trusted to tell the client's address in `X-Forwarded-For`, from `ZHUR_GATE_TRUSTED_PROXIES`.
+    trusted_proxies: Vec<IpAddr>,
+    /// Whether connections over Unix sockets are trusted the same way, if `ZHUR_GATE_TRUSTED_PROXIES` lists `unix`.
+    trust_unix: bool,
+    state: Mutex<State>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn from_env() -> Self {
+        let (trusted_proxies, trust_unix) = match std::env::var("ZHUR_GATE_TRUSTED_PROXIES") {
+            Ok(v) => (v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty() && *s != "unix")
+                .filter_map(|s| match s.parse::<IpAddr>() {
+                    Ok(ip) => Some(ip),
+                    Err(_) => {
+                        warn!("Skipping \"{}\" in ZHUR_GATE_TRUSTED_PROXIES, as it is not an IP address.", s);
+                        None
+                    }
+                })
+                .collect(), v.split(',').any(|s| s.trim() == "unix")),
+            Err(_) => (Vec::new(), false),
+        };
+        Self {
+            ip: env_rate_limit("ZHUR_GATE_RATE_LIMIT_IP"),
+            app: env_rate_limit("ZHUR_GATE_RATE_LIMIT_APP"),
+            owner: env_rate_limit("ZHUR_GATE_RATE_LIMIT_OWNER"),
+            trusted_proxies,
+            trust_unix,
+            state: Mutex::new(State::default()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+    /// Works out which client a request comes from. Connections from trusted proxies, and over Unix sockets if those are trusted,
+    /// are taken to be relaying for the rightmost address in `X-Forwarded-For` that is not itself a trusted proxy.
+    pub fn client_ip(&self, req: &Request<Body>, peer: &str) -> Option<IpAddr> {
+        let peer_ip = peer.parse::<SocketAddr>().ok().map(|a| a.ip());
+        let trusted = match peer_ip {
+            Some(ip) => self.trusted_proxies.contains(&ip),
+            None => self.trust_unix,
+        };
+        if !trusted {
+            return peer_ip;
+        }
+        let forwarded = req.headers().get_all("X-Forwarded-For").iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|s| s.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        forwarded.into_iter().rev()
+            .find(|ip| !self.trusted_proxies.contains(ip))
+            .or(peer_ip)
+    }
+    /// Takes a token from the client's own bucket, before it is known which app the request is for.
+    pub fn check_client(&self, ip: Option<IpAddr>) -> Result<(), InvocationError> {
+        self.sweep();
+        let mut state = self.state.lock().unwrap();
+        if let (Some(ip), Some(limit)) = (ip, &self.ip) {
+            take(&mut state, Key::Ip(ip), limit, None)?;
+        }
+        Ok(())
+    }
+    /// Takes a token from the buckets of the app, its owner, and the client as a client of the app.
+    /// If any is empty, none are touched, so rejected requests do not count against the others.
+    pub fn check_app(&self, ip: Option<IpAddr>, owner: &str, app_name: &str, manifest: &AppManifest) -> Result<(), InvocationError> {
+        let mut checks = Vec::new();
+        if let Some(limit) = manifest.rate_limits.app.as_ref().or(self.app.as_ref()) {
+            checks.push((Key::App(owner.to_owned(), app_name.to_owned()), limit));
+        }
+        if let Some(limit) = &self.owner {
+            checks.push((Key::Owner(owner.to_owned()), limit));
+        }
+        if let (Some(ip), Some(limit)) = (ip, &manifest.rate_limits.per_ip) {
+            checks.push((Key::AppIp(owner.to_owned(), app_name.to_owned(), ip), limit));
+        }
+        let app = Some((owner, app_name));
+        let mut state = self.state.lock().unwrap();
+        for (key, limit) in &checks {
+            peek(&mut state, key.clone(), limit, app)?;
+        }
+        for (key, limit) in checks {
+            take(&mut state, key, limit, app)?;
+        }
+        Ok(())
+    }
+    /// Records that the core served an app, so requests to it turned away from now on are counted by app.
+    pub fn app_resolved(&self, owner: &str, app_name: &str) {
+        self.state.lock().unwrap().resolved_apps.insert((owner.to_owned(), app_name.to_owned()));
+    }
+    /// Forgets buckets that have filled back up, as they are no different from new ones.
+    fn sweep(&self) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if last_sweep.elapsed() < SWEEP_INTERVAL {
+                return;
+            }
+            *last_sweep = Instant::now();
+        }
+        let mut state = self.state.lock().unwrap();
+        let before = state.buckets.len();
+        state.buckets.retain(|_, bucket| {
+            bucket.tokens + bucket.updated.elapsed().as_secs_f64() * bucket.limit.per_second < bucket.limit.burst as f64
+        });
+        trace!("Forgot {} rate limiting buckets.", before - state.buckets.len());
+    }
+    /// The rejection counters, in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::from("# HELP zhur_gate_rate_limited_total Requests rejected by rate limiting.\n# TYPE zhur_gate_rate_limited_total counter\n");
+        for scope in &["ip", "app_ip", "app", "owner"] {
+            let count = state.by_scope.get(scope).copied().unwrap_or(0);
+            out.push_str(&format!("zhur_gate_rate_limited_total{{scope=\"{}\"}} {}\n", scope, count));
+        }
+        out.push_str("# HELP zhur_gate_rate_limited_app_total Requests to an app rejected by rate limiting, whatever the scope.\n# TYPE zhur_gate_rate_limited_app_total counter\n");
+        for ((owner, app_name), count) in &state.by_app {
+            out.push_str(&format!("zhur_gate_rate_limited_app_total{{owner=\"{}\",app=\"{}\"}} {}\n", escape_label(owner), escape_label(app_name), count));
+        }
+        out
+    }
+}
+
+/// Refills a bucket for the time since it was last used, creating it full if it is new.
+fn refill<'a>(state: &'a mut State, key: Key, limit: &RateLimit) -> &'a mut Bucket {
+    let bucket = state.buckets.entry(key).or_insert_with(|| Bucket {
+        tokens: limit.burst as f64,
+        updated: Instant::now(),
+        limit: *limit,
+    });
+    let elapsed = bucket.updated.elapsed().as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+    bucket.updated = Instant::now();
+    bucket.limit = *limit;
+    bucket
+}
+
+/// Checks that a bucket has a token to spare, counting a rejection if it does not.
+fn peek(state: &mut State, key: Key, limit: &RateLimit, app: Option<(&str, &str)>) -> Result<(), InvocationError> {
+    let scope = key.scope();
+    let tokens = refill(state, key, limit).tokens;
+    if tokens >= 1.0 {
+        return Ok(());
+    }
+    *state.by_scope.entry(scope).or_insert(0) += 1;
+    if let Some((owner, app_name)) = app {
+        let key = (owner.to_owned(), app_name.to_owned());
+        if state.resolved_apps.contains(&key) {
+            *state.by_app.entry(key).or_insert(0) += 1;
+        }
+    }
+    // Round up, so clients retrying on time find a token waiting.
+    let retry_after = ((1.0 - tokens) / limit.per_second).ceil().max(1.0) as u64;
+    debug!("A {} rate limit was hit. Retry in {} seconds.", scope, retry_after);
+    Err(InvocationError::RateLimited(retry_after))
+}
+
+/// Takes a token from a bucket, if it has one.
+fn take(state: &mut State, key: Key, limit: &RateLimit, app: Option<(&str, &str)>) -> Result<(), InvocationError> {
+    peek(state, key.clone(), limit, app)?;
+    refill(state, key, limit).tokens -= 1.0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zhur_common::msg::gate_apst::AppRateLimits;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter {
+            ip: Some(RateLimit { per_second: 1.0, burst: 2 }),
+            app: None,
+            owner: None,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            trust_unix: false,
+            state: Mutex::new(State::default()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn manifest(app: Option<RateLimit>, per_ip: Option<RateLimit>) -> AppManifest {
+        AppManifest {
+            rate_limits: AppRateLimits { app, per_ip },
+            ..AppManifest::default()
+        }
+    }
+
+    fn request(forwarded_for: &str) -> Request<Body> {
+        Request::builder().header("X-Forwarded-For", forwarded_for).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn buckets_allow_a_burst_and_then_refill() {
+        let limiter = RateLimiter {
+            ip: Some(RateLimit { per_second: 20.0, burst: 2 }),
+            ..limiter()
+        };
+        let ip = Some("192.0.2.1".parse().unwrap());
+        assert!(limiter.check_client(ip).is_ok());
+        assert!(limiter.check_client(ip).is_ok());
+        assert!(matches!(limiter.check_client(ip), Err(InvocationError::RateLimited(1))));
+        // Other clients have buckets of their own.
+        assert!(limiter.check_client(Some("192.0.2.2".parse().unwrap())).is_ok());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(limiter.check_client(ip).is_ok());
+    }
+    #[test]
+    fn retry_after_rounds_up() {
+        let limiter = RateLimiter {
+            ip: Some(RateLimit { per_second: 0.25, burst: 1 }),
+            ..limiter()
+        };
+        let ip = Some("192.0.2.1".parse().unwrap());
+        assert!(limiter.check_client(ip).is_ok());
+        assert!(matches!(limiter.check_client(ip), Err(InvocationError::RateLimited(4))));
+    }
+    #[test]
+    fn rejected_requests_take_no_tokens_from_other_buckets() {
+        let limiter = RateLimiter {
+            owner: Some(RateLimit { per_second: 0.001, burst: 2 }),
+            ..limiter()
+        };
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let strict = manifest(None, Some(RateLimit { per_second: 0.001, burst: 1 }));
+        assert!(limiter.check_app(ip, "alice", "notes", &strict).is_ok());
+        assert!(limiter.check_app(ip, "alice", "notes", &strict).is_err());
+        // The second request was turned away by its client bucket, so the owner still has a token for another client.
+        let other = Some("192.0.2.2".parse().unwrap());
+        assert!(limiter.check_app(other, "alice", "notes", &strict).is_ok());
+        assert!(limiter.check_app(Some("192.0.2.3".parse().unwrap()), "alice", "notes", &strict).is_err());
+    }
+    #[test]
+    fn only_resolved_apps_are_counted_by_app() {
+        let limiter = limiter();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let strict = manifest(Some(RateLimit { per_second: 0.001, burst: 1 }), None);
+        limiter.app_resolved("alice", "notes");
+        for app_name in &["notes", "made-up"] {
+            assert!(limiter.check_app(ip, "alice", app_name, &strict).is_ok());
+            assert!(limiter.check_app(ip, "alice", app_name, &strict).is_err());
+        }
+        let metrics = limiter.metrics();
+        assert!(metrics.contains("zhur_gate_rate_limited_total{scope=\"app\"} 2\n"));
+        assert!(metrics.contains("zhur_gate_rate_limited_app_total{owner=\"alice\",app=\"notes\"} 1\n"));
+        assert!(!metrics.contains("made-up"));
+    }
+    #[test]
+    fn metric_labels_are_escaped() {
+        assert_eq!(escape_label("plain"), "plain");
+        assert_eq!(escape_label("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+    }
+    #[test]
+    fn forwarded_for_is_only_trusted_from_trusted_proxies() {
+        let limiter = limiter();
+        let req = request("203.0.113.7, 10.0.0.1");
+        assert_eq!(limiter.client_ip(&req, "10.0.0.1:4321"), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(limiter.client_ip(&req, "192.0.2.1:4321"), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(limiter.client_ip(&req, "unix"), None);
+        let limiter = RateLimiter {
+            trust_unix: true,
+            ..limiter
+        };
+        assert_eq!(limiter.client_ip(&req, "unix"), Some("203.0.113.7".parse().unwrap()));
+    }
+}
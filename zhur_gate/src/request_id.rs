@@ -0,0 +1,82 @@
+use hyper::{Body, Request};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first half of every ID this process makes up, set the first time one is needed.
+static SEED: AtomicU64 = AtomicU64::new(0);
+/// The second half of the next ID.
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// The longest incoming request ID that is accepted.
+const MAX_LENGTH: usize = 128;
+
+/// The header request IDs are taken from and echoed back in.
+pub const HEADER: &str = "X-Request-Id";
+
+/// Picks the ID of a request: the one in its `X-Request-Id` header, as set by a client or a proxy in front of the gateway,
+/// if it is short and made up of safe characters only, or a new one otherwise.
+pub fn for_request(req: &Request<Body>) -> String {
+    let incoming = req.headers().get(HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+            && id.len() <= MAX_LENGTH
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:+/=".contains(c))
+        });
+    match incoming {
+        Some(id) => id.to_owned(),
+        None => generate(),
+    }
+}
+
+/// Makes up an ID for a request, unique across gateway processes and restarts for all practical purposes:
+/// 32 hex digits, the first half taken from the time and process ID at startup and the second half counting requests.
+pub fn generate() -> String {
+    if SEED.load(Ordering::Relaxed) == 0 {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1);
+        let seed = (nanos ^ ((std::process::id() as u64) << 40)).max(1);
+        let _ = SEED.compare_exchange(0, seed, Ordering::Relaxed, Ordering::Relaxed);
+    }
+    format!("{:016x}{:016x}", SEED.load(Ordering::Relaxed), NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder();
+        if let Some(id) = id {
+            builder = builder.header(HEADER, id);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    fn is_generated(id: &str) -> bool {
+        id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    #[test]
+    fn safe_incoming_ids_are_kept() {
+        for id in &["abc-123", "req_1.2:3+4/5=", "A", &"x".repeat(MAX_LENGTH)] {
+            assert_eq!(&for_request(&request(Some(id))), id);
+        }
+    }
+    #[test]
+    fn unsafe_or_missing_ids_are_replaced() {
+        let too_long = "x".repeat(MAX_LENGTH + 1);
+        for id in &[None, Some(""), Some("has space"), Some("quote\""), Some("semi;colon"), Some(too_long.as_str())] {
+            assert!(is_generated(&for_request(&request(*id))), "{:?}", id);
+        }
+        let mut req = request(None);
+        req.headers_mut().insert(HEADER, hyper::header::HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap());
+        assert!(is_generated(&for_request(&req)));
+    }
+    #[test]
+    fn generated_ids_differ() {
+        let (a, b) = (generate(), generate());
+        assert!(is_generated(&a) && is_generated(&b));
+        assert_ne!(a, b);
+        assert_eq!(a[..16], b[..16]);
+    }
+}
@@ -0,0 +1,47 @@
+use zhur_common::log::*;
+use zhur_invk::InvocationError;
+
+/// How the gateway tells which app a request is for, as set by `ZHUR_GATE_ROUTING`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Routing {
+    /// By the Host header, as in `app.owner.example.com` or a custom domain. The default.
+    Host,
+    /// By the first two segments of the path, as in `/owner/app/...`, so a single host can serve every app without wildcard DNS.
+    Path,
+}
+
+impl Routing {
+    pub fn from_env() -> Self {
+        match std::env::var("ZHUR_GATE_ROUTING") {
+            Ok(v) => match v.as_str() {
+                "host" => Self::Host,
+                "path" => Self::Path,
+                other => {
+                    warn!("ZHUR_GATE_ROUTING set to invalid value \"{}\". Assuming \"host\".", other);
+                    Self::Host
+                }
+            },
+            Err(_) => {
+                warn!("ZHUR_GATE_ROUTING not set. Assuming default of \"host\".");
+                Self::Host
+            }
+        }
+    }
+}
+
+/// Splits a path of the form `/owner/app/rest` into the owner, the app's name and the `/owner/app` prefix, which the path always starts with.
+pub fn split_app_path(path: &str) -> Result<(String, String, String), InvocationError> {
+    let mut segments = path.strip_prefix('/').unwrap_or(path).splitn(3, '/');
+    let owner = segments.next().unwrap_or("");
+    let app_name = segments.next().unwrap_or("");
+    if owner.is_empty() {
+        warn!("Received an HTTP request for \"{}\", which names no app, returning a no ID error.", path);
+        return Err(InvocationError::NoId);
+    }
+    if app_name.is_empty() {
+        warn!("Received an HTTP request for \"{}\", which is not of the form /owner/app/...", path);
+        return Err(InvocationError::MalformedId(path.into()));
+    }
+    let prefix = format!("/{}/{}", owner, app_name);
+    Ok((owner.to_owned(), app_name.to_owned(), prefix))
+}
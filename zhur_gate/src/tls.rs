@@ -0,0 +1,285 @@
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{ClientHello, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig};
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use zhur_common::log::*;
+use crate::Shared;
+
+/// The port HTTPS is served on if `ZHUR_GATE_TLS_PORT` is not set.
+const DEFAULT_TLS_PORT: u16 = 8443;
+/// How often the certificate directory is checked for changes if `ZHUR_GATE_TLS_RELOAD` is not set, in seconds.
+const DEFAULT_RELOAD_INTERVAL: u64 = 30;
+
+/// HTTPS settings, read from the environment. TLS is only enabled if `ZHUR_GATE_TLS_CERTS` is set.
+pub struct TlsSettings {
+    /// The directory certificates and keys are loaded from.
+    pub cert_dir: PathBuf,
+    /// The port HTTPS is served on, on localhost, unless `ZHUR_GATE_LISTEN` says otherwise.
+    pub port: u16,
+    /// Whether plain HTTP requests get redirected to HTTPS instead of being served.
+    pub redirect: bool,
+    pub reload_interval: Duration,
+}
+
+impl TlsSettings {
+    pub fn from_env() -> Option<Self> {
+        let cert_dir = match std::env::var("ZHUR_GATE_TLS_CERTS") {
+            Ok(d) => PathBuf::from(d),
+            Err(_) => {
+                info!("ZHUR_GATE_TLS_CERTS not set. Serving plain HTTP only.");
+                return None;
+            }
+        };
+        let port = match std::env::var("ZHUR_GATE_TLS_PORT") {
+            Ok(v) => match v.parse::<u16>() {
+                Ok(n) => n,
+                Err(_) => {
+                    warn!("ZHUR_GATE_TLS_PORT set to invalid value \"{}\". Assuming port {}.", &v, DEFAULT_TLS_PORT);
+                    DEFAULT_TLS_PORT
+                }
+            },
+            Err(_) => {
+                warn!("ZHUR_GATE_TLS_PORT not set. Assuming port {}.", DEFAULT_TLS_PORT);
+                DEFAULT_TLS_PORT
+            }
+        };
+        let redirect = match std::env::var("ZHUR_GATE_HTTPS_REDIRECT") {
+            Ok(v) => match v.as_str() {
+                "1" | "true" | "yes" => true,
+                "0" | "false" | "no" => false,
+                other => {
+                    warn!("ZHUR_GATE_HTTPS_REDIRECT set to invalid value \"{}\". Assuming true.", other);
+                    true
+                }
+            },
+            Err(_) => true,
+        };
+        let reload_interval = match std::env::var("ZHUR_GATE_TLS_RELOAD") {
+            Ok(v) => match v.parse::<u64>() {
+                Ok(n) if n > 0 => n,
+                _ => {
+                    warn!("ZHUR_GATE_TLS_RELOAD set to invalid value \"{}\". Assuming {} seconds.", &v, DEFAULT_RELOAD_INTERVAL);
+                    DEFAULT_RELOAD_INTERVAL
+                }
+            },
+            Err(_) => DEFAULT_RELOAD_INTERVAL,
+        };
+        Some(Self {
+            cert_dir,
+            port,
+            redirect,
+            reload_interval: Duration::from_secs(reload_interval),
+        })
+    }
+}
+
+/// The certificates loaded from a directory of PEM files, picked by the server name the client asks for (SNI).
+/// `example.com.crt` and `example.com.key` serve `example.com`, `_.example.com.*` serve any `*.example.com`,
+/// and `default.*` serves clients asking for no name or one without a certificate of its own.
+#[derive(Default)]
+struct Certs {
+    by_name: HashMap<String, CertifiedKey>,
+    default: Option<CertifiedKey>,
+}
+
+impl Certs {
+    fn load(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Could not read the certificate directory {:?}: {}", dir, e))?;
+        let mut certs = Self::default();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("crt") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(n) => n.to_ascii_lowercase(),
+                None => continue,
+            };
+            let key = match load_key(&path.with_extension("key"), &path) {
+                Ok(k) => k,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            debug!("Loaded the certificate {:?}.", &path);
+            match name.as_str() {
+                "default" => certs.default = Some(key),
+                _ => { certs.by_name.insert(name, key); },
+            }
+        }
+        Ok(certs)
+    }
+    fn get(&self, name: Option<&str>) -> Option<&CertifiedKey> {
+        let name = match name {
+            Some(n) => n.trim_end_matches('.').to_ascii_lowercase(),
+            None => return self.default.as_ref(),
+        };
+        let wildcard = name.splitn(2, '.').nth(1).map(|parent| format!("_.{}", parent));
+        self.by_name.get(&name)
+            .or_else(|| wildcard.and_then(|w| self.by_name.get(&w)))
+            .or_else(|| self.default.as_ref())
+    }
+}
+
+/// Loads a certificate chain and the private key that goes with it, which can be either PKCS#8 or RSA.
+fn load_key(key_path: &Path, cert_path: &Path) -> Result<CertifiedKey, String> {
+    let open = |path: &Path| File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open {:?}: {}", path, e));
+    let chain = certs(&mut open(cert_path)?)
+        .map_err(|_| format!("{:?} is not a valid PEM certificate chain.", cert_path))?;
+    if chain.is_empty() {
+        return Err(format!("{:?} contains no certificates.", cert_path));
+    }
+    let mut keys: Vec<PrivateKey> = pkcs8_private_keys(&mut open(key_path)?)
+        .map_err(|_| format!("{:?} is not a valid PEM file.", key_path))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(key_path)?)
+            .map_err(|_| format!("{:?} is not a valid PEM file.", key_path))?;
+    }
+    let key = keys.into_iter().next()
+        .ok_or_else(|| format!("{:?} contains no private key.", key_path))?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|_| format!("The private key in {:?} is of an unsupported type.", key_path))?;
+    Ok(CertifiedKey::new(chain, Arc::new(signing_key)))
+}
+
+/// Picks certificates by SNI, from a set that can be swapped out while connections are being served.
+struct CertResolver {
+    certs: RwLock<Certs>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let name: Option<&str> = client_hello.server_name().map(|n| n.into());
+        let key = self.certs.read().unwrap().get(name).cloned();
+        if key.is_none() {
+            debug!("No certificate for the server name {:?}.", name);
+        }
+        key
+    }
+}
+
+/// Modification times of the files in a directory, to tell when it has changed.
+fn dir_state(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut state = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.flatten()
+            .map(|e| (e.path(), e.metadata().and_then(|m| m.modified()).ok()))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    state.sort();
+    state
+}
+
+/// Watches the certificate directory, loading the certificates again whenever a file in it changes.
+/// If the new set cannot be loaded, the old one stays in use.
+fn watch_certs(resolver: Arc<CertResolver>, dir: PathBuf, interval: Duration) {
+    std::thread::Builder::new()
+        .name("gate_cert_reloader".to_owned())
+        .spawn(move || {
+            let mut state = dir_state(&dir);
+            loop {
+                std::thread::sleep(interval);
+                let new_state = dir_state(&dir);
+                if new_state == state {
+                    continue;
+                }
+                state = new_state;
+                match Certs::load(&dir) {
+                    Ok(certs) => {
+                        info!("Reloaded {} certificate(s) from {:?}.", certs.by_name.len() + certs.default.iter().count(), &dir);
+                        *resolver.certs.write().unwrap() = certs;
+                    },
+                    Err(e) => error!("Could not reload the certificates, keeping the old ones: {}", e),
+                }
+            }
+        })
+        .expect("Could not launch the certificate reloading thread!");
+}
+
+/// Builds the TLS configuration, loading the certificates and keeping them up to date.
+/// Fails if there are none to start with, as every HTTPS connection would fail.
+pub fn server_config(settings: &TlsSettings) -> Result<Arc<ServerConfig>, String> {
+    let certs = Certs::load(&settings.cert_dir)?;
+    if certs.by_name.is_empty() && certs.default.is_none() {
+        return Err(format!("There are no usable certificates in {:?}.", &settings.cert_dir));
+    }
+    let resolver = Arc::new(CertResolver { certs: RwLock::new(certs) });
+    watch_certs(resolver.clone(), settings.cert_dir.clone(), settings.reload_interval);
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(Arc::new(config))
+}
+
+/// Accepts TLS connections on a bound address and serves the requests coming through them.
+pub(crate) async fn serve_tls(listener: TcpListener, addr: SocketAddr, config: Arc<ServerConfig>, shared: Shared) {
+    info!("Serving HTTPS at {}.", &addr);
+    let acceptor = TlsAcceptor::from(config);
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Could not accept a TCP connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(s) => s,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", &remote_addr, e);
+                    return;
+                }
+            };
+            let ip = remote_addr.to_string();
+            let head_buf_size = shared.limits.head_buf_size();
+            let service = service_fn(move |req| shared.clone().serve(req, ip.clone()));
+            if let Err(e) = Http::new().max_buf_size(head_buf_size).serve_connection(stream, service).await {
+                debug!("HTTPS connection with {} failed: {}", &remote_addr, e);
+            }
+        });
+    }
+}
+
+/// Sends a plain HTTP request to the same place over HTTPS.
+pub fn https_redirect(req: &Request<Body>, tls_port: u16) -> Response<Body> {
+    let host = match req.headers().get(hyper::header::HOST).and_then(|h| h.to_str().ok()) {
+        Some(h) => h,
+        None => return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("HTTPS is required, but the request has no Host header to redirect to.".into())
+            .unwrap(),
+    };
+    // Strip any port, minding bracketed IPv6 addresses.
+    let hostname = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let authority = match tls_port {
+        443 => hostname.to_owned(),
+        port => format!("{}:{}", hostname, port),
+    };
+    let path_and_query = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(hyper::header::LOCATION, format!("https://{}{}", authority, path_and_query))
+        .body(Body::empty())
+        .unwrap()
+}
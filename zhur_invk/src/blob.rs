@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// The most bytes moved in one piece when putting or getting a blob, so large files never have to cross the wire in one message.
+pub const BLOB_CHUNK_SIZE: usize = 256 * 1024;
+
+/// What the blob service knows about a stored blob.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BlobMeta {
+    /// Where the blob is stored, as a `/`-separated path such as `avatars/alice.png`.
+    pub path: String,
+    /// The size of the blob in bytes.
+    pub size: u64,
+    /// The MIME type given when the blob was put.
+    pub content_type: String,
+    /// When the blob was last put, in seconds since the Unix epoch.
+    pub modified: u64,
+    /// Changes whenever the blob is put again, so readers can tell if it was replaced under them.
+    pub etag: String,
+}
@@ -27,6 +27,32 @@ pub enum InvocationError {
     WapcError(String),
     /// An internal problem occurred within the core.
     OtherInternal,
+
+    // The variants below are gateway-side, for requests exceeding its limits:
+    /// The request body is larger than the limit, given in bytes.
+    BodyTooLarge(u64),
+    /// The request has too many headers, or they are too large altogether.
+    HeadersTooLarge,
+    /// The request's URI is longer than the limit, given in bytes.
+    UriTooLong(usize),
+    /// The client, app or owner has made too many requests. Retry after the given number of seconds.
+    RateLimited(u64),
+}
+impl InvocationError {
+    /// The HTTP status code the gateway answers with when an invocation fails this way.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::MalformedRequest | Self::NoId | Self::MalformedId(_) => 400,
+            Self::NoSuchApp(..) => 404,
+            Self::NoCore | Self::MalformedReply => 502,
+            Self::TimedOut => 504,
+            Self::SerializeErr | Self::WapcError(_) | Self::OtherInternal => 500,
+            Self::BodyTooLarge(_) => 413,
+            Self::UriTooLong(_) => 414,
+            Self::RateLimited(_) => 429,
+            Self::HeadersTooLarge => 431,
+        }
+    }
 }
 impl Display for InvocationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -41,7 +67,12 @@ impl Display for InvocationError {
             
             Self::NoSuchApp(owner, app_name) => format!("The Zhur core could not find an app named {}:{}. It may have been disabled.", owner, app_name),
             Self::WapcError(s) => format!("The waPC host within the core returned the following error: {}", s),
-            Self::OtherInternal => "The core encountered an internal error that prevented it from returning a proper reply.".to_owned()
+            Self::OtherInternal => "The core encountered an internal error that prevented it from returning a proper reply.".to_owned(),
+
+            Self::BodyTooLarge(limit) => format!("The request body is larger than the limit of {} bytes.", limit),
+            Self::HeadersTooLarge => "The request's headers are too many or too large.".to_owned(),
+            Self::UriTooLong(limit) => format!("The request's URI is longer than the limit of {} bytes.", limit),
+            Self::RateLimited(secs) => format!("Too many requests have been made. Try again in {} seconds.", secs)
         };
         f.write_str(&text)
     }
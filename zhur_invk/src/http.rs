@@ -5,7 +5,7 @@ use std::collections::BTreeMap;
 pub struct HttpReq {
     /// The method of the request.
     pub method: String,
-    /// The path of the request.
+    /// The path of the request, relative to where the app is mounted.
     pub path: String,
     /// Headers represented as pairs of `String`s.
     pub headers: BTreeMap<String, String>,
@@ -17,6 +17,15 @@ pub struct HttpReq {
     pub ip_addr: String,
     /// The body of the request, as bytes.
     pub body: Vec<u8>,
+    /// The part of the original path that selected the app, such as `/owner/app` with path-based routing.
+    /// It is empty when apps are told apart by host instead.
+    pub path_prefix: String,
+}
+impl HttpReq {
+    /// Turns a path within the app, such as `/static/app.css`, into one that works for the visitor, taking the app's mount point into account.
+    pub fn url_for(&self, path: &str) -> String {
+        format!("{}/{}", self.path_prefix, path.trim_start_matches('/'))
+    }
 }
 #[derive(Clone, Debug, Deserialize, Serialize)]
 /// A Serde-friendly representation of an HTTP response.
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// How a secondary index picks the value it indexes out of a stored record.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum IndexExtractor {
+    /// The record is JSON. Index the value found at this JSON pointer (e.g. `/email`), in its serialized JSON form.
+    JsonPointer(String),
+    /// Index a range of the record's raw bytes, given as an offset and a length.
+    /// This suits bincode-encoded records, whose leading fixed-size fields always sit at the same offsets.
+    Bytes(u64, u64),
+}
+
+/// What happened to a K/V entry.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum KvOp {
+    /// The entry was set to this value.
+    Set(Vec<u8>),
+    /// The entry was deleted, or it expired.
+    Del,
+}
+/// A change to an entry in a K/V table, published by the K/V store and delivered to apps watching the table.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KvChange {
+    pub owner: String,
+    pub table: String,
+    pub key: String,
+    pub op: KvOp,
+}
+
+/// How sure the K/V store has to be that a write has reached the disk before acknowledging it.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Durability {
+    /// Whatever the K/V store is configured to use.
+    Default,
+    /// Acknowledge right away, leaving the write to sled's next background flush. A crash can lose writes acknowledged since then.
+    Async,
+    /// Flush to disk before acknowledging. Safest, but every write pays for a flush of its own.
+    Flush,
+    /// Wait for the next group commit, which flushes all writes made over the last few milliseconds together.
+    GroupCommit,
+}
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::Default
+    }
+}
@@ -4,6 +4,12 @@ pub mod err;
 pub use err::InvocationError;
 pub mod http;
 pub use http::*;
+/// Types shared between apps and the K/V store.
+pub mod kv;
+/// Types shared between apps and the SQL service.
+pub mod sql;
+/// Types shared between apps and the blob service.
+pub mod blob;
 /// Struct representing a Zhur app invocation.
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Invocation {
@@ -13,4 +19,6 @@ pub struct Invocation {
     pub app_name: String,
     /// The input for the app.
     pub payload: Vec<u8>,
+    /// The ID the gateway gave the HTTP request, or took from its `X-Request-Id` header, for matching up log lines across modules.
+    pub request_id: String,
 }
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+/// A value going into or coming out of an app's SQL database, following SQLite's storage classes.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Integer(v)
+    }
+}
+impl From<i32> for SqlValue {
+    fn from(v: i32) -> Self {
+        SqlValue::Integer(v as i64)
+    }
+}
+impl From<u32> for SqlValue {
+    fn from(v: u32) -> Self {
+        SqlValue::Integer(v as i64)
+    }
+}
+/// SQLite has no boolean type, so booleans are stored as 0 and 1.
+impl From<bool> for SqlValue {
+    fn from(v: bool) -> Self {
+        SqlValue::Integer(v as i64)
+    }
+}
+impl From<f64> for SqlValue {
+    fn from(v: f64) -> Self {
+        SqlValue::Real(v)
+    }
+}
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_owned())
+    }
+}
+impl From<Vec<u8>> for SqlValue {
+    fn from(v: Vec<u8>) -> Self {
+        SqlValue::Blob(v)
+    }
+}
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        match v {
+            Some(v) => v.into(),
+            None => SqlValue::Null,
+        }
+    }
+}
+
+impl SqlValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            SqlValue::Integer(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SqlValue::Real(v) => Some(*v),
+            SqlValue::Integer(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SqlValue::Text(v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            SqlValue::Blob(v) => Some(v),
+            SqlValue::Text(v) => Some(v.as_bytes()),
+            _ => None,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        *self == SqlValue::Null
+    }
+}
+
+/// The result of a query: the names of the columns selected, and the rows found, each with a value per column.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SqlRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<SqlValue>>,
+}
+
+impl SqlRows {
+    /// Finds the position of the column with the given name in each row.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+}
+
+/// The result of a statement that does not return rows.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct SqlExecuted {
+    /// How many rows were inserted, updated or deleted.
+    pub rows_affected: u64,
+    /// The rowid of the most recently inserted row in the database.
+    pub last_insert_id: i64,
+}
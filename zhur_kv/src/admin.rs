@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::kv_admin::{Admin2Kv, Kv2Admin};
+use zhur_common::zmq::Socket;
+use crate::store::full_key;
+use crate::{backup, replication, KvStore};
+
+/// Serves `Admin2Kv` requests on their own socket, so administrative work can happen while the store keeps serving the core.
+pub struct AdminServer {
+    /// ZMQ rep socket for handling incoming requests.
+    rep_socket: Socket,
+    store: KvStore,
+    /// Export and import paths are resolved relative to this directory, and may not leave it.
+    export_dir: PathBuf,
+}
+
+impl AdminServer {
+    pub fn new(rep_socket: Socket, store: KvStore, export_dir: PathBuf) -> Self {
+        Self { rep_socket, store, export_dir }
+    }
+    fn handle(&self) {
+        let request_bytes = self.rep_socket.recv_bytes(0)
+        .expect("Expected to be able to receive an admin request!");
+        let reply = match deserialize::<Admin2Kv>(&request_bytes) {
+            Ok(request) => self.handle_admin(request),
+            Err(_) => {
+                warn!("Got bytes on the admin socket that could not be deserialized as Admin2Kv.");
+                Kv2Admin::Error("Malformed request.".to_owned())
+            }
+        };
+        let reply_bytes = serialize(&reply)
+        .expect("Expected to serialize into Kv2Admin");
+        self.rep_socket.send(reply_bytes, 0)
+        .expect("Expected to send a reply back to the admin client.");
+    }
+    fn handle_admin(&self, request: Admin2Kv) -> Kv2Admin {
+        let is_write = matches!(request, Admin2Kv::Import(..) | Admin2Kv::Set(..) | Admin2Kv::Del(..));
+        if is_write && self.store.is_read_only() {
+            return Kv2Admin::Error("This K/V store is a read-only replica. Make changes on its primary, or promote it first.".to_owned());
+        }
+        let result = match request {
+            Admin2Kv::Export(path, owner, table) => {
+                info!("Got a request to export data to {:?}.", &path);
+                backup::resolve_path(&self.export_dir, &path)
+                .and_then(|path| backup::export(&self.store, &path, owner.as_deref(), table.as_deref()))
+                .map(Kv2Admin::Exported)
+            },
+            Admin2Kv::Import(path) => {
+                info!("Got a request to import data from {:?}.", &path);
+                backup::resolve_path(&self.export_dir, &path)
+                .and_then(|path| backup::import(&self.store, &path))
+                .map(Kv2Admin::Imported)
+            },
+            Admin2Kv::ListTables(owner) => {
+                trace!("Got a request to list the tables of {}.", &owner);
+                Ok(Kv2Admin::Tables(self.store.tables(&owner)))
+            },
+            Admin2Kv::ListKeys(owner, table, after, limit) => {
+                trace!("Got a request to list the keys in {}:{}.", &owner, &table);
+                let keys = self.store.scan(&full_key(&owner, &table, ""), after.as_deref(), limit as usize)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+                Ok(Kv2Admin::Keys(keys))
+            },
+            Admin2Kv::Get(owner, table, key) => {
+                Ok(Kv2Admin::Value(self.store.get(&full_key(&owner, &table, &key))))
+            },
+            Admin2Kv::Set(owner, table, key, value) => {
+                info!("Got an admin request to set {}:{}:{}.", &owner, &table, &key);
+                self.store.set(&full_key(&owner, &table, &key), value, None);
+                Ok(Kv2Admin::Done)
+            },
+            Admin2Kv::Del(owner, table, key) => {
+                info!("Got an admin request to delete {}:{}:{}.", &owner, &table, &key);
+                self.store.remove(&full_key(&owner, &table, &key));
+                Ok(Kv2Admin::Done)
+            },
+            Admin2Kv::ReplicationStatus => {
+                Ok(Kv2Admin::Replication(self.store.is_read_only(), replication::head(&self.store), replication::position(&self.store)))
+            },
+            Admin2Kv::Promote => {
+                if self.store.is_read_only() {
+                    info!("Got a request to promote this replica to a primary.");
+                    replication::promote(&self.store);
+                }
+                Ok(Kv2Admin::Done)
+            }
+        };
+        result.unwrap_or_else(|e| {
+            warn!("Admin request failed: {}", &e);
+            Kv2Admin::Error(e)
+        })
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_admin".to_owned())
+            .spawn(move || {
+                let server = self;
+                loop {
+                    server.handle();
+                }
+            })
+            .expect("Could not launch the K/V admin thread!")
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use zhur_common::log::*;
+use zhur_common::hex::{from_hex, to_hex};
+use crate::store::{full_key, split_full_key};
+use crate::KvStore;
+
+/// Identifies files produced by `export`.
+const FORMAT_NAME: &str = "zhur_kv";
+/// Version of the export format. Bump this whenever `Header` or `Record` change in an incompatible way.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The first line of an export file.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+/// Each line after the header holds one of these.
+#[derive(Deserialize, Serialize)]
+struct Record {
+    owner: String,
+    table: String,
+    key: String,
+    /// The value, hex-encoded.
+    value: String,
+    /// When the entry expires, in seconds since the Unix epoch, if it was set with a TTL.
+    expires_at: Option<u64>,
+}
+
+/// Resolves a file name given in an admin request against the export directory.
+/// Absolute paths and `..` are refused, so admin requests cannot read or overwrite files anywhere else.
+pub fn resolve_path(export_dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(name);
+    let is_plain = relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !is_plain {
+        return Err(format!("{:?} is not a relative path within the export directory.", name));
+    }
+    Ok(export_dir.join(relative))
+}
+
+/// Writes entries into a JSON Lines file at `path`: a `Header` line followed by one `Record` line per entry.
+/// Runs against the live database, so writes made during the export may or may not be included.
+pub fn export(store: &KvStore, path: &Path, owner: Option<&str>, table: Option<&str>) -> Result<u64, String> {
+    let prefix = match (owner, table) {
+        (None, None) => String::new(),
+        (Some(o), None) => format!("{}:", o),
+        (Some(o), Some(t)) => format!("{}:{}:", o, t),
+        (None, Some(_)) => return Err("Cannot export a table without specifying its owner.".to_owned()),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+        .map_err(|e| format!("Could not create the directory {:?}: {}", parent, e))?;
+    }
+    let file = File::create(path)
+    .map_err(|e| format!("Could not create {:?}: {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    let header = Header {
+        format: FORMAT_NAME.to_owned(),
+        version: FORMAT_VERSION,
+    };
+    write_line(&mut writer, &header)?;
+    let mut count = 0;
+    for (full_key, value, expires_at) in store.entries(&prefix) {
+        let record = match split_full_key(&full_key) {
+            Some((owner, table, key)) => Record {
+                owner: owner.to_owned(),
+                table: table.to_owned(),
+                key: key.to_owned(),
+                value: to_hex(&value),
+                expires_at,
+            },
+            None => {
+                warn!("Skipping the malformed key {:?} while exporting.", &full_key);
+                continue;
+            }
+        };
+        write_line(&mut writer, &record)?;
+        count += 1;
+    }
+    writer.flush()
+    .map_err(|e| format!("Could not write to {:?}: {}", path, e))?;
+    info!("Exported {} entries to {:?}.", count, path);
+    Ok(count)
+}
+
+/// Reads a file produced by `export` and writes its entries into the store, overwriting entries with the same keys.
+pub fn import(store: &KvStore, path: &Path) -> Result<u64, String> {
+    let file = File::open(path)
+    .map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+    let mut lines = BufReader::new(file).lines();
+    let header: Header = match lines.next() {
+        Some(line) => {
+            let line = line.map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+            serde_json::from_str(&line)
+            .map_err(|e| format!("{:?} does not start with a valid header: {}", path, e))?
+        },
+        None => return Err(format!("{:?} is empty.", path)),
+    };
+    if header.format != FORMAT_NAME {
+        return Err(format!("{:?} is not a zhur_kv export.", path));
+    }
+    if header.version > FORMAT_VERSION {
+        return Err(format!("{:?} uses format version {}, but only versions up to {} are supported.", path, header.version, FORMAT_VERSION));
+    }
+    let mut count = 0;
+    for (index, line) in lines.enumerate() {
+        let line_no = index + 2;
+        let line = line.map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        if line.is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line)
+        .map_err(|e| format!("Line {} of {:?} is not a valid record: {}", line_no, path, e))?;
+        let value = from_hex(&record.value)
+        .ok_or_else(|| format!("Line {} of {:?} has a value that is not valid hex.", line_no, path))?;
+        store.set_expiring_at(&full_key(&record.owner, &record.table, &record.key), value, record.expires_at);
+        count += 1;
+    }
+    info!("Imported {} entries from {:?}.", count, path);
+    Ok(count)
+}
+
+fn write_line<W: Write, S: Serialize>(writer: &mut W, item: &S) -> Result<(), String> {
+    serde_json::to_writer(&mut *writer, item)
+    .map_err(|e| format!("Could not write an export line: {}", e))?;
+    writer.write_all(b"\n")
+    .map_err(|e| format!("Could not write an export line: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::resolve_path;
+
+    #[test]
+    fn paths_resolve_within_the_export_directory() {
+        let dir = Path::new("/srv/exports");
+        assert_eq!(resolve_path(dir, "all.jsonl"), Ok(PathBuf::from("/srv/exports/all.jsonl")));
+        assert_eq!(resolve_path(dir, "alice/todos.jsonl"), Ok(PathBuf::from("/srv/exports/alice/todos.jsonl")));
+        assert_eq!(resolve_path(dir, "./all.jsonl"), Ok(PathBuf::from("/srv/exports/./all.jsonl")));
+    }
+
+    #[test]
+    fn paths_leaving_the_export_directory_are_refused() {
+        let dir = Path::new("/srv/exports");
+        for name in &["", "/etc/passwd", "../kv.sled", "alice/../../kv.sled", ".."] {
+            assert!(resolve_path(dir, name).is_err(), "{:?} was not refused", name);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use zhur_common::{bincode::{deserialize, serialize}, init_logger, zmq::{Context, SocketType}};
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::{change_topic, KvChange, KvOp, DEFAULT_KV_PUB_ENDPOINT};
+use zhur_common::msg::kv_admin::{Admin2Kv, Kv2Admin, DEFAULT_KV_ADMIN_ENDPOINT};
+
+const USAGE: &str = "Usage:
+    zhur_kv_admin export <file> [owner [table]]
+    zhur_kv_admin import <file>
+    zhur_kv_admin watch [owner [table]]
+    zhur_kv_admin status
+    zhur_kv_admin promote
+Files are read and written on the machine running zhur_kv, relative to its ZHUR_KV_EXPORT_DIR.";
+
+/// Command-line client for the K/V store's admin endpoint.
+fn main() {
+    init_logger();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(|a| a.as_str()).collect::<Vec<_>>();
+    let request = match args.as_slice() {
+        ["export", path] => Admin2Kv::Export(path.to_string(), None, None),
+        ["export", path, owner] => Admin2Kv::Export(path.to_string(), Some(owner.to_string()), None),
+        ["export", path, owner, table] => Admin2Kv::Export(path.to_string(), Some(owner.to_string()), Some(table.to_string())),
+        ["import", path] => Admin2Kv::Import(path.to_string()),
+        ["status"] => Admin2Kv::ReplicationStatus,
+        ["promote"] => Admin2Kv::Promote,
+        ["watch"] => return watch(String::new()),
+        ["watch", owner] => return watch(format!("{}:", owner)),
+        ["watch", owner, table] => return watch(change_topic(owner, table)),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let endpoint = match std::env::var("ZHUR_KV_ADMIN_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_KV_ADMIN_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_ADMIN_ENDPOINT);
+            DEFAULT_KV_ADMIN_ENDPOINT.to_string()
+        }
+    };
+    let ctx = Context::new();
+    let socket = ctx.socket(SocketType::REQ).unwrap();
+    socket.connect(&endpoint).expect("Could not connect to the K/V admin endpoint!");
+    socket.send(serialize(&request).unwrap(), 0).unwrap();
+    let reply_bytes = socket.recv_bytes(0).unwrap();
+    match deserialize::<Kv2Admin>(&reply_bytes).unwrap() {
+        Kv2Admin::Exported(n) => println!("Exported {} entries.", n),
+        Kv2Admin::Imported(n) => println!("Imported {} entries.", n),
+        Kv2Admin::Replication(read_only, head, position) => {
+            println!("Role: {}", if read_only { "replica" } else { "primary" });
+            println!("Replication log head: #{}", head);
+            if let Some(p) = position {
+                println!("Caught up with the primary to: #{}", p);
+            }
+        },
+        Kv2Admin::Done => println!("Done."),
+        Kv2Admin::Error(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        },
+        other => {
+            eprintln!("Unexpected reply: {:?}", other);
+            std::process::exit(1);
+        }
+    }
+}
+/// Prints every change published by the K/V store under the given topic prefix, until interrupted.
+fn watch(topic: String) {
+    let endpoint = match std::env::var("ZHUR_KV_PUB_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_KV_PUB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_PUB_ENDPOINT);
+            DEFAULT_KV_PUB_ENDPOINT.to_string()
+        }
+    };
+    let ctx = Context::new();
+    let socket = ctx.socket(SocketType::SUB).unwrap();
+    socket.connect(&endpoint).expect("Could not connect to the K/V change publishing endpoint!");
+    socket.set_subscribe(topic.as_bytes()).unwrap();
+    loop {
+        let frames = socket.recv_multipart(0).unwrap();
+        let change = match frames.last().map(|f| deserialize::<KvChange>(f)) {
+            Some(Ok(c)) => c,
+            _ => {
+                warn!("Got a message that could not be deserialized to a KvChange.");
+                continue;
+            }
+        };
+        match change.op {
+            KvOp::Set(value) => println!("SET {}:{}:{} ({} bytes)", change.owner, change.table, change.key, value.len()),
+            KvOp::Del => println!("DEL {}:{}:{}", change.owner, change.table, change.key),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::Durability;
+use crate::storage::{SharedStorage, SledStorage, SqliteStorage};
+use crate::{LoggedStorage, DEFAULT_GROUP_COMMIT_INTERVAL, DEFAULT_REPL_LOG_SIZE, DEFAULT_REPL_POLL_INTERVAL, DEFAULT_SWEEP_INTERVAL};
+
+/// Where the sled database goes if `ZHUR_KV_PATH` is not set. A leading `~` is expanded to the user's home directory.
+pub const DEFAULT_KV_PATH: &str = "~/.zhur/kv.sled";
+/// Where the SQLite database goes if `ZHUR_KV_PATH` is not set.
+pub const DEFAULT_SQLITE_KV_PATH: &str = "~/.zhur/kv.sqlite3";
+/// Where exports are written to and imports read from if `ZHUR_KV_EXPORT_DIR` is not set.
+pub const DEFAULT_EXPORT_DIR: &str = "~/.zhur/kv_exports";
+
+/// The storage engines the K/V store can keep its data in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// An embedded sled database, kept in a directory.
+    Sled,
+    /// A single SQLite database file.
+    Sqlite,
+}
+
+/// What part the K/V store plays in replication.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    /// Takes writes and serves its replication log to any replicas.
+    Primary,
+    /// Follows the primary at the given endpoint, serving reads only until promoted.
+    Replica(String),
+}
+
+/// Settings for the K/V store, read from environment variables at startup.
+#[derive(Clone, Debug)]
+pub struct KvConfig {
+    /// Which storage engine to use. Set with `ZHUR_KV_BACKEND` to `sled` (the default) or `sqlite`.
+    pub backend: Backend,
+    /// Directory holding the sled database, or the SQLite database file. Set with `ZHUR_KV_PATH`.
+    pub path: PathBuf,
+    /// Maximum size of sled's page cache in bytes. Set with `ZHUR_KV_CACHE_CAPACITY`.
+    pub cache_capacity: u64,
+    /// How often sled flushes to disk in the background, in milliseconds. `None` means only on explicit flushes.
+    /// Set with `ZHUR_KV_FLUSH_EVERY_MS`, where `0` disables background flushing.
+    pub flush_every_ms: Option<u64>,
+    /// Whether sled zstd-compresses data on disk. Set with `ZHUR_KV_COMPRESSION`.
+    pub use_compression: bool,
+    /// sled's zstd compression level, from 1 to 22. Set with `ZHUR_KV_COMPRESSION_FACTOR`.
+    pub compression_factor: i32,
+    /// Time between two sweeps for expired entries, in seconds. Set with `ZHUR_KV_SWEEP_INTERVAL`.
+    pub sweep_interval: Duration,
+    /// How many requests from the core can be carried out at once. Set with `ZHUR_KV_WORKERS`.
+    pub workers: usize,
+    /// How writes that do not ask for anything in particular are acknowledged.
+    /// Set with `ZHUR_KV_DURABILITY` to `async` (the default), `flush` or `group`.
+    pub default_durability: Durability,
+    /// How long the group committer gathers writes before flushing them together, in milliseconds.
+    /// Set with `ZHUR_KV_GROUP_COMMIT_MS`.
+    pub group_commit_interval: Duration,
+    /// Set with `ZHUR_KV_ROLE` to `primary` (the default) or `replica`.
+    /// Replicas follow the primary whose replication endpoint `ZHUR_KV_PRIMARY_ENDPOINT` is set to.
+    pub role: Role,
+    /// How many writes the replication log keeps for replicas to pull. Set with `ZHUR_KV_REPL_LOG_SIZE`, where `0` disables the log.
+    pub repl_log_size: u64,
+    /// How long a replica waits before asking its primary again after finding itself up to date, in milliseconds.
+    /// Set with `ZHUR_KV_REPL_POLL_MS`.
+    pub repl_poll_interval: Duration,
+    /// The only directory admin requests may export to or import from. Set with `ZHUR_KV_EXPORT_DIR`.
+    pub export_dir: PathBuf,
+}
+
+impl KvConfig {
+    /// Reads the configuration from the environment, returning a description of the problem if any value is invalid.
+    pub fn from_env() -> Result<Self, String> {
+        let backend = match std::env::var("ZHUR_KV_BACKEND").as_deref() {
+            Ok("sled") | Err(_) => Backend::Sled,
+            Ok("sqlite") => Backend::Sqlite,
+            Ok(other) => return Err(format!("ZHUR_KV_BACKEND set to invalid value \"{}\". Expected sled or sqlite.", other)),
+        };
+        let default_path = match backend {
+            Backend::Sled => DEFAULT_KV_PATH,
+            Backend::Sqlite => DEFAULT_SQLITE_KV_PATH,
+        };
+        let path = match std::env::var("ZHUR_KV_PATH") {
+            Ok(p) => p,
+            Err(_) => {
+                warn!("ZHUR_KV_PATH not set. Assuming default of {:?}.", default_path);
+                default_path.to_string()
+            }
+        };
+        let role = match std::env::var("ZHUR_KV_ROLE").as_deref() {
+            Ok("primary") | Err(_) => Role::Primary,
+            Ok("replica") => match std::env::var("ZHUR_KV_PRIMARY_ENDPOINT") {
+                Ok(e) => Role::Replica(e),
+                Err(_) => return Err("ZHUR_KV_ROLE is set to replica, but ZHUR_KV_PRIMARY_ENDPOINT is not set.".to_owned()),
+            },
+            Ok(other) => return Err(format!("ZHUR_KV_ROLE set to invalid value \"{}\". Expected primary or replica.", other)),
+        };
+        let export_dir = match std::env::var("ZHUR_KV_EXPORT_DIR") {
+            Ok(d) => d,
+            Err(_) => {
+                warn!("ZHUR_KV_EXPORT_DIR not set. Assuming default of {:?}.", DEFAULT_EXPORT_DIR);
+                DEFAULT_EXPORT_DIR.to_string()
+            }
+        };
+        let config = Self {
+            backend,
+            path: expand_home(&path)?,
+            cache_capacity: env_or("ZHUR_KV_CACHE_CAPACITY", 1024 * 1024 * 1024)?,
+            flush_every_ms: match env_or("ZHUR_KV_FLUSH_EVERY_MS", 500)? {
+                0 => None,
+                ms => Some(ms),
+            },
+            use_compression: env_or("ZHUR_KV_COMPRESSION", false)?,
+            compression_factor: env_or("ZHUR_KV_COMPRESSION_FACTOR", 5)?,
+            sweep_interval: Duration::from_secs(env_or("ZHUR_KV_SWEEP_INTERVAL", DEFAULT_SWEEP_INTERVAL.as_secs())?),
+            workers: env_or("ZHUR_KV_WORKERS", 4)?,
+            default_durability: match std::env::var("ZHUR_KV_DURABILITY") {
+                Ok(v) => parse_durability(&v)?,
+                Err(_) => Durability::Async,
+            },
+            group_commit_interval: Duration::from_millis(env_or("ZHUR_KV_GROUP_COMMIT_MS", DEFAULT_GROUP_COMMIT_INTERVAL.as_millis() as u64)?),
+            role,
+            repl_log_size: env_or("ZHUR_KV_REPL_LOG_SIZE", DEFAULT_REPL_LOG_SIZE)?,
+            repl_poll_interval: Duration::from_millis(env_or("ZHUR_KV_REPL_POLL_MS", DEFAULT_REPL_POLL_INTERVAL.as_millis() as u64)?),
+            export_dir: expand_home(&export_dir)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+    fn validate(&self) -> Result<(), String> {
+        match self.backend {
+            Backend::Sled if self.path.exists() && !self.path.is_dir() => {
+                return Err(format!("ZHUR_KV_PATH {:?} exists, but is not a directory.", self.path));
+            },
+            Backend::Sqlite if self.path.is_dir() => {
+                return Err(format!("ZHUR_KV_PATH {:?} is a directory, but SQLite needs a file.", self.path));
+            },
+            _ => (),
+        }
+        if self.cache_capacity == 0 {
+            return Err("ZHUR_KV_CACHE_CAPACITY must be greater than zero.".to_owned());
+        }
+        if !(1..=22).contains(&self.compression_factor) {
+            return Err(format!("ZHUR_KV_COMPRESSION_FACTOR must be between 1 and 22, got {}.", self.compression_factor));
+        }
+        if self.sweep_interval.as_secs() == 0 {
+            return Err("ZHUR_KV_SWEEP_INTERVAL must be greater than zero.".to_owned());
+        }
+        if self.workers == 0 {
+            return Err("ZHUR_KV_WORKERS must be greater than zero.".to_owned());
+        }
+        if self.group_commit_interval.as_millis() == 0 {
+            return Err("ZHUR_KV_GROUP_COMMIT_MS must be greater than zero.".to_owned());
+        }
+        if self.repl_poll_interval.as_millis() == 0 {
+            return Err("ZHUR_KV_REPL_POLL_MS must be greater than zero.".to_owned());
+        }
+        if self.export_dir.exists() && !self.export_dir.is_dir() {
+            return Err(format!("ZHUR_KV_EXPORT_DIR {:?} exists, but is not a directory.", self.export_dir));
+        }
+        Ok(())
+    }
+    /// Opens the database described by this configuration, creating its parent directories if need be.
+    /// Unless disabled, writes get recorded in the replication log.
+    pub fn open(&self) -> Result<SharedStorage, String> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Could not create the directory {:?}: {}", parent, e))?;
+        }
+        info!("Opening the {:?} K/V database at {:?}.", self.backend, self.path);
+        let storage: SharedStorage = match self.backend {
+            Backend::Sled => Arc::new(SledStorage::new(self.open_sled()?)),
+            Backend::Sqlite => Arc::new(SqliteStorage::open(&self.path)?),
+        };
+        match self.repl_log_size {
+            0 => Ok(storage),
+            size => Ok(Arc::new(LoggedStorage::new(storage, size))),
+        }
+    }
+    fn open_sled(&self) -> Result<sled::Db, String> {
+        sled::Config::new()
+        .path(&self.path)
+        .cache_capacity(self.cache_capacity)
+        .flush_every_ms(self.flush_every_ms)
+        .use_compression(self.use_compression)
+        .compression_factor(self.compression_factor)
+        .open()
+        .map_err(|e| format!("Could not open the K/V database at {:?}: {}", self.path, e))
+    }
+}
+
+/// Reads and parses an environment variable, falling back to `default` if it is not set.
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(v) => v.parse::<T>()
+            .map_err(|_| format!("{} set to invalid value \"{}\".", name, v)),
+        Err(_) => Ok(default),
+    }
+}
+fn parse_durability(value: &str) -> Result<Durability, String> {
+    match value {
+        "async" => Ok(Durability::Async),
+        "flush" => Ok(Durability::Flush),
+        "group" => Ok(Durability::GroupCommit),
+        _ => Err(format!("ZHUR_KV_DURABILITY set to invalid value \"{}\". Expected async, flush or group.", value)),
+    }
+}
+/// Expands a leading `~` into the current user's home directory, since sled takes paths literally.
+fn expand_home(path: &str) -> Result<PathBuf, String> {
+    if path == "~" || path.starts_with("~/") {
+        let home = std::env::var("HOME")
+        .map_err(|_| format!("Cannot expand \"{}\", as HOME is not set.", path))?;
+        Ok(PathBuf::from(home).join(path.trim_start_matches('~').trim_start_matches('/')))
+    } else {
+        Ok(PathBuf::from(path))
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::{Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use zhur_common::log::*;
+use crate::KvStore;
+
+/// Default time the group committer waits for more writes before flushing them together.
+pub const DEFAULT_GROUP_COMMIT_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Lets writers wait for a flush made on their behalf by the `GroupCommitter`.
+#[derive(Default)]
+pub(crate) struct GroupCommit {
+    state: Mutex<CommitState>,
+    flushed: Condvar,
+}
+#[derive(Default)]
+struct CommitState {
+    /// Tickets handed out to writers so far. Each writer waits until the flushed count catches up with its ticket.
+    requested: u64,
+    /// How many tickets are covered by a finished flush.
+    flushed: u64,
+}
+
+impl GroupCommit {
+    /// Blocks until a flush started after this call has finished. Writes made before calling this are then on disk.
+    pub(crate) fn wait(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.requested += 1;
+        let ticket = state.requested;
+        while state.flushed < ticket {
+            state = self.flushed.wait(state).unwrap();
+        }
+    }
+    /// The newest ticket handed out, or `None` if every writer has already been taken care of.
+    fn pending(&self) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        match state.requested > state.flushed {
+            true => Some(state.requested),
+            false => None,
+        }
+    }
+    /// Wakes up every writer holding a ticket up to `ticket`.
+    fn release(&self, ticket: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.flushed = ticket;
+        self.flushed.notify_all();
+    }
+}
+
+/// Flushes the K/V store every few milliseconds if there are writers waiting for a group commit.
+pub struct GroupCommitter {
+    store: KvStore,
+    interval: Duration,
+}
+
+impl GroupCommitter {
+    pub fn new(store: KvStore, interval: Duration) -> Self {
+        Self { store, interval }
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_group_committer".to_owned())
+            .spawn(move || {
+                let committer = self;
+                loop {
+                    std::thread::sleep(committer.interval);
+                    let group_commit = committer.store.group_commit();
+                    // Everything up to this ticket was written before the flush starts, so the flush covers it.
+                    if let Some(ticket) = group_commit.pending() {
+                        let flushed = committer.store.flush();
+                        trace!("Group commit flushed {} bytes.", flushed);
+                        group_commit.release(ticket);
+                    }
+                }
+            })
+            .expect("Could not launch the K/V group committer thread!")
+    }
+}
@@ -0,0 +1,185 @@
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::IndexExtractor;
+use zhur_common::hex::to_hex;
+use crate::storage::{SharedStorage, Tree};
+use crate::store::split_full_key;
+
+/// Secondary indexes over K/V tables.
+/// Every indexed entry gets a key of the form `owner:table:index:<indexed value as hex>:key` in the entry tree,
+/// so finding entries by their indexed value is a prefix scan. Hex keeps stray colons in indexed values from breaking that up.
+/// `Tree::IndexDefs` holds serialized `IndexExtractor`s, keyed by `owner:table:index`.
+/// `Tree::IndexEntries` holds the entries, with empty values; everything of note is in the key.
+#[derive(Clone)]
+pub struct Indexes {
+    storage: SharedStorage,
+}
+
+impl Indexes {
+    pub fn new(storage: SharedStorage) -> Self {
+        Self { storage }
+    }
+    /// Defines an index, replacing any previous one of the same name, then indexes the table's existing entries, given by full key.
+    pub fn define<I>(&self, owner: &str, table: &str, name: &str, extractor: &IndexExtractor, existing: I)
+    where I: Iterator<Item = (String, Vec<u8>)> {
+        self.drop_index(owner, table, name);
+        self.storage.insert(Tree::IndexDefs, def_key(owner, table, name).as_bytes(), &serialize(extractor).unwrap());
+        let mut count = 0;
+        for (full_key, value) in existing {
+            if let Some((_, _, key)) = split_full_key(&full_key) {
+                if let Some(indexed) = extract(extractor, &value) {
+                    self.storage.insert(Tree::IndexEntries, entry_key(owner, table, name, &indexed, key).as_bytes(), &[]);
+                    count += 1;
+                }
+            }
+        }
+        info!("Defined index {}:{}:{}, covering {} existing entries.", owner, table, name, count);
+    }
+    /// Removes an index's definition and all of its entries.
+    pub fn drop_index(&self, owner: &str, table: &str, name: &str) {
+        self.storage.remove(Tree::IndexDefs, def_key(owner, table, name).as_bytes());
+        let entry_keys = self.storage.scan_prefix(Tree::IndexEntries, entry_prefix(owner, table, name).as_bytes())
+            .map(|(entry_key, _)| (entry_key, None))
+            .collect();
+        self.storage.apply_batch(Tree::IndexEntries, entry_keys);
+    }
+    /// Brings every index on an entry's table up to date after its value changed from `old` to `new`, `None` meaning absent.
+    pub fn update(&self, full_key: &str, old: Option<&[u8]>, new: Option<&[u8]>) {
+        let (owner, table, key) = match split_full_key(full_key) {
+            Some(parts) => parts,
+            None => return,
+        };
+        let prefix = format!("{}:{}:", owner, table);
+        for (def_key, extractor_bytes) in self.storage.scan_prefix(Tree::IndexDefs, prefix.as_bytes()) {
+            let name = String::from_utf8_lossy(&def_key[prefix.len()..]).into_owned();
+            let extractor = deserialize::<IndexExtractor>(&extractor_bytes).unwrap();
+            let old_indexed = old.and_then(|v| extract(&extractor, v));
+            let new_indexed = new.and_then(|v| extract(&extractor, v));
+            if old_indexed == new_indexed {
+                continue;
+            }
+            if let Some(indexed) = old_indexed {
+                self.storage.remove(Tree::IndexEntries, entry_key(owner, table, &name, &indexed, key).as_bytes());
+            }
+            if let Some(indexed) = new_indexed {
+                self.storage.insert(Tree::IndexEntries, entry_key(owner, table, &name, &indexed, key).as_bytes(), &[]);
+            }
+        }
+    }
+    /// Lists the entries whose indexed value equals `value`, fetching their values by key with `get`. Unknown indexes have no entries.
+    /// For `JsonPointer` indexes, `value` is JSON and gets normalized the same way indexed values are.
+    /// Index entries are not written atomically with the values they point to, so racing writes to the same key can leave a stale one behind.
+    /// Each hit's current value is therefore checked against the index before it is returned.
+    pub fn query<F>(&self, owner: &str, table: &str, name: &str, value: &[u8], get: F) -> Vec<(String, Vec<u8>)>
+    where F: Fn(&str) -> Option<Vec<u8>> {
+        let extractor = match self.storage.get(Tree::IndexDefs, def_key(owner, table, name).as_bytes()) {
+            Some(bytes) => deserialize::<IndexExtractor>(&bytes).unwrap(),
+            None => {
+                warn!("Got a query for the undefined index {}:{}:{}.", owner, table, name);
+                return Vec::new();
+            }
+        };
+        let indexed = match &extractor {
+            IndexExtractor::JsonPointer(_) => match serde_json::from_slice::<serde_json::Value>(value) {
+                Ok(json) => serde_json::to_vec(&json).unwrap(),
+                Err(_) => return Vec::new(),
+            },
+            IndexExtractor::Bytes(_, _) => value.to_vec(),
+        };
+        let prefix = format!("{}{}:", entry_prefix(owner, table, name), to_hex(&indexed));
+        self.storage.scan_prefix(Tree::IndexEntries, prefix.as_bytes())
+            .filter_map(|(entry_key, _)| {
+                let key = String::from_utf8_lossy(&entry_key[prefix.len()..]).into_owned();
+                let current = get(&key)?;
+                if extract(&extractor, &current).as_ref() != Some(&indexed) {
+                    trace!("Skipping the stale index entry for {}:{}:{} in {}.", owner, table, key, name);
+                    return None;
+                }
+                Some((key, current))
+            })
+            .collect()
+    }
+}
+
+/// Picks the indexed value out of a record, if it has one.
+fn extract(extractor: &IndexExtractor, value: &[u8]) -> Option<Vec<u8>> {
+    match extractor {
+        IndexExtractor::JsonPointer(pointer) => {
+            let json = serde_json::from_slice::<serde_json::Value>(value).ok()?;
+            serde_json::to_vec(json.pointer(pointer)?).ok()
+        },
+        IndexExtractor::Bytes(offset, len) => {
+            let start = *offset as usize;
+            let end = start.checked_add(*len as usize)?;
+            value.get(start..end).map(|bytes| bytes.to_vec())
+        }
+    }
+}
+fn def_key(owner: &str, table: &str, name: &str) -> String {
+    format!("{}:{}:{}", owner, table, name)
+}
+fn entry_prefix(owner: &str, table: &str, name: &str) -> String {
+    format!("{}:{}:{}:", owner, table, name)
+}
+fn entry_key(owner: &str, table: &str, name: &str, indexed: &[u8], key: &str) -> String {
+    format!("{}{}:{}", entry_prefix(owner, table, name), to_hex(indexed), key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use zhur_common::msg::core_kv::IndexExtractor;
+    use crate::storage::{SledStorage, Storage, Tree};
+    use super::*;
+
+    fn temp_indexes() -> (Arc<SledStorage>, Indexes) {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = Arc::new(SledStorage::new(db));
+        let indexes = Indexes::new(storage.clone());
+        (storage, indexes)
+    }
+
+    #[test]
+    fn json_pointers_extract_normalized_json() {
+        let extractor = IndexExtractor::JsonPointer("/owner/name".to_owned());
+        let value = br#"{ "owner": { "name":  "alice" }, "done": false }"#;
+        assert_eq!(extract(&extractor, value), Some(br#""alice""#.to_vec()));
+        assert_eq!(extract(&extractor, br#"{"owner": {}}"#), None);
+        assert_eq!(extract(&extractor, b"not json"), None);
+    }
+
+    #[test]
+    fn byte_ranges_extract_only_when_in_bounds() {
+        assert_eq!(extract(&IndexExtractor::Bytes(1, 2), b"abcd"), Some(b"bc".to_vec()));
+        assert_eq!(extract(&IndexExtractor::Bytes(3, 2), b"abcd"), None);
+        assert_eq!(extract(&IndexExtractor::Bytes(u64::MAX, u64::MAX), b"abcd"), None);
+        assert_eq!(extract(&IndexExtractor::Bytes(4, 0), b"abcd"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn updates_move_entries_between_indexed_values() {
+        let (storage, indexes) = temp_indexes();
+        let get = |key: &str| storage.get(Tree::Data, format!("alice:t:{}", key).as_bytes());
+        indexes.define("alice", "t", "first", &IndexExtractor::Bytes(0, 1), std::iter::empty());
+        storage.insert(Tree::Data, b"alice:t:k", b"a1");
+        indexes.update("alice:t:k", None, Some(b"a1"));
+        assert_eq!(indexes.query("alice", "t", "first", b"a", get), vec![("k".to_owned(), b"a1".to_vec())]);
+        storage.insert(Tree::Data, b"alice:t:k", b"b1");
+        indexes.update("alice:t:k", Some(b"a1"), Some(b"b1"));
+        assert!(indexes.query("alice", "t", "first", b"a", get).is_empty());
+        assert_eq!(indexes.query("alice", "t", "first", b"b", get), vec![("k".to_owned(), b"b1".to_vec())]);
+    }
+
+    #[test]
+    fn stale_entries_are_left_out_of_queries() {
+        let (storage, indexes) = temp_indexes();
+        let get = |key: &str| storage.get(Tree::Data, format!("alice:t:{}", key).as_bytes());
+        indexes.define("alice", "t", "first", &IndexExtractor::Bytes(0, 1), std::iter::empty());
+        // Two racing writes whose index updates land in the wrong order leave an entry for "a" pointing at a value starting with "b".
+        storage.insert(Tree::Data, b"alice:t:k", b"b1");
+        indexes.update("alice:t:k", Some(b"a1"), Some(b"b1"));
+        indexes.update("alice:t:k", None, Some(b"a1"));
+        assert!(indexes.query("alice", "t", "first", b"a", get).is_empty());
+        assert_eq!(indexes.query("alice", "t", "first", b"b", get), vec![("k".to_owned(), b"b1".to_vec())]);
+    }
+}
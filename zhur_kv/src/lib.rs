@@ -0,0 +1,34 @@
+/// Herein lives the `KvStore`, which carries out `Core2Kv` requests against the configured storage.
+mod store;
+pub use store::KvStore;
+/// The storage engines the K/V store can keep its data in.
+pub mod storage;
+pub use storage::{SharedStorage, Storage};
+/// Secondary indexes over tables.
+mod index;
+/// Waiting for writes to reach the disk before acknowledging them.
+mod durability;
+pub use durability::{GroupCommitter, DEFAULT_GROUP_COMMIT_INTERVAL};
+/// Background removal of expired entries.
+mod sweep;
+pub use sweep::{Sweeper, DEFAULT_SWEEP_INTERVAL};
+/// Threads carrying out requests from the core.
+mod worker;
+pub use worker::{Worker, WORKER_ENDPOINT};
+/// Startup configuration.
+pub mod config;
+pub use config::KvConfig;
+/// Publishing of changes for watchers.
+mod publish;
+pub use publish::Publisher;
+/// Export and import of data in a portable format.
+pub mod backup;
+/// Streaming of writes from a primary store to read-only replicas.
+mod replication;
+pub use replication::{start_replication, LoggedStorage, ReplicationServer, Replicator, DEFAULT_REPL_LOG_SIZE, DEFAULT_REPL_POLL_INTERVAL};
+/// Serving of administrative requests.
+mod admin;
+pub use admin::AdminServer;
+pub use zhur_common::msg::core_kv::DEFAULT_KV_ENDPOINT as DEFAULT_ENDPOINT;
+pub use zhur_common::msg::core_kv::DEFAULT_KV_PUB_ENDPOINT as DEFAULT_PUB_ENDPOINT;
+pub use zhur_common::msg::kv_admin::DEFAULT_KV_ADMIN_ENDPOINT as DEFAULT_ADMIN_ENDPOINT;
@@ -1,45 +1,62 @@
-use zhur_common::{bincode::{deserialize, serialize}, init_logger, msg::core_kv::{Core2Kv, DEFAULT_KV_ENDPOINT, Kv2Core}, zmq::{Context, SocketType}};
+use zhur_common::{init_logger, zmq::{proxy, Context, SocketType}};
 use zhur_common::log::*;
+use zhur_kv::{start_replication, AdminServer, DEFAULT_ADMIN_ENDPOINT, DEFAULT_ENDPOINT, DEFAULT_PUB_ENDPOINT, GroupCommitter, KvConfig, KvStore, Publisher, Sweeper, Worker, WORKER_ENDPOINT};
 fn main() {
     init_logger();
-    let db = sled::open("~/.zhur/kv.sled").unwrap();
+    let config = match KvConfig::from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Invalid K/V store configuration: {} Exiting.", e);
+            std::process::exit(1);
+        }
+    };
+    let storage = match config.open() {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("{} Exiting.", e);
+            std::process::exit(1);
+        }
+    };
+    let store = KvStore::new(storage, config.default_durability);
+    Sweeper::new(store.clone(), config.sweep_interval).run_as_thread();
+    GroupCommitter::new(store.clone(), config.group_commit_interval).run_as_thread();
     let zmq_ctx = Context::new();
-    let socket = zmq_ctx.socket(SocketType::REP).unwrap();
+    start_replication(&zmq_ctx, &config, &store);
+    let router_socket = zmq_ctx.socket(SocketType::ROUTER).unwrap();
     let endpoint = match std::env::var("ZHUR_KV_ENDPOINT") {
         Ok(e) => e,
         Err(_) => {
-            warn!("ZHUR_KV_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_ENDPOINT);
-            DEFAULT_KV_ENDPOINT.to_string()
+            warn!("ZHUR_KV_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_ENDPOINT);
+            DEFAULT_ENDPOINT.to_string()
+        }
+    };
+    router_socket.bind(&endpoint).expect("Could not connect to KV server!");
+    let admin_socket = zmq_ctx.socket(SocketType::REP).unwrap();
+    let admin_endpoint = match std::env::var("ZHUR_KV_ADMIN_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_KV_ADMIN_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_ADMIN_ENDPOINT);
+            DEFAULT_ADMIN_ENDPOINT.to_string()
+        }
+    };
+    admin_socket.bind(&admin_endpoint).expect("Could not bind the K/V admin socket!");
+    AdminServer::new(admin_socket, store.clone(), config.export_dir.clone()).run_as_thread();
+    let pub_socket = zmq_ctx.socket(SocketType::PUB).unwrap();
+    let pub_endpoint = match std::env::var("ZHUR_KV_PUB_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_KV_PUB_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_PUB_ENDPOINT);
+            DEFAULT_PUB_ENDPOINT.to_string()
         }
     };
-    socket.bind(&endpoint).expect("Could not connect to KV server!");
-    loop {
-        let request_bytes = socket.recv_bytes(0).unwrap();
-        trace!("Got request bytes.");
-        let request: Core2Kv = deserialize(&request_bytes).unwrap();
-        let response = match request {
-            Core2Kv::KvGet(owner, table, key) => {
-                let full_key = format!("{}:{}:{}", owner, table, key);
-                trace!("Got a request to get {}", &full_key);
-                let bytes = db.get(&full_key).unwrap().map(|ivec| ivec.to_vec());
-                Kv2Core::Value(bytes)
-            },
-            Core2Kv::KvSet(owner, table, key, value) => {
-                let full_key = format!("{}:{}:{}", owner, table, key);
-                trace!("Got a request to set {}", &full_key);
-                db.insert(&full_key, value).unwrap();
-                Kv2Core::OperationSuccessful
-            },
-            Core2Kv::KvDel(owner, table, key) => {
-                let full_key = format!("{}:{}:{}", owner, table, key);
-                trace!("Got a request to delete {}", &full_key);
-                db.remove(&full_key).unwrap();
-                Kv2Core::OperationSuccessful
-            }
-        };
-        let res_bytes = serialize(&response).unwrap();
-        trace!("Sending reply...");
-        socket.send(res_bytes, 0).unwrap();
-        trace!("Sent reply!");
+    pub_socket.bind(&pub_endpoint).expect("Could not bind the K/V change publishing socket!");
+    Publisher::new(pub_socket, store.clone()).run_as_thread();
+    let dealer_socket = zmq_ctx.socket(SocketType::DEALER).unwrap();
+    dealer_socket.bind(WORKER_ENDPOINT).expect("Could not bind the K/V worker endpoint!");
+    for id in 0..config.workers {
+        Worker::new(id, &zmq_ctx, store.clone()).run_as_thread();
     }
+    info!("Serving K/V requests with {} workers.", config.workers);
+    // Requests from the core are spread out over the workers, and their replies routed back to whichever socket sent them.
+    proxy(&router_socket, &dealer_socket).expect("The K/V request proxy failed!");
 }
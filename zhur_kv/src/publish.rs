@@ -0,0 +1,56 @@
+use std::thread::JoinHandle;
+use zhur_common::bincode::serialize;
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::{change_topic, KvChange, KvOp};
+use zhur_common::zmq::Socket;
+use crate::storage::StorageEvent;
+use crate::store::split_full_key;
+use crate::KvStore;
+
+/// Publishes a `KvChange` for every write to the store, as seen by `Storage::watch`, on a PUB socket.
+/// Each message has two frames: the `owner:table:` topic, so subscribers can filter by prefix, and the serialized change.
+pub struct Publisher {
+    pub_socket: Socket,
+    store: KvStore,
+}
+
+impl Publisher {
+    pub fn new(pub_socket: Socket, store: KvStore) -> Self {
+        Self { pub_socket, store }
+    }
+    fn publish(&self, event: StorageEvent) {
+        let (full_key, op) = match event {
+            StorageEvent::Insert(key, value) => (key, KvOp::Set(value)),
+            StorageEvent::Remove(key) => (key, KvOp::Del),
+        };
+        let full_key = String::from_utf8_lossy(&full_key);
+        let change = match split_full_key(&full_key) {
+            Some((owner, table, key)) => KvChange {
+                owner: owner.to_owned(),
+                table: table.to_owned(),
+                key: key.to_owned(),
+                op,
+            },
+            None => {
+                warn!("Not publishing a change to the malformed key {:?}.", &full_key);
+                return;
+            }
+        };
+        let topic = change_topic(&change.owner, &change.table);
+        trace!("Publishing a change to {}{}.", &topic, &change.key);
+        self.pub_socket.send_multipart(vec![topic.into_bytes(), serialize(&change).unwrap()], 0)
+        .expect("Expected to be able to publish a K/V change.");
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_publisher".to_owned())
+            .spawn(move || {
+                let publisher = self;
+                for event in publisher.store.watch() {
+                    publisher.publish(event);
+                }
+                error!("The K/V store's change subscription ended!");
+            })
+            .expect("Could not launch the K/V publisher thread!")
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::kv_repl::{Primary2Replica, ReplOp, Replica2Primary, DEFAULT_KV_REPL_ENDPOINT};
+use zhur_common::sockets::timed_req_socket;
+use zhur_common::zmq::{Context, Socket, SocketType};
+use crate::storage::{SharedStorage, Storage, StorageEvent, Tree};
+use crate::config::Role;
+use crate::{KvConfig, KvStore};
+
+/// Default number of writes kept in the replication log. Replicas falling further behind than this have to start over from a snapshot.
+pub const DEFAULT_REPL_LOG_SIZE: u64 = 100_000;
+/// Default time a replica waits before asking again after finding itself up to date.
+pub const DEFAULT_REPL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How many writes or snapshot pairs a replica asks for at a time.
+const REPL_BATCH_SIZE: u32 = 1000;
+/// How long a replica waits for its primary to answer, in milliseconds.
+const REPL_TIMEOUT: i32 = 5000;
+/// Key in `Tree::ReplState` under which a replica keeps the sequence number of the last write it applied from its primary.
+const POSITION_KEY: &[u8] = b"primary_position";
+/// Key in `Tree::ReplState` under which a replica keeps the replication endpoint of the primary it follows.
+const PRIMARY_KEY: &[u8] = b"primary_endpoint";
+/// Key in `Tree::ReplState` under which a promoted replica keeps the endpoint of the primary it stopped following,
+/// so that it stays promoted across restarts.
+const PROMOTED_FROM_KEY: &[u8] = b"promoted_from";
+
+/// How many locks the keys of replicated writes are spread over. Writes to keys under different locks go ahead in parallel.
+const LOCK_STRIPES: usize = 64;
+
+/// Wraps another `Storage`, recording every write to the replicated trees in `Tree::ReplLog` under increasing sequence numbers,
+/// so replicas can pull them. Writes to the same key are serialized so they are logged in the order they were made in;
+/// writes to other keys are not, as every logged write carries the whole new value and their relative order does not matter.
+/// The write and its log entry are not atomic: a crash in between loses the write for replicas until they next start over from a snapshot.
+pub struct LoggedStorage {
+    inner: SharedStorage,
+    /// The sequence number of the latest write given one, `0` if none. Its log entry may still be on its way in.
+    head: AtomicU64,
+    /// Locks serializing the writes to each key, picked by the key's hash.
+    stripes: Vec<Mutex<()>>,
+    /// How many writes to keep in the log.
+    retain: u64,
+}
+
+impl LoggedStorage {
+    pub fn new(inner: SharedStorage, retain: u64) -> Self {
+        trim_to_last_run(&*inner);
+        let head = find_head(&*inner);
+        debug!("The replication log is at sequence number {}.", head);
+        // The log may have been kept longer before. Trimming it right away keeps its sequence numbers contiguous.
+        if head > retain {
+            let stale = inner.range_from(Tree::ReplLog, &[])
+                .take_while(|(key, _)| decode_seq(key) <= head - retain)
+                .map(|(key, _)| (key, None))
+                .collect::<Vec<_>>();
+            inner.apply_batch(Tree::ReplLog, stale);
+        }
+        Self {
+            inner,
+            head: AtomicU64::new(head),
+            stripes: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            retain,
+        }
+    }
+    /// The index of the lock serializing writes to a key.
+    fn stripe(&self, tree: Tree, key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        tree.name().hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % LOCK_STRIPES as u64) as usize
+    }
+    fn lock(&self, tree: Tree, key: &[u8]) -> MutexGuard<'_, ()> {
+        self.stripes[self.stripe(tree, key)].lock().unwrap()
+    }
+    /// Logs a write. Called with the key's lock held, after the write itself, so a sequence number is only handed out for a write already made.
+    fn append(&self, tree: Tree, key: &[u8], value: Option<&[u8]>) {
+        let seq = self.head.fetch_add(1, Ordering::SeqCst) + 1;
+        let op = ReplOp {
+            tree: tree.name().to_owned(),
+            key: key.to_vec(),
+            value: value.map(|v| v.to_vec()),
+        };
+        self.inner.insert(Tree::ReplLog, &encode_seq(seq), &serialize(&op).unwrap());
+        // Dropping one entry for every one added keeps the log at `retain` entries, and keeps its sequence numbers contiguous.
+        if seq > self.retain {
+            self.inner.remove(Tree::ReplLog, &encode_seq(seq - self.retain));
+        }
+    }
+}
+
+impl Storage for LoggedStorage {
+    fn get(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(tree, key)
+    }
+    fn insert(&self, tree: Tree, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        if !is_replicated(tree) {
+            return self.inner.insert(tree, key, value);
+        }
+        let _lock = self.lock(tree, key);
+        let old = self.inner.insert(tree, key, value);
+        self.append(tree, key, Some(value));
+        old
+    }
+    fn remove(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        if !is_replicated(tree) {
+            return self.inner.remove(tree, key);
+        }
+        let _lock = self.lock(tree, key);
+        let old = self.inner.remove(tree, key);
+        if old.is_some() {
+            self.append(tree, key, None);
+        }
+        old
+    }
+    fn range_from<'a>(&'a self, tree: Tree, start: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        self.inner.range_from(tree, start)
+    }
+    fn apply_batch(&self, tree: Tree, ops: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        if !is_replicated(tree) {
+            return self.inner.apply_batch(tree, ops);
+        }
+        // Taking the locks in order keeps two batches from each waiting on a lock the other holds.
+        let mut stripes = ops.iter().map(|(key, _)| self.stripe(tree, key)).collect::<Vec<_>>();
+        stripes.sort_unstable();
+        stripes.dedup();
+        let _locks = stripes.into_iter().map(|i| self.stripes[i].lock().unwrap()).collect::<Vec<_>>();
+        self.inner.apply_batch(tree, ops.clone());
+        for (key, value) in &ops {
+            self.append(tree, key, value.as_deref());
+        }
+    }
+    fn fetch_and_update(&self, tree: Tree, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>) -> Option<Vec<u8>> {
+        if !is_replicated(tree) {
+            return self.inner.fetch_and_update(tree, key, f);
+        }
+        let _lock = self.lock(tree, key);
+        // `f` may be called more than once. The value stored is whatever the last call produced.
+        let mut new = None;
+        let old = self.inner.fetch_and_update(tree, key, &mut |old| {
+            new = f(old);
+            new.clone()
+        });
+        if old.is_some() || new.is_some() {
+            self.append(tree, key, new.as_deref());
+        }
+        old
+    }
+    fn compare_and_swap(&self, tree: Tree, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> bool {
+        if !is_replicated(tree) {
+            return self.inner.compare_and_swap(tree, key, old, new);
+        }
+        let _lock = self.lock(tree, key);
+        let swapped = self.inner.compare_and_swap(tree, key, old, new);
+        if swapped {
+            self.append(tree, key, new);
+        }
+        swapped
+    }
+    fn flush(&self) -> usize {
+        self.inner.flush()
+    }
+    fn watch(&self) -> Box<dyn Iterator<Item = StorageEvent> + Send> {
+        self.inner.watch()
+    }
+}
+
+fn is_replicated(tree: Tree) -> bool {
+    Tree::REPLICATED.contains(&tree)
+}
+fn encode_seq(seq: u64) -> [u8; 8] {
+    seq.to_be_bytes()
+}
+fn decode_seq(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+/// The sequence number of the oldest write still in the log, if any.
+fn first_seq(storage: &dyn Storage) -> Option<u64> {
+    storage.range_from(Tree::ReplLog, &[]).next().map(|(key, _)| decode_seq(&key))
+}
+/// Finds the sequence number of the latest write in the log, `0` if there is none.
+/// The log's sequence numbers are contiguous, so this searches for the end of the run starting at the oldest one.
+fn find_head(storage: &dyn Storage) -> u64 {
+    let mut head = match first_seq(storage) {
+        Some(seq) => seq,
+        None => return 0,
+    };
+    let exists = |seq: u64| storage.get(Tree::ReplLog, &encode_seq(seq)).is_some();
+    // Gallop until past the end, then narrow back down. `head` always exists and `head + step` never does.
+    let mut step = 1;
+    while exists(head + step) {
+        head += step;
+        step *= 2;
+    }
+    while step > 1 {
+        step /= 2;
+        if exists(head + step) {
+            head += step;
+        }
+    }
+    head
+}
+
+/// Drops the log up to its last gap, left by a crash between a write getting its sequence number and its log entry going in.
+/// The gap would never fill, so replicas waiting on it are sent to start over from a snapshot instead.
+fn trim_to_last_run(storage: &dyn Storage) {
+    let mut expected = None;
+    let mut last_gap = None;
+    for (key, _) in storage.range_from(Tree::ReplLog, &[]) {
+        let seq = decode_seq(&key);
+        if expected.map_or(false, |e| seq != e) {
+            last_gap = Some(seq);
+        }
+        expected = Some(seq + 1);
+    }
+    if let Some(gap_end) = last_gap {
+        warn!("The replication log is missing writes before #{}. Replicas behind it will start over from a snapshot.", gap_end);
+        let stale = storage.range_from(Tree::ReplLog, &[])
+            .take_while(|(key, _)| decode_seq(key) < gap_end)
+            .map(|(key, _)| (key, None))
+            .collect::<Vec<_>>();
+        storage.apply_batch(Tree::ReplLog, stale);
+    }
+}
+
+/// Serves the replication log and snapshots of the store to replicas.
+pub struct ReplicationServer {
+    /// ZMQ rep socket for handling incoming requests.
+    rep_socket: Socket,
+    store: KvStore,
+}
+
+impl ReplicationServer {
+    pub fn new(rep_socket: Socket, store: KvStore) -> Self {
+        Self { rep_socket, store }
+    }
+    fn handle(&self) {
+        let request_bytes = self.rep_socket.recv_bytes(0)
+        .expect("Expected to be able to receive a replication request!");
+        let reply = match deserialize::<Replica2Primary>(&request_bytes) {
+            Ok(request) => self.handle_request(request),
+            Err(_) => {
+                warn!("Got bytes on the replication socket that could not be deserialized as Replica2Primary.");
+                Primary2Replica::Error("Malformed request.".to_owned())
+            }
+        };
+        self.rep_socket.send(serialize(&reply).unwrap(), 0)
+        .expect("Expected to send a reply back to the replica.");
+    }
+    fn handle_request(&self, request: Replica2Primary) -> Primary2Replica {
+        let storage = self.store.storage();
+        match request {
+            Replica2Primary::Head => Primary2Replica::Head(find_head(&**storage)),
+            Replica2Primary::Pull(after, limit) => {
+                trace!("A replica asked for writes after #{}.", after);
+                match first_seq(&**storage) {
+                    None if after > 0 => return Primary2Replica::Behind,
+                    // Writes right after `after` were dropped from the log.
+                    Some(first) if after + 1 < first => return Primary2Replica::Behind,
+                    // The replica claims to have seen writes this log never had, so it must have followed some other primary.
+                    Some(first) if after >= first && storage.get(Tree::ReplLog, &encode_seq(after)).is_none() => return Primary2Replica::Behind,
+                    _ => (),
+                }
+                // Writes are logged in parallel, so a later one can be in before an earlier one. Stopping at the first gap
+                // leaves the rest for the next pull, once the earlier write is in.
+                let ops = storage.range_from(Tree::ReplLog, &encode_seq(after + 1))
+                    .take(limit.min(REPL_BATCH_SIZE) as usize)
+                    .map(|(key, value)| (decode_seq(&key), value))
+                    .zip(after + 1..)
+                    .take_while(|((seq, _), expected)| seq == expected)
+                    .map(|((seq, value), _)| (seq, deserialize::<ReplOp>(&value).unwrap()))
+                    .collect();
+                Primary2Replica::Ops(ops)
+            },
+            Replica2Primary::Snapshot(tree_name, after, limit) => {
+                let tree = match Tree::from_name(&tree_name) {
+                    Some(t) if is_replicated(t) => t,
+                    _ => return Primary2Replica::Error(format!("There is no replicated tree named {:?}.", &tree_name)),
+                };
+                // The replica resumes after the last key it got, and nothing can sort between that key and itself plus a zero byte.
+                let start = match after {
+                    Some(mut key) => {
+                        key.push(0);
+                        key
+                    },
+                    None => Vec::new(),
+                };
+                let pairs = storage.range_from(tree, &start)
+                    .take(limit.min(REPL_BATCH_SIZE) as usize)
+                    .collect();
+                Primary2Replica::Pairs(pairs)
+            }
+        }
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_repl_server".to_owned())
+            .spawn(move || {
+                let server = self;
+                loop {
+                    server.handle();
+                }
+            })
+            .expect("Could not launch the K/V replication server thread!")
+    }
+}
+
+/// Keeps a read-only store up to date with a primary by pulling its replication log, until the store gets promoted.
+pub struct Replicator {
+    req_socket: Socket,
+    store: KvStore,
+    poll_interval: Duration,
+}
+
+impl Replicator {
+    pub fn new(zmq_ctx: &Context, primary_endpoint: &str, store: KvStore, poll_interval: Duration) -> Self {
+        let req_socket = timed_req_socket(zmq_ctx, REPL_TIMEOUT);
+        req_socket.connect(primary_endpoint).expect("Could not connect to the primary K/V store!");
+        Self {
+            req_socket,
+            store,
+            poll_interval,
+        }
+    }
+    fn request(&self, request: Replica2Primary) -> Result<Primary2Replica, String> {
+        self.req_socket.send(serialize(&request).unwrap(), 0)
+        .map_err(|e| format!("Could not send a request to the primary: {}", e))?;
+        let reply_bytes = self.req_socket.recv_bytes(0)
+        .map_err(|e| format!("The primary did not answer: {}", e))?;
+        match deserialize::<Primary2Replica>(&reply_bytes) {
+            Ok(Primary2Replica::Error(e)) => Err(format!("The primary returned an error: {}", e)),
+            Ok(reply) => Ok(reply),
+            Err(_) => Err("Got a reply from the primary that could not be deserialized to a Primary2Replica.".to_owned()),
+        }
+    }
+    /// Applies the next batch of writes, starting over from a snapshot if need be. Returns whether there were any.
+    fn catch_up(&self) -> Result<bool, String> {
+        let position = match position(&self.store) {
+            Some(p) => p,
+            None => {
+                self.resync()?;
+                return Ok(true);
+            }
+        };
+        match self.request(Replica2Primary::Pull(position, REPL_BATCH_SIZE))? {
+            Primary2Replica::Ops(ops) if ops.is_empty() => Ok(false),
+            Primary2Replica::Ops(ops) => apply_ops(&self.store, ops),
+            Primary2Replica::Behind => {
+                warn!("The primary no longer has the writes after #{}. Starting over from a snapshot.", position);
+                self.resync()?;
+                Ok(true)
+            },
+            other => Err(format!("Expected writes from the primary, got {:?}.", other)),
+        }
+    }
+    /// Replaces everything in the replicated trees with a copy of the primary's, then carries on from where its log was when the copy began.
+    /// Writes made on the primary during the copy are applied again afterwards, which is harmless, since every logged write carries the whole new value.
+    fn resync(&self) -> Result<(), String> {
+        let head = match self.request(Replica2Primary::Head)? {
+            Primary2Replica::Head(h) => h,
+            other => return Err(format!("Expected the head of the primary's log, got {:?}.", other)),
+        };
+        info!("Copying everything from the primary, whose log is at #{}.", head);
+        let storage = self.store.storage();
+        // As with single writes, a promotion partway through stops the copy, leaving whatever was copied so far.
+        let still_following = || {
+            let lock = self.store.lock_replication();
+            match self.store.is_read_only() {
+                true => Some(lock),
+                false => None,
+            }
+        };
+        match still_following() {
+            Some(_lock) => storage.remove(Tree::ReplState, POSITION_KEY),
+            None => return Ok(()),
+        };
+        for tree in Tree::REPLICATED.iter().copied() {
+            match still_following() {
+                Some(_lock) => clear(&**storage, tree),
+                None => return Ok(()),
+            }
+            let mut after = None;
+            loop {
+                let pairs = match self.request(Replica2Primary::Snapshot(tree.name().to_owned(), after.clone(), REPL_BATCH_SIZE))? {
+                    Primary2Replica::Pairs(p) => p,
+                    other => return Err(format!("Expected pairs from the primary, got {:?}.", other)),
+                };
+                if pairs.is_empty() {
+                    break;
+                }
+                after = pairs.last().map(|(key, _)| key.clone());
+                match still_following() {
+                    Some(_lock) => storage.apply_batch(tree, pairs.into_iter().map(|(key, value)| (key, Some(value))).collect()),
+                    None => return Ok(()),
+                }
+            }
+        }
+        match still_following() {
+            Some(_lock) => storage.insert(Tree::ReplState, POSITION_KEY, &encode_seq(head)),
+            None => return Ok(()),
+        };
+        info!("Finished copying from the primary.");
+        Ok(())
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_replicator".to_owned())
+            .spawn(move || {
+                let replicator = self;
+                while replicator.store.is_read_only() {
+                    match replicator.catch_up() {
+                        Ok(true) => (),
+                        Ok(false) => std::thread::sleep(replicator.poll_interval),
+                        Err(e) => {
+                            warn!("Replication failed: {} Retrying.", e);
+                            std::thread::sleep(replicator.poll_interval);
+                        }
+                    }
+                }
+                info!("The K/V store was promoted. No longer following the primary.");
+            })
+            .expect("Could not launch the K/V replicator thread!")
+    }
+}
+
+/// Applies writes pulled from the primary and records how far they went. Returns whether the store is still following the primary.
+/// A promotion partway through stops the rest from being applied: the store is then a primary of its own, and writes to it must not be overwritten.
+fn apply_ops(store: &KvStore, ops: Vec<(u64, ReplOp)>) -> Result<bool, String> {
+    let storage = store.storage();
+    for (seq, op) in ops {
+        let _lock = store.lock_replication();
+        // A promotion has already forgotten the old primary, and that should stick.
+        if !store.is_read_only() {
+            return Ok(false);
+        }
+        apply(&**storage, op)?;
+        storage.insert(Tree::ReplState, POSITION_KEY, &encode_seq(seq));
+        trace!("Caught up with the primary to #{}.", seq);
+    }
+    Ok(true)
+}
+fn apply(storage: &dyn Storage, op: ReplOp) -> Result<(), String> {
+    let tree = match Tree::from_name(&op.tree) {
+        Some(t) if is_replicated(t) => t,
+        _ => return Err(format!("The primary logged a write to {:?}, which is not a replicated tree.", &op.tree)),
+    };
+    match op.value {
+        Some(value) => storage.insert(tree, &op.key, &value),
+        None => storage.remove(tree, &op.key),
+    };
+    Ok(())
+}
+/// Removes everything from a tree.
+fn clear(storage: &dyn Storage, tree: Tree) {
+    loop {
+        let keys = storage.range_from(tree, &[])
+            .take(REPL_BATCH_SIZE as usize)
+            .map(|(key, _)| (key, None))
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            break;
+        }
+        storage.apply_batch(tree, keys);
+    }
+}
+/// How far into its primary's log a replica has caught up, if it has started following one.
+pub(crate) fn position(store: &KvStore) -> Option<u64> {
+    store.storage().get(Tree::ReplState, POSITION_KEY).map(|bytes| decode_seq(&bytes))
+}
+/// The sequence number of the latest write in the store's own log.
+pub(crate) fn head(store: &KvStore) -> u64 {
+    find_head(&**store.storage())
+}
+/// Makes the store writable and forgets its place in the old primary's log, so that following any primary again starts from a snapshot.
+/// The old primary is remembered, so the store stays promoted if restarted with it still configured as the one to follow.
+pub(crate) fn promote(store: &KvStore) {
+    let _lock = store.lock_replication();
+    let storage = store.storage();
+    storage.remove(Tree::ReplState, POSITION_KEY);
+    let primary = storage.get(Tree::ReplState, PRIMARY_KEY).unwrap_or_default();
+    storage.insert(Tree::ReplState, PROMOTED_FROM_KEY, &primary);
+    storage.flush();
+    store.set_read_only(false);
+}
+/// Whether a store configured to follow the given primary should do so, rather than stay the primary it was promoted to.
+fn should_follow(store: &KvStore, primary_endpoint: &str) -> bool {
+    let storage = store.storage();
+    if let Some(promoted_from) = storage.get(Tree::ReplState, PROMOTED_FROM_KEY) {
+        if promoted_from == primary_endpoint.as_bytes() {
+            return false;
+        }
+        info!("This K/V store was once promoted, but is now set to follow another primary.");
+        storage.remove(Tree::ReplState, PROMOTED_FROM_KEY);
+    }
+    storage.insert(Tree::ReplState, PRIMARY_KEY, primary_endpoint.as_bytes());
+    true
+}
+
+/// Sets up replication as configured: serving the store's log to replicas on `ZHUR_KV_REPL_ENDPOINT`, unless the log is disabled,
+/// and, for replicas, making the store read-only and following the primary.
+pub fn start_replication(zmq_ctx: &Context, config: &KvConfig, store: &KvStore) {
+    if let Role::Replica(primary_endpoint) = &config.role {
+        if should_follow(store, primary_endpoint) {
+            info!("Running as a read-only replica of {}.", primary_endpoint);
+            store.set_read_only(true);
+            Replicator::new(zmq_ctx, primary_endpoint, store.clone(), config.repl_poll_interval).run_as_thread();
+        } else {
+            warn!("This K/V store was promoted away from {}, so it stays a primary. Set ZHUR_KV_PRIMARY_ENDPOINT to another primary to follow that one instead.", primary_endpoint);
+        }
+    }
+    if config.repl_log_size == 0 {
+        return;
+    }
+    let repl_socket = zmq_ctx.socket(SocketType::REP).unwrap();
+    let repl_endpoint = match std::env::var("ZHUR_KV_REPL_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_KV_REPL_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_REPL_ENDPOINT);
+            DEFAULT_KV_REPL_ENDPOINT.to_string()
+        }
+    };
+    repl_socket.bind(&repl_endpoint).expect("Could not bind the K/V replication socket!");
+    ReplicationServer::new(repl_socket, store.clone()).run_as_thread();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use zhur_common::msg::core_kv::Durability;
+    use crate::storage::SledStorage;
+    use super::*;
+
+    fn temp_storage() -> SharedStorage {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        Arc::new(SledStorage::new(db))
+    }
+    fn logged_seqs(storage: &dyn Storage) -> Vec<u64> {
+        storage.range_from(Tree::ReplLog, &[]).map(|(key, _)| decode_seq(&key)).collect()
+    }
+    fn op(key: &str, value: &str) -> ReplOp {
+        ReplOp { tree: Tree::Data.name().to_owned(), key: key.as_bytes().to_vec(), value: Some(value.as_bytes().to_vec()) }
+    }
+
+    #[test]
+    fn concurrent_writes_are_all_logged_contiguously() {
+        let inner = temp_storage();
+        let logged = Arc::new(LoggedStorage::new(inner.clone(), 10_000));
+        let writers = (0..8).map(|t| {
+            let logged = logged.clone();
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    logged.insert(Tree::Data, format!("{}:{}", t, i).as_bytes(), b"v");
+                }
+            })
+        }).collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(logged_seqs(&*inner), (1..=800).collect::<Vec<_>>());
+        assert_eq!(find_head(&*inner), 800);
+    }
+    #[test]
+    fn writes_to_the_same_key_are_logged_in_order() {
+        let inner = temp_storage();
+        let logged = LoggedStorage::new(inner.clone(), 100);
+        logged.insert(Tree::Data, b"k", b"1");
+        logged.insert(Tree::Data, b"k", b"2");
+        logged.remove(Tree::Data, b"k");
+        let values = inner.range_from(Tree::ReplLog, &[])
+            .map(|(_, value)| deserialize::<ReplOp>(&value).unwrap().value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![Some(b"1".to_vec()), Some(b"2".to_vec()), None]);
+    }
+    #[test]
+    fn a_gap_left_by_a_crash_is_trimmed_on_startup() {
+        let inner = temp_storage();
+        for seq in &[1u64, 2, 3, 5, 6] {
+            inner.insert(Tree::ReplLog, &encode_seq(*seq), &serialize(&op("k", "v")).unwrap());
+        }
+        let logged = LoggedStorage::new(inner.clone(), 100);
+        assert_eq!(logged_seqs(&*inner), vec![5, 6]);
+        logged.insert(Tree::Data, b"k", b"v");
+        assert_eq!(logged_seqs(&*inner), vec![5, 6, 7]);
+    }
+    #[test]
+    fn pulls_stop_at_writes_still_on_their_way_in() {
+        let storage = temp_storage();
+        for seq in &[1u64, 2, 4] {
+            storage.insert(Tree::ReplLog, &encode_seq(*seq), &serialize(&op("k", "v")).unwrap());
+        }
+        let zmq_ctx = Context::new();
+        let server = ReplicationServer::new(zmq_ctx.socket(SocketType::REP).unwrap(), KvStore::new(storage, Durability::Async));
+        match server.handle_request(Replica2Primary::Pull(0, 10)) {
+            Primary2Replica::Ops(ops) => assert_eq!(ops.into_iter().map(|(seq, _)| seq).collect::<Vec<_>>(), vec![1, 2]),
+            other => panic!("Expected writes, got {:?}", other),
+        }
+    }
+    #[test]
+    fn writes_from_the_primary_stop_once_promoted() {
+        let store = KvStore::new(temp_storage(), Durability::Async);
+        store.set_read_only(true);
+        assert_eq!(apply_ops(&store, vec![(1, op("a", "1"))]), Ok(true));
+        assert_eq!(position(&store), Some(1));
+        promote(&store);
+        assert_eq!(apply_ops(&store, vec![(2, op("a", "2")), (3, op("b", "3"))]), Ok(false));
+        assert_eq!(store.storage().get(Tree::Data, b"a"), Some(b"1".to_vec()));
+        assert_eq!(store.storage().get(Tree::Data, b"b"), None);
+        assert_eq!(position(&store), None);
+    }
+    #[test]
+    fn promotion_lasts_until_another_primary_is_set() {
+        let store = KvStore::new(temp_storage(), Durability::Async);
+        assert!(should_follow(&store, "tcp://primary-a"));
+        promote(&store);
+        assert!(!should_follow(&store, "tcp://primary-a"));
+        assert!(should_follow(&store, "tcp://primary-b"));
+        assert!(should_follow(&store, "tcp://primary-b"));
+    }
+}
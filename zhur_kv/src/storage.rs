@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+/// The sled implementation of `Storage`.
+mod sled_backend;
+pub use sled_backend::SledStorage;
+/// The SQLite implementation of `Storage`.
+mod sqlite_backend;
+pub use sqlite_backend::SqliteStorage;
+
+/// The separate keyspaces the K/V store keeps its data in. Keys in one never clash with keys in another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tree {
+    /// Stored values, keyed by `owner:table:key`.
+    Data,
+    /// Expiry timestamps of the values set with a TTL, keyed like the values themselves.
+    Expiry,
+    /// Secondary index definitions.
+    IndexDefs,
+    /// Secondary index entries.
+    IndexEntries,
+    /// The replication log, keyed by big-endian sequence number. Not itself replicated.
+    ReplLog,
+    /// Bookkeeping of a replica, such as how far into its primary's log it has caught up. Not itself replicated.
+    ReplState,
+}
+
+impl Tree {
+    pub const ALL: [Tree; 6] = [Tree::Data, Tree::Expiry, Tree::IndexDefs, Tree::IndexEntries, Tree::ReplLog, Tree::ReplState];
+    /// The trees whose contents a replica copies from its primary.
+    pub const REPLICATED: [Tree; 4] = [Tree::Data, Tree::Expiry, Tree::IndexDefs, Tree::IndexEntries];
+    /// A name for the tree, usable as a sled tree name or an SQL table name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Tree::Data => "data",
+            Tree::Expiry => "expiry",
+            Tree::IndexDefs => "index_defs",
+            Tree::IndexEntries => "index_entries",
+            Tree::ReplLog => "repl_log",
+            Tree::ReplState => "repl_state",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Tree> {
+        Tree::ALL.iter().copied().find(|tree| tree.name() == name)
+    }
+}
+
+/// A change made to `Tree::Data`, as seen by `Storage::watch`.
+#[derive(Clone, Debug)]
+pub enum StorageEvent {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+/// An ordered key/value storage engine the `KvStore` keeps its trees in.
+/// Keys are compared bytewise. Every method is atomic with respect to the others, and storage errors are treated as fatal.
+pub trait Storage: Send + Sync {
+    fn get(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>>;
+    /// Sets a key, returning its old value.
+    fn insert(&self, tree: Tree, key: &[u8], value: &[u8]) -> Option<Vec<u8>>;
+    /// Removes a key, returning its old value.
+    fn remove(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>>;
+    /// Iterates over the pairs whose keys are greater than or equal to `start`, in key order.
+    /// The iterator need not reflect writes made while it is in use.
+    fn range_from<'a>(&'a self, tree: Tree, start: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+    /// Applies several sets (`Some`) and removals (`None`) to a tree, all or nothing.
+    fn apply_batch(&self, tree: Tree, ops: Vec<(Vec<u8>, Option<Vec<u8>>)>);
+    /// Replaces a key's value with `f` of its old value, `None` meaning absent, returning the old value.
+    fn fetch_and_update(&self, tree: Tree, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>) -> Option<Vec<u8>>;
+    /// Replaces a key's value with `new` only if it currently is `old`, `None` meaning absent. Returns whether it did.
+    fn compare_and_swap(&self, tree: Tree, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> bool;
+    /// Makes every write so far durable, returning how many bytes that took writing if the engine knows.
+    fn flush(&self) -> usize;
+    /// Subscribes to every change made to `Tree::Data` from now on.
+    fn watch(&self) -> Box<dyn Iterator<Item = StorageEvent> + Send>;
+    /// Iterates over the pairs whose keys start with `prefix`, in key order.
+    fn scan_prefix<'a>(&'a self, tree: Tree, prefix: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        let prefix = prefix.to_vec();
+        let range = self.range_from(tree, &prefix);
+        Box::new(range.take_while(move |(key, _)| key.starts_with(&prefix)))
+    }
+}
+
+/// Shared handle to whichever `Storage` the K/V store was configured with.
+pub type SharedStorage = Arc<dyn Storage>;
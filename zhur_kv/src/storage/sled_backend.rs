@@ -0,0 +1,81 @@
+use sled::{Batch, Db, Event};
+use super::{Storage, StorageEvent, Tree};
+
+/// Keeps the K/V store's trees in a sled database. `Tree::Data` is sled's default tree, so databases from before there was a choice of storage still open.
+pub struct SledStorage {
+    db: Db,
+    expiry: sled::Tree,
+    index_defs: sled::Tree,
+    index_entries: sled::Tree,
+    repl_log: sled::Tree,
+    repl_state: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn new(db: Db) -> Self {
+        let open = |tree: Tree| db.open_tree(tree.name())
+            .unwrap_or_else(|e| panic!("Expected to be able to open the {} tree: {}", tree.name(), e));
+        Self {
+            expiry: open(Tree::Expiry),
+            index_defs: open(Tree::IndexDefs),
+            index_entries: open(Tree::IndexEntries),
+            repl_log: open(Tree::ReplLog),
+            repl_state: open(Tree::ReplState),
+            db,
+        }
+    }
+    fn tree(&self, tree: Tree) -> &sled::Tree {
+        match tree {
+            // `Db` derefs to its default tree.
+            Tree::Data => &self.db,
+            Tree::Expiry => &self.expiry,
+            Tree::IndexDefs => &self.index_defs,
+            Tree::IndexEntries => &self.index_entries,
+            Tree::ReplLog => &self.repl_log,
+            Tree::ReplState => &self.repl_state,
+        }
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree(tree).get(key).unwrap().map(|v| v.to_vec())
+    }
+    fn insert(&self, tree: Tree, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.tree(tree).insert(key, value).unwrap().map(|v| v.to_vec())
+    }
+    fn remove(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree(tree).remove(key).unwrap().map(|v| v.to_vec())
+    }
+    fn range_from<'a>(&'a self, tree: Tree, start: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        Box::new(self.tree(tree).range(start.to_vec()..).map(|pair| {
+            let (key, value) = pair.unwrap();
+            (key.to_vec(), value.to_vec())
+        }))
+    }
+    fn apply_batch(&self, tree: Tree, ops: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let mut batch = Batch::default();
+        for (key, value) in ops {
+            match value {
+                Some(v) => batch.insert(key, v),
+                None => batch.remove(key),
+            }
+        }
+        self.tree(tree).apply_batch(batch).unwrap();
+    }
+    fn fetch_and_update(&self, tree: Tree, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>) -> Option<Vec<u8>> {
+        self.tree(tree).fetch_and_update(key, |old| f(old)).unwrap().map(|v| v.to_vec())
+    }
+    fn compare_and_swap(&self, tree: Tree, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> bool {
+        self.tree(tree).compare_and_swap(key, old, new).unwrap().is_ok()
+    }
+    fn flush(&self) -> usize {
+        self.db.flush().expect("Could not flush the sled database!")
+    }
+    fn watch(&self) -> Box<dyn Iterator<Item = StorageEvent> + Send> {
+        Box::new(self.db.watch_prefix(vec![]).map(|event| match event {
+            Event::Insert { key, value } => StorageEvent::Insert(key.to_vec(), value.to_vec()),
+            Event::Remove { key } => StorageEvent::Remove(key.to_vec()),
+        }))
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use zhur_common::flume::{unbounded, Sender};
+use super::{Storage, StorageEvent, Tree};
+
+/// How many pairs a range iterator reads from SQLite at a time.
+const PAGE_SIZE: i64 = 256;
+
+/// Keeps the K/V store's trees in an SQLite database, one `key BLOB PRIMARY KEY, value BLOB` table per tree,
+/// so the data can be looked at with the `sqlite3` shell and other standard tools.
+/// The database runs in WAL mode. Writes survive the process crashing right away and a power loss once flushed.
+pub struct SqliteStorage {
+    /// Connections can't be shared between threads, so every operation takes its turn with this one.
+    conn: Mutex<Connection>,
+    /// Everyone watching `Tree::Data`. Disconnected watchers get dropped on the next write.
+    watchers: Mutex<Vec<Sender<StorageEvent>>>,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the database at `path`, creating any missing tables.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path)
+        .map_err(|e| format!("Could not open the SQLite database at {:?}: {}", path, e))?;
+        // `PRAGMA journal_mode` returns the mode it ended up in, so it has to be run as a query.
+        conn.query_row("PRAGMA journal_mode = WAL", params![], |_| Ok(()))
+        .map_err(|e| format!("Could not switch {:?} to WAL mode: {}", path, e))?;
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")
+        .map_err(|e| format!("Could not configure {:?}: {}", path, e))?;
+        for tree in Tree::ALL.iter() {
+            let sql = format!("CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, value BLOB NOT NULL) WITHOUT ROWID", tree.name());
+            conn.execute(&sql, params![])
+            .map_err(|e| format!("Could not create the {} table in {:?}: {}", tree.name(), path, e))?;
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+            watchers: Mutex::new(Vec::new()),
+        })
+    }
+    /// Passes events on to the watchers. Called with the connection locked, so events arrive in the order they were committed in.
+    fn notify(&self, tree: Tree, events: Vec<StorageEvent>) {
+        if tree != Tree::Data || events.is_empty() {
+            return;
+        }
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| events.iter().all(|event| watcher.send(event.clone()).is_ok()));
+    }
+    /// Reads up to `PAGE_SIZE` pairs starting at `start`, or right after it if `inclusive` is false.
+    fn page(&self, tree: Tree, start: &[u8], inclusive: bool) -> VecDeque<(Vec<u8>, Vec<u8>)> {
+        let op = if inclusive { ">=" } else { ">" };
+        let sql = format!("SELECT key, value FROM {} WHERE key {} ?1 ORDER BY key LIMIT ?2", tree.name(), op);
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(&sql).unwrap();
+        let page = stmt.query_map(params![start, PAGE_SIZE], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+        page
+    }
+}
+
+fn read_value(conn: &Connection, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+    let sql = format!("SELECT value FROM {} WHERE key = ?1", tree.name());
+    conn.query_row(&sql, params![key], |row| row.get(0)).optional().unwrap()
+}
+fn write_value(conn: &Connection, tree: Tree, key: &[u8], value: Option<&[u8]>) {
+    match value {
+        Some(v) => {
+            let sql = format!("INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)", tree.name());
+            conn.execute(&sql, params![key, v]).unwrap();
+        },
+        None => {
+            let sql = format!("DELETE FROM {} WHERE key = ?1", tree.name());
+            conn.execute(&sql, params![key]).unwrap();
+        }
+    }
+}
+fn event(key: &[u8], value: Option<&[u8]>) -> StorageEvent {
+    match value {
+        Some(v) => StorageEvent::Insert(key.to_vec(), v.to_vec()),
+        None => StorageEvent::Remove(key.to_vec()),
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        let conn = self.conn.lock().unwrap();
+        read_value(&conn, tree, key)
+    }
+    fn insert(&self, tree: Tree, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.fetch_and_update(tree, key, &mut |_| Some(value.to_vec()))
+    }
+    fn remove(&self, tree: Tree, key: &[u8]) -> Option<Vec<u8>> {
+        self.fetch_and_update(tree, key, &mut |_| None)
+    }
+    fn range_from<'a>(&'a self, tree: Tree, start: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        let mut buffer = self.page(tree, start, true);
+        Box::new(std::iter::from_fn(move || {
+            if buffer.len() == 1 {
+                // Refill before handing out the last pair, using its key as the starting point of the next page.
+                let last = buffer.pop_front().unwrap();
+                buffer = self.page(tree, &last.0, false);
+                return Some(last);
+            }
+            buffer.pop_front()
+        }))
+    }
+    fn apply_batch(&self, tree: Tree, ops: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().unwrap();
+        for (key, value) in &ops {
+            write_value(&tx, tree, key, value.as_deref());
+        }
+        tx.commit().unwrap();
+        self.notify(tree, ops.iter().map(|(key, value)| event(key, value.as_deref())).collect());
+    }
+    fn fetch_and_update(&self, tree: Tree, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().unwrap();
+        let old = read_value(&tx, tree, key);
+        let new = f(old.as_deref());
+        write_value(&tx, tree, key, new.as_deref());
+        tx.commit().unwrap();
+        if old.is_some() || new.is_some() {
+            self.notify(tree, vec![event(key, new.as_deref())]);
+        }
+        old
+    }
+    fn compare_and_swap(&self, tree: Tree, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> bool {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().unwrap();
+        if read_value(&tx, tree, key).as_deref() != old {
+            return false;
+        }
+        write_value(&tx, tree, key, new);
+        tx.commit().unwrap();
+        self.notify(tree, vec![event(key, new)]);
+        true
+    }
+    fn flush(&self) -> usize {
+        // With `synchronous = NORMAL`, the WAL gets synced to disk before every checkpoint.
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", params![], |_| Ok(()))
+        .expect("Could not checkpoint the SQLite database!");
+        0
+    }
+    fn watch(&self) -> Box<dyn Iterator<Item = StorageEvent> + Send> {
+        let (tx, rx) = unbounded();
+        self.watchers.lock().unwrap().push(tx);
+        Box::new(rx.into_iter())
+    }
+}
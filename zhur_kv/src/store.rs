@@ -0,0 +1,450 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::{Core2Kv, Durability, Kv2Core};
+use crate::durability::GroupCommit;
+use crate::index::Indexes;
+use crate::storage::{SharedStorage, StorageEvent, Tree};
+
+/// Handle to the K/V database. Cheap to clone, so it can be shared between the request loop and background threads.
+#[derive(Clone)]
+pub struct KvStore {
+    /// The storage engine. Values live in `Tree::Data` under `owner:table:key`.
+    /// `Tree::Expiry` maps the full keys of entries set with a TTL to the moment they expire, in seconds since the Unix epoch (big-endian).
+    storage: SharedStorage,
+    /// Secondary indexes, kept up to date on every write.
+    indexes: Indexes,
+    /// How writes asking for `Durability::Default` are acknowledged. Never `Durability::Default` itself.
+    default_durability: Durability,
+    /// Writers waiting for the `GroupCommitter` to flush.
+    group_commit: Arc<GroupCommit>,
+    /// Whether the store is a replica, refusing writes other than the ones replicated from its primary.
+    read_only: Arc<AtomicBool>,
+    /// Held while a replicated write is applied, and while the store is promoted, so a promotion never lands in the middle of one.
+    replication: Arc<Mutex<()>>,
+}
+
+impl KvStore {
+    /// Opens the store. Writes made with `Durability::GroupCommit` only get acknowledged while a `GroupCommitter` is running for it.
+    pub fn new(storage: SharedStorage, default_durability: Durability) -> Self {
+        let indexes = Indexes::new(storage.clone());
+        let default_durability = match default_durability {
+            Durability::Default => Durability::Async,
+            d => d,
+        };
+        Self {
+            storage,
+            indexes,
+            default_durability,
+            group_commit: Arc::new(GroupCommit::default()),
+            read_only: Arc::new(AtomicBool::new(false)),
+            replication: Arc::new(Mutex::new(())),
+        }
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+    /// Locks out promotion, or replicated writes, until the guard is dropped.
+    pub(crate) fn lock_replication(&self) -> MutexGuard<'_, ()> {
+        self.replication.lock().unwrap()
+    }
+    /// Makes the store refuse or take writes, for every handle to it.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+    /// Carries out a `Core2Kv` request and produces the reply to it.
+    pub fn handle(&self, request: Core2Kv) -> Kv2Core {
+        if request.is_write() && self.is_read_only() {
+            debug!("Refusing a write, as this store is a read-only replica.");
+            return Kv2Core::ReadOnly;
+        }
+        if let Some(name) = request.invalid_name() {
+            debug!("Refusing a request naming {:?}, as table and index names cannot contain colons.", name);
+            return Kv2Core::InvalidName(name.to_owned());
+        }
+        match request {
+            Core2Kv::KvGet(owner, table, key) => {
+                let full_key = full_key(&owner, &table, &key);
+                trace!("Got a request to get {}", &full_key);
+                Kv2Core::Value(self.get(&full_key))
+            },
+            Core2Kv::KvSet(owner, table, key, value, ttl, durability) => {
+                let full_key = full_key(&owner, &table, &key);
+                trace!("Got a request to set {}", &full_key);
+                self.set(&full_key, value, ttl);
+                self.commit(durability);
+                Kv2Core::OperationSuccessful
+            },
+            Core2Kv::KvDel(owner, table, key, durability) => {
+                let full_key = full_key(&owner, &table, &key);
+                trace!("Got a request to delete {}", &full_key);
+                self.remove(&full_key);
+                self.commit(durability);
+                Kv2Core::OperationSuccessful
+            },
+            Core2Kv::KvGetMany(owner, table, keys) => {
+                trace!("Got a request to get {} keys from {}:{}", keys.len(), &owner, &table);
+                let values = keys.iter()
+                .map(|key| self.get(&full_key(&owner, &table, key)))
+                .collect();
+                Kv2Core::Values(values)
+            },
+            Core2Kv::KvSetMany(owner, table, pairs, durability) => {
+                trace!("Got a request to set {} keys in {}:{}", pairs.len(), &owner, &table);
+                let pairs = pairs.into_iter()
+                .map(|(key, value)| (full_key(&owner, &table, &key), value))
+                .collect();
+                self.set_many(pairs);
+                self.commit(durability);
+                Kv2Core::OperationSuccessful
+            },
+            Core2Kv::KvDefineIndex(owner, table, name, extractor) => {
+                trace!("Got a request to define the index {}:{}:{} as {:?}", &owner, &table, &name, &extractor);
+                let existing = self.entries(&format!("{}:{}:", owner, table))
+                .map(|(full_key, value, _)| (full_key, value));
+                self.indexes.define(&owner, &table, &name, &extractor, existing);
+                Kv2Core::OperationSuccessful
+            },
+            Core2Kv::KvDropIndex(owner, table, name) => {
+                trace!("Got a request to drop the index {}:{}:{}", &owner, &table, &name);
+                self.indexes.drop_index(&owner, &table, &name);
+                Kv2Core::OperationSuccessful
+            },
+            Core2Kv::KvQueryIndex(owner, table, name, value) => {
+                trace!("Got a query on the index {}:{}:{}", &owner, &table, &name);
+                let entries = self.indexes.query(&owner, &table, &name, &value, |key| self.get(&full_key(&owner, &table, key)));
+                Kv2Core::Entries(entries)
+            },
+            Core2Kv::KvIncrement(owner, table, key, durability) => {
+                let full_key = full_key(&owner, &table, &key);
+                trace!("Got a request to increment {}", &full_key);
+                let counter = self.increment(&full_key);
+                self.commit(durability);
+                Kv2Core::Counter(counter)
+            },
+            Core2Kv::KvRaiseCounter(owner, table, key, at_least, durability) => {
+                let full_key = full_key(&owner, &table, &key);
+                trace!("Got a request to raise {} to at least {}", &full_key, at_least);
+                let counter = self.update_counter(&full_key, |n| n.max(at_least));
+                self.commit(durability);
+                Kv2Core::Counter(counter)
+            },
+            Core2Kv::KvScan(owner, table, after, limit) => {
+                trace!("Got a request to scan {}:{} after {:?}", &owner, &table, &after);
+                let prefix = full_key(&owner, &table, "");
+                Kv2Core::Entries(self.scan(&prefix, after.as_deref(), limit as usize))
+            }
+        }
+    }
+    /// Makes sure the writes made so far are as durable as asked for before they get acknowledged.
+    fn commit(&self, durability: Durability) {
+        let durability = match durability {
+            Durability::Default => self.default_durability,
+            d => d,
+        };
+        match durability {
+            Durability::Default | Durability::Async => (),
+            Durability::Flush => {
+                self.flush();
+            },
+            Durability::GroupCommit => self.group_commit.wait(),
+        }
+    }
+    /// Flushes every pending write to disk, returning how many bytes were written.
+    pub(crate) fn flush(&self) -> usize {
+        self.storage.flush()
+    }
+    pub(crate) fn group_commit(&self) -> &GroupCommit {
+        &self.group_commit
+    }
+    pub(crate) fn storage(&self) -> &SharedStorage {
+        &self.storage
+    }
+    /// Gets a value, treating it as absent if it has expired but not yet been swept.
+    pub(crate) fn get(&self, full_key: &str) -> Option<Vec<u8>> {
+        if self.is_expired(full_key) {
+            trace!("{} has expired, treating it as absent.", full_key);
+            return None;
+        }
+        self.storage.get(Tree::Data, full_key.as_bytes())
+    }
+    /// Sets a value, expiring it after `ttl` seconds if given. Setting a value without a TTL makes it permanent again.
+    pub(crate) fn set(&self, full_key: &str, value: Vec<u8>, ttl: Option<u64>) {
+        self.set_expiring_at(full_key, value, ttl.map(|secs| now().saturating_add(secs)));
+    }
+    /// Sets a value that expires at the given moment, in seconds since the Unix epoch, or never if `None`.
+    pub(crate) fn set_expiring_at(&self, full_key: &str, value: Vec<u8>, expires_at: Option<u64>) {
+        match expires_at {
+            Some(ts) => {
+                self.storage.insert(Tree::Expiry, full_key.as_bytes(), &ts.to_be_bytes());
+            },
+            None => {
+                self.storage.remove(Tree::Expiry, full_key.as_bytes());
+            }
+        }
+        let old = self.storage.insert(Tree::Data, full_key.as_bytes(), &value);
+        self.indexes.update(full_key, old.as_deref(), Some(&value));
+    }
+    /// Sets several values without TTLs, applying all of them atomically.
+    fn set_many(&self, pairs: Vec<(String, Vec<u8>)>) {
+        let mut expiry_batch = Vec::with_capacity(pairs.len());
+        let mut batch = Vec::with_capacity(pairs.len());
+        let mut old_values = Vec::with_capacity(pairs.len());
+        for (full_key, value) in &pairs {
+            expiry_batch.push((full_key.as_bytes().to_vec(), None));
+            batch.push((full_key.as_bytes().to_vec(), Some(value.clone())));
+            old_values.push(self.storage.get(Tree::Data, full_key.as_bytes()));
+        }
+        self.storage.apply_batch(Tree::Expiry, expiry_batch);
+        self.storage.apply_batch(Tree::Data, batch);
+        for ((full_key, value), old) in pairs.iter().zip(old_values) {
+            self.indexes.update(full_key, old.as_deref(), Some(value));
+        }
+    }
+    /// Atomically increments the counter at `full_key` and returns its new value.
+    fn increment(&self, full_key: &str) -> u64 {
+        self.update_counter(full_key, |n| n.saturating_add(1))
+    }
+    /// Atomically replaces the counter at `full_key` with `f` of its current value and returns the new one.
+    /// A counter that has expired but not been swept yet starts over from zero, without a TTL, as if it had been swept.
+    fn update_counter<F: Fn(u64) -> u64>(&self, full_key: &str, f: F) -> u64 {
+        if self.is_expired(full_key) {
+            self.remove(full_key);
+        }
+        let old = self.storage.fetch_and_update(Tree::Data, full_key.as_bytes(), &mut |old| Some(f(decode_counter(old)).to_le_bytes().to_vec()));
+        let new = f(decode_counter(old.as_deref()));
+        self.indexes.update(full_key, old.as_deref(), Some(&new.to_le_bytes()));
+        new
+    }
+    /// Lists up to `limit` unexpired entries under `prefix` in key order, starting right after `after` if given.
+    /// The keys returned have the prefix stripped.
+    pub(crate) fn scan(&self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<(String, Vec<u8>)> {
+        let now = now();
+        // Pages resume right after the last key of the previous one: no key can sort between it and the same key ending in NUL.
+        let start = match after {
+            Some(key) => format!("{}{}\0", prefix, key),
+            None => prefix.to_owned(),
+        };
+        self.storage.range_from(Tree::Data, start.as_bytes())
+            .take_while(|(full_key, _)| full_key.starts_with(prefix.as_bytes()))
+            .filter(|(full_key, _)| match self.storage.get(Tree::Expiry, full_key) {
+                Some(ts) => decode_timestamp(&ts) > now,
+                None => true
+            })
+            .take(limit)
+            .map(|(full_key, value)| (String::from_utf8_lossy(&full_key[prefix.len()..]).into_owned(), value))
+            .collect()
+    }
+    pub(crate) fn remove(&self, full_key: &str) {
+        let old = self.storage.remove(Tree::Data, full_key.as_bytes());
+        self.storage.remove(Tree::Expiry, full_key.as_bytes());
+        self.indexes.update(full_key, old.as_deref(), None);
+    }
+    /// Iterates over the unexpired entries whose full keys start with `prefix`, along with their expiry timestamps.
+    pub(crate) fn entries<'a>(&'a self, prefix: &str) -> impl Iterator<Item = (String, Vec<u8>, Option<u64>)> + 'a {
+        let now = now();
+        self.storage.scan_prefix(Tree::Data, prefix.as_bytes()).filter_map(move |(full_key, value)| {
+            let expires_at = self.storage.get(Tree::Expiry, &full_key).map(|ts| decode_timestamp(&ts));
+            match expires_at {
+                Some(ts) if ts <= now => None,
+                _ => Some((String::from_utf8_lossy(&full_key).into_owned(), value, expires_at))
+            }
+        })
+    }
+    /// Lists the names of the tables an owner has entries in.
+    pub(crate) fn tables(&self, owner: &str) -> Vec<String> {
+        let prefix = format!("{}:", owner);
+        let mut tables = Vec::new();
+        let mut start = prefix.clone();
+        while let Some((full_key, _)) = self.storage.range_from(Tree::Data, start.as_bytes()).next() {
+            if !full_key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let full_key = String::from_utf8_lossy(&full_key).into_owned();
+            match split_full_key(&full_key) {
+                Some((_, table, _)) => {
+                    // ';' comes right after ':', so this skips over every other key in the table.
+                    start = format!("{}{};", prefix, table);
+                    tables.push(table.to_owned());
+                },
+                None => start = format!("{}\0", full_key)
+            }
+        }
+        tables
+    }
+    /// Subscribes to every change made to the stored values.
+    pub(crate) fn watch(&self) -> Box<dyn Iterator<Item = StorageEvent> + Send> {
+        self.storage.watch()
+    }
+    fn is_expired(&self, full_key: &str) -> bool {
+        match self.storage.get(Tree::Expiry, full_key.as_bytes()) {
+            Some(ts) => decode_timestamp(&ts) <= now(),
+            None => false
+        }
+    }
+    /// Deletes every entry whose TTL has elapsed and returns how many were deleted.
+    /// Replicas leave this to their primary, whose removals reach them like any other write.
+    pub fn sweep(&self) -> usize {
+        if self.is_read_only() {
+            return 0;
+        }
+        let now = now();
+        let mut removed = 0;
+        for (full_key, ts) in self.storage.range_from(Tree::Expiry, &[]) {
+            if decode_timestamp(&ts) > now {
+                continue;
+            }
+            // Both removals are compare-and-swaps, so an entry that was set again while we were sweeping is left alone.
+            let value = self.storage.get(Tree::Data, &full_key);
+            if !self.storage.compare_and_swap(Tree::Expiry, &full_key, Some(ts.as_slice()), None) {
+                continue;
+            }
+            if let Some(v) = value {
+                if self.storage.compare_and_swap(Tree::Data, &full_key, Some(v.as_slice()), None) {
+                    self.indexes.update(&String::from_utf8_lossy(&full_key), Some(&v), None);
+                    removed += 1;
+                }
+            }
+        }
+        removed
+    }
+}
+
+/// Produces the `owner:table:key` string values are stored under.
+pub(crate) fn full_key(owner: &str, table: &str, key: &str) -> String {
+    format!("{}:{}:{}", owner, table, key)
+}
+/// Splits a full key back up into its owner, table and key. Keys may contain colons; owners and tables may not.
+pub(crate) fn split_full_key(full_key: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = full_key.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(owner), Some(table), Some(key)) => Some((owner, table, key)),
+        _ => None
+    }
+}
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+    .expect("The system clock is set before the Unix epoch!")
+    .as_secs()
+}
+/// Reads a counter stored as a bincode-encoded (little-endian) `u64`, missing or unreadable counters counting as zero.
+fn decode_counter(old: Option<&[u8]>) -> u64 {
+    match old {
+        Some(bytes) if bytes.len() == 8 => {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        },
+        _ => 0
+    }
+}
+fn decode_timestamp(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use zhur_common::msg::core_kv::{Durability, IndexExtractor};
+    use crate::storage::{SledStorage, Tree};
+    use super::*;
+
+    fn temp_store() -> KvStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        KvStore::new(Arc::new(SledStorage::new(db)), Durability::Async)
+    }
+
+    #[test]
+    fn expired_entries_are_absent_before_being_swept() {
+        let store = temp_store();
+        store.set_expiring_at("alice:t:gone", b"x".to_vec(), Some(now() - 1));
+        store.set("alice:t:kept", b"y".to_vec(), Some(3600));
+        assert_eq!(store.get("alice:t:gone"), None);
+        assert_eq!(store.get("alice:t:kept"), Some(b"y".to_vec()));
+        let keys: Vec<String> = store.scan("alice:t:", None, 10).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["kept".to_owned()]);
+        assert_eq!(store.entries("alice:t:").count(), 1);
+    }
+
+    #[test]
+    fn sweeping_removes_only_expired_entries() {
+        let store = temp_store();
+        store.set_expiring_at("alice:t:gone", b"x".to_vec(), Some(now() - 1));
+        store.set("alice:t:kept", b"y".to_vec(), Some(3600));
+        store.set("alice:t:forever", b"z".to_vec(), None);
+        assert_eq!(store.sweep(), 1);
+        assert_eq!(store.storage().get(Tree::Data, b"alice:t:gone"), None);
+        assert_eq!(store.storage().get(Tree::Expiry, b"alice:t:gone"), None);
+        assert!(store.storage().get(Tree::Expiry, b"alice:t:kept").is_some());
+        assert_eq!(store.get("alice:t:forever"), Some(b"z".to_vec()));
+        assert_eq!(store.sweep(), 0);
+    }
+
+    #[test]
+    fn setting_without_a_ttl_makes_an_entry_permanent() {
+        let store = temp_store();
+        store.set_expiring_at("alice:t:k", b"x".to_vec(), Some(now() - 1));
+        store.set("alice:t:k", b"y".to_vec(), None);
+        assert_eq!(store.get("alice:t:k"), Some(b"y".to_vec()));
+        assert_eq!(store.sweep(), 0);
+    }
+
+    #[test]
+    fn expired_counters_start_over() {
+        let store = temp_store();
+        assert_eq!(store.increment("alice:t:hits"), 1);
+        assert_eq!(store.increment("alice:t:hits"), 2);
+        store.set_expiring_at("alice:t:hits", 5u64.to_le_bytes().to_vec(), Some(now() - 1));
+        assert_eq!(store.increment("alice:t:hits"), 1);
+        assert_eq!(store.storage().get(Tree::Expiry, b"alice:t:hits"), None);
+        assert_eq!(store.sweep(), 0);
+        assert_eq!(store.get("alice:t:hits"), Some(1u64.to_le_bytes().to_vec()));
+    }
+
+    #[test]
+    fn counters_saturate() {
+        let store = temp_store();
+        assert_eq!(store.increment("alice:t:new"), 1);
+        store.set("alice:t:full", u64::MAX.to_le_bytes().to_vec(), None);
+        assert_eq!(store.increment("alice:t:full"), u64::MAX);
+        store.set("alice:t:junk", b"junk".to_vec(), None);
+        assert_eq!(store.increment("alice:t:junk"), 1);
+    }
+
+    #[test]
+    fn counters_are_only_ever_raised() {
+        let store = temp_store();
+        let raise = |at_least| store.handle(Core2Kv::KvRaiseCounter("alice".into(), "ids".into(), "todos".into(), at_least, Durability::Default));
+        assert!(matches!(raise(5), Kv2Core::Counter(5)));
+        assert_eq!(store.increment("alice:ids:todos"), 6);
+        assert!(matches!(raise(3), Kv2Core::Counter(6)));
+        assert_eq!(store.increment("alice:ids:todos"), 7);
+    }
+
+    #[test]
+    fn names_with_colons_are_refused() {
+        let store = temp_store();
+        let reply = store.handle(Core2Kv::KvSet("alice".into(), "t:x".into(), "k".into(), b"v".to_vec(), None, Durability::Default));
+        assert!(matches!(reply, Kv2Core::InvalidName(name) if name == "t:x"));
+        let reply = store.handle(Core2Kv::KvDefineIndex("alice".into(), "t".into(), "x:by_tag".into(), IndexExtractor::Bytes(0, 1)));
+        assert!(matches!(reply, Kv2Core::InvalidName(name) if name == "x:by_tag"));
+        assert!(store.storage().scan_prefix(Tree::IndexDefs, b"alice:").next().is_none());
+        // Keys may still contain colons.
+        let reply = store.handle(Core2Kv::KvSet("alice".into(), "t".into(), "k:1".into(), b"v".to_vec(), None, Durability::Default));
+        assert!(matches!(reply, Kv2Core::OperationSuccessful));
+        assert_eq!(store.get("alice:t:k:1"), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn huge_ttls_do_not_overflow() {
+        let store = temp_store();
+        store.set("alice:t:k", b"x".to_vec(), Some(u64::MAX));
+        assert_eq!(store.get("alice:t:k"), Some(b"x".to_vec()));
+        let expiry = store.storage().get(Tree::Expiry, b"alice:t:k").unwrap();
+        assert_eq!(decode_timestamp(&expiry), u64::MAX);
+    }
+}
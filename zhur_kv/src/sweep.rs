@@ -0,0 +1,34 @@
+use std::thread::JoinHandle;
+use std::time::Duration;
+use zhur_common::log::*;
+use crate::KvStore;
+
+/// Default time between two sweeps for expired entries.
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically deletes expired entries from the K/V store, so they do not pile up on disk.
+pub struct Sweeper {
+    store: KvStore,
+    interval: Duration,
+}
+
+impl Sweeper {
+    pub fn new(store: KvStore, interval: Duration) -> Self {
+        Self { store, interval }
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("kv_sweeper".to_owned())
+            .spawn(move || {
+                let sweeper = self;
+                loop {
+                    std::thread::sleep(sweeper.interval);
+                    let removed = sweeper.store.sweep();
+                    if removed > 0 {
+                        debug!("Swept {} expired entries from the K/V store.", removed);
+                    }
+                }
+            })
+            .expect("Could not launch the K/V sweeper thread!")
+    }
+}
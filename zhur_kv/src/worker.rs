@@ -0,0 +1,68 @@
+use std::thread::JoinHandle;
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::core_kv::{Kv2Core, TaggedCore2Kv, TaggedKv2Core};
+use zhur_common::zmq::{Context, Socket, SocketType};
+use crate::KvStore;
+
+/// In-process endpoint the front ROUTER socket's requests are spread out over workers through.
+pub const WORKER_ENDPOINT: &str = "inproc://zhur_kv_workers";
+
+/// One of several threads carrying out `Core2Kv` requests, so a slow request does not hold up the others.
+pub struct Worker {
+    /// Inner numeral ID, for logging.
+    id: usize,
+    /// ZMQ rep socket connected to `WORKER_ENDPOINT`.
+    rep_socket: Socket,
+    store: KvStore,
+}
+
+impl Worker {
+    /// Creates a worker. `WORKER_ENDPOINT` must already be bound on the same context.
+    pub fn new(id: usize, zmq_ctx: &Context, store: KvStore) -> Self {
+        let rep_socket = zmq_ctx.socket(SocketType::REP).unwrap();
+        rep_socket.connect(WORKER_ENDPOINT).expect("Could not connect a K/V worker to the worker endpoint!");
+        Self { id, rep_socket, store }
+    }
+    fn handle(&self) {
+        let request_bytes = self.rep_socket.recv_bytes(0).unwrap();
+        trace!("K/V worker #{} got request bytes.", self.id);
+        let res_bytes = match deserialize::<TaggedCore2Kv>(&request_bytes) {
+            Ok((tag, request_id, request)) => {
+                let request_id = request_id.as_deref().unwrap_or("-");
+                debug!("[{}] K/V worker #{} carrying out a {} as #{}.", request_id, self.id, if request.is_write() { "write" } else { "read" }, tag);
+                let reply = self.store.handle(request);
+                if let Kv2Core::ReadOnly = reply {
+                    warn!("[{}] K/V worker #{} refused a write, as this store is a read-only replica.", request_id, self.id);
+                }
+                let response: TaggedKv2Core = (tag, reply);
+                serialize(&response).unwrap()
+            },
+            // A core built against a different Core2Kv still sends a readable tag, so it gets a Malformed reply instead of waiting on one.
+            Err(_) => match deserialize::<u64>(&request_bytes) {
+                Ok(tag) => {
+                    warn!("K/V worker #{} got bytes tagged #{} that could not be deserialized to a TaggedCore2Kv.", self.id, tag);
+                    let response: TaggedKv2Core = (tag, Kv2Core::Malformed);
+                    serialize(&response).unwrap()
+                },
+                Err(_) => {
+                    warn!("K/V worker #{} got bytes that could not be deserialized to a TaggedCore2Kv, nor even carry a tag.", self.id);
+                    Vec::new()
+                }
+            }
+        };
+        trace!("K/V worker #{} sending reply...", self.id);
+        self.rep_socket.send(res_bytes, 0).unwrap();
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name(format!("kv_worker_{}", self.id))
+            .spawn(move || {
+                let worker = self;
+                loop {
+                    worker.handle();
+                }
+            })
+            .expect("Could not launch a K/V worker thread!")
+    }
+}
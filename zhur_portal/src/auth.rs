@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::Deserialize;
+use warp::{Filter, Rejection};
+
+use crate::errors::{Forbidden, Unauthorized};
+
+/// The claims the portal expects in a bearer token.
+/// Tokens must also carry an `exp` claim, which `jsonwebtoken` checks by itself.
+#[derive(Deserialize)]
+pub struct Claims {
+    /// The owner the token was issued to.
+    pub sub: String,
+}
+
+/// Reads the secret used to verify HS256 tokens from `ZHUR_PORTAL_JWT_SECRET`, returning a description of the problem if it is missing.
+/// There is deliberately no default, as a well-known secret would let anyone sign tokens.
+pub fn secret_from_env() -> Result<Arc<String>, String> {
+    match std::env::var("ZHUR_PORTAL_JWT_SECRET") {
+        Ok(secret) if !secret.is_empty() => Ok(Arc::new(secret)),
+        _ => Err("ZHUR_PORTAL_JWT_SECRET must be set to the secret used to sign portal tokens.".to_owned()),
+    }
+}
+
+/// Extracts the owner a request was made by from its `Authorization: Bearer <token>` header, rejecting requests without a valid token.
+pub fn authenticated(secret: Arc<String>) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |header: Option<String>| {
+        let secret = secret.clone();
+        async move {
+            let header = header.ok_or_else(|| warp::reject::custom(Unauthorized))?;
+            let token = header.strip_prefix("Bearer ").ok_or_else(|| warp::reject::custom(Unauthorized))?;
+            let data = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())
+            .map_err(|_| warp::reject::custom(Unauthorized))?;
+            Ok::<_, Rejection>(data.claims.sub)
+        }
+    })
+}
+
+/// Makes sure owners can only get at their own data.
+pub fn authorize(sub: &str, owner: &str) -> Result<(), Rejection> {
+    if sub == owner {
+        Ok(())
+    } else {
+        Err(warp::reject::custom(Forbidden))
+    }
+}
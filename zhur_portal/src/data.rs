@@ -0,0 +1,185 @@
+//! Endpoints for browsing and editing the data an owner's apps keep in the K/V store.
+//! - `GET /data/:owner` lists the owner's tables.
+//! - `GET /data/:owner/:table?after=&limit=` lists keys in a table, a page at a time.
+//! - `GET /data/:owner/:table/:key` shows a value decoded as UTF-8 and JSON where possible, and always as hex.
+//! - `PUT /data/:owner/:table/:key?format=raw|hex` sets a value from the request body.
+//! - `DELETE /data/:owner/:table/:key` deletes an entry.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection, Reply};
+use zhur_common::hex::{from_hex, to_hex};
+use zhur_common::msg::kv_admin::{Admin2Kv, Kv2Admin};
+
+use crate::auth::{authenticated, authorize};
+use crate::errors::{BadValue, KvError, NoSuchEntry};
+use crate::kv::KvAdmin;
+
+/// How many keys are listed per page if the request does not say.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+/// The most keys that can be listed per page.
+const MAX_PAGE_SIZE: u32 = 1000;
+/// The largest value that can be set through the portal, in bytes.
+const MAX_VALUE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct KeysQuery {
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct KeysPage {
+    keys: Vec<String>,
+    /// Pass this as `after` to get the next page. Absent on the last page.
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PutQuery {
+    format: Option<String>,
+}
+
+/// A value, shown every way it can be decoded.
+#[derive(Serialize)]
+struct ValueView {
+    key: String,
+    size: usize,
+    utf8: Option<String>,
+    json: Option<serde_json::Value>,
+    hex: String,
+}
+
+impl ValueView {
+    fn new(key: String, value: Vec<u8>) -> Self {
+        Self {
+            key,
+            size: value.len(),
+            utf8: String::from_utf8(value.clone()).ok(),
+            json: serde_json::from_slice(&value).ok(),
+            hex: to_hex(&value),
+        }
+    }
+}
+
+/// All of the data browsing routes.
+pub fn routes(kv_admin: Arc<KvAdmin>, secret: Arc<String>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let data = warp::path("data");
+    let list_tables_route = warp::get()
+    .and(data)
+    .and(decoded_param())
+    .and(warp::path::end())
+    .and(authenticated(secret.clone()))
+    .and(with_kv_admin(kv_admin.clone()))
+    .and_then(list_tables);
+    let list_keys_route = warp::get()
+    .and(data)
+    .and(decoded_param())
+    .and(decoded_param())
+    .and(warp::path::end())
+    .and(warp::query::<KeysQuery>())
+    .and(authenticated(secret.clone()))
+    .and(with_kv_admin(kv_admin.clone()))
+    .and_then(list_keys);
+    let entry = data
+    .and(decoded_param())
+    .and(decoded_param())
+    .and(decoded_param())
+    .and(warp::path::end());
+    let get_entry_route = warp::get()
+    .and(entry)
+    .and(authenticated(secret.clone()))
+    .and(with_kv_admin(kv_admin.clone()))
+    .and_then(get_entry);
+    let put_entry_route = warp::put()
+    .and(entry)
+    .and(warp::query::<PutQuery>())
+    .and(warp::body::content_length_limit(MAX_VALUE_SIZE))
+    .and(warp::body::bytes())
+    .and(authenticated(secret.clone()))
+    .and(with_kv_admin(kv_admin.clone()))
+    .and_then(put_entry);
+    let delete_entry_route = warp::delete()
+    .and(entry)
+    .and(authenticated(secret))
+    .and(with_kv_admin(kv_admin))
+    .and_then(delete_entry);
+    list_tables_route
+    .or(list_keys_route)
+    .or(get_entry_route)
+    .or(put_entry_route)
+    .or(delete_entry_route)
+}
+
+fn with_kv_admin(kv_admin: Arc<KvAdmin>) -> impl Filter<Extract = (Arc<KvAdmin>,), Error = Infallible> + Clone {
+    warp::any().map(move || kv_admin.clone())
+}
+
+/// A path segment, percent-decoded, as keys can contain characters that have to be escaped in URLs.
+fn decoded_param() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path::param::<String>().map(|segment: String| percent_decode_str(&segment).decode_utf8_lossy().into_owned())
+}
+
+async fn list_tables(owner: String, sub: String, kv_admin: Arc<KvAdmin>) -> Result<impl Reply, Rejection> {
+    authorize(&sub, &owner)?;
+    match kv_admin.request(Admin2Kv::ListTables(owner)).await? {
+        Kv2Admin::Tables(tables) => Ok(warp::reply::json(&tables)),
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn list_keys(owner: String, table: String, query: KeysQuery, sub: String, kv_admin: Arc<KvAdmin>) -> Result<impl Reply, Rejection> {
+    authorize(&sub, &owner)?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    match kv_admin.request(Admin2Kv::ListKeys(owner, table, query.after, limit)).await? {
+        Kv2Admin::Keys(keys) => {
+            let next = match keys.len() as u32 == limit {
+                true => keys.last().cloned(),
+                false => None,
+            };
+            Ok(warp::reply::json(&KeysPage { keys, next }))
+        },
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn get_entry(owner: String, table: String, key: String, sub: String, kv_admin: Arc<KvAdmin>) -> Result<impl Reply, Rejection> {
+    authorize(&sub, &owner)?;
+    match kv_admin.request(Admin2Kv::Get(owner, table, key.clone())).await? {
+        Kv2Admin::Value(Some(value)) => Ok(warp::reply::json(&ValueView::new(key, value))),
+        Kv2Admin::Value(None) => Err(warp::reject::custom(NoSuchEntry)),
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn put_entry(owner: String, table: String, key: String, query: PutQuery, body: Bytes, sub: String, kv_admin: Arc<KvAdmin>) -> Result<impl Reply, Rejection> {
+    authorize(&sub, &owner)?;
+    let value = match query.format.as_deref() {
+        None | Some("raw") => body.to_vec(),
+        Some("hex") => {
+            let text = std::str::from_utf8(&body).map_err(|_| warp::reject::custom(BadValue("Hex values must be sent as text.".to_string())))?;
+            from_hex(text.trim()).ok_or_else(|| warp::reject::custom(BadValue("The value is not valid hex.".to_string())))?
+        },
+        Some(other) => return Err(warp::reject::custom(BadValue(format!("Unknown value format {:?}.", other)))),
+    };
+    match kv_admin.request(Admin2Kv::Set(owner, table, key, value)).await? {
+        Kv2Admin::Done => Ok(warp::http::StatusCode::NO_CONTENT),
+        other => Err(unexpected(other)),
+    }
+}
+
+async fn delete_entry(owner: String, table: String, key: String, sub: String, kv_admin: Arc<KvAdmin>) -> Result<impl Reply, Rejection> {
+    authorize(&sub, &owner)?;
+    match kv_admin.request(Admin2Kv::Del(owner, table, key)).await? {
+        Kv2Admin::Done => Ok(warp::http::StatusCode::NO_CONTENT),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn unexpected(reply: Kv2Admin) -> Rejection {
+    warp::reject::custom(KvError(format!("Unexpected reply from the K/V store: {:?}", reply)))
+}
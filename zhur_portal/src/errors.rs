@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::Reply;
+
+/// The request had no valid bearer token.
+#[derive(Debug)]
+pub struct Unauthorized;
+impl Reject for Unauthorized {}
+
+/// The token is valid, but for a different owner.
+#[derive(Debug)]
+pub struct Forbidden;
+impl Reject for Forbidden {}
+
+/// The requested entry does not exist.
+#[derive(Debug)]
+pub struct NoSuchEntry;
+impl Reject for NoSuchEntry {}
+
+/// A value sent for an entry could not be decoded.
+#[derive(Debug)]
+pub struct BadValue(pub String);
+impl Reject for BadValue {}
+
+/// The K/V store did not answer in time.
+#[derive(Debug)]
+pub struct KvUnavailable;
+impl Reject for KvUnavailable {}
+
+/// The K/V store answered with an error.
+#[derive(Debug)]
+pub struct KvError(pub String);
+impl Reject for KvError {}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Turns rejections into JSON error replies.
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found.".to_string())
+    } else if err.find::<Unauthorized>().is_some() {
+        (StatusCode::UNAUTHORIZED, "A valid bearer token is required.".to_string())
+    } else if err.find::<Forbidden>().is_some() {
+        (StatusCode::FORBIDDEN, "You can only access your own data.".to_string())
+    } else if err.find::<NoSuchEntry>().is_some() {
+        (StatusCode::NOT_FOUND, "No such entry.".to_string())
+    } else if let Some(BadValue(e)) = err.find() {
+        (StatusCode::BAD_REQUEST, e.clone())
+    } else if err.find::<KvUnavailable>().is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, "The K/V store is unavailable.".to_string())
+    } else if let Some(KvError(e)) = err.find() {
+        (StatusCode::INTERNAL_SERVER_ERROR, e.clone())
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        (StatusCode::BAD_REQUEST, "Invalid query string.".to_string())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "The value is too large.".to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed.".to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", err))
+    };
+    let body = warp::reply::json(&ErrorBody { error: message });
+    Ok(warp::reply::with_status(body, status))
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+
+use warp::Rejection;
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::kv_admin::{Admin2Kv, Kv2Admin, DEFAULT_KV_ADMIN_ENDPOINT};
+use zhur_common::sockets::timed_req_socket;
+use zhur_common::zmq::{Context, Socket};
+
+use crate::errors::{KvError, KvUnavailable};
+
+/// How long to wait for the K/V store to answer, in milliseconds.
+const KV_ADMIN_TIMEOUT: i32 = 5000;
+
+/// Client for the K/V store's admin endpoint, shared between request handlers.
+pub struct KvAdmin {
+    /// ZMQ sockets aren't thread-safe, so requests take turns.
+    req_socket: Mutex<Socket>,
+}
+
+impl KvAdmin {
+    pub fn new(zmq_ctx: &Context) -> Self {
+        let endpoint = match std::env::var("ZHUR_KV_ADMIN_ENDPOINT") {
+            Ok(e) => e,
+            Err(_) => {
+                warn!("ZHUR_KV_ADMIN_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_KV_ADMIN_ENDPOINT);
+                DEFAULT_KV_ADMIN_ENDPOINT.to_string()
+            }
+        };
+        let req_socket = timed_req_socket(zmq_ctx, KV_ADMIN_TIMEOUT);
+        req_socket.connect(&endpoint).expect("Could not connect to the K/V admin endpoint!");
+        Self {
+            req_socket: Mutex::new(req_socket)
+        }
+    }
+    /// Sends a request to the K/V store without blocking the async runtime.
+    /// `Kv2Admin::Error` replies and failures to reach the store are turned into rejections.
+    pub async fn request(self: Arc<Self>, request: Admin2Kv) -> Result<Kv2Admin, Rejection> {
+        let reply = tokio::task::spawn_blocking(move || self.request_blocking(request))
+        .await
+        .expect("The K/V admin request task panicked!");
+        match reply {
+            Some(Kv2Admin::Error(e)) => Err(warp::reject::custom(KvError(e))),
+            Some(reply) => Ok(reply),
+            None => Err(warp::reject::custom(KvUnavailable)),
+        }
+    }
+    fn request_blocking(&self, request: Admin2Kv) -> Option<Kv2Admin> {
+        let socket = self.req_socket.lock().unwrap();
+        socket.send(serialize(&request).unwrap(), 0).unwrap();
+        match socket.recv_bytes(0) {
+            Ok(bytes) => match deserialize::<Kv2Admin>(&bytes) {
+                Ok(reply) => Some(reply),
+                Err(_) => {
+                    error!("Got a reply from the K/V store that could not be deserialized.");
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("Got no reply from the K/V store: {}", e);
+                None
+            }
+        }
+    }
+}
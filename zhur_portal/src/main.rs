@@ -1,9 +1,27 @@
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
 
 use warp::filters;
 use warp::Filter;
+use zhur_common::{init_logger, log::*, zmq};
+
+mod auth;
+mod data;
+mod errors;
+mod kv;
+
 #[tokio::main]
 async fn main() {
+    init_logger();
+    let zmq_ctx = zmq::Context::new();
+    let kv_admin = Arc::new(kv::KvAdmin::new(&zmq_ctx));
+    let secret = match auth::secret_from_env() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{} Exiting.", e);
+            std::process::exit(1);
+        }
+    };
     let hw_filter = filters::method::get()
     .and(filters::path::end())
     .map(|| "Hello, world!".to_string());
@@ -11,6 +29,9 @@ async fn main() {
     .and(filters::path::path("hello"))
     .and(filters::path::param())
     .map(|name: String| format!("Hello, {}!", name));
-    let filter = hw_filter.or(hw_name_filter);
+    let filter = hw_filter
+    .or(hw_name_filter)
+    .or(data::routes(kv_admin, secret))
+    .recover(errors::handle_rejection);
     warp::serve(filter).run(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8004)).await;
-}
\ No newline at end of file
+}
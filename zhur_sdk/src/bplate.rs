@@ -1,6 +1,13 @@
 /// This macro takes any function that takes an `&HttpReq` and a `&mut HttpRes`, then generates the WAPC boilerplate for running it.
 /// This lets you just focus on writing your app.
 /// HTTP request deserialization, response serialization and all such are all taken care of here.
+///
+/// Extra handlers can also be given by name:
+/// - `kv_change = f`, a function taking a `&KvChange`, which is then called for changes to any tables watched with `kv_watch`.
+///
+/// ```ignore
+/// handle_http!(handler, kv_change = on_change);
+/// ```
 #[macro_export]
 macro_rules! handle_http {
     ($http_handler:ident) => {
@@ -20,4 +27,35 @@ macro_rules! handle_http {
             zhur_sdk::reex::wapc_guest::register_function("handle_http", outer_handler);
         }
     };
+    ($http_handler:ident, $($extra:ident = $value:expr),+ $(,)?) => {
+        fn outer_handler(msg: &[u8]) -> zhur_sdk::reex::wapc_guest::CallResult {
+            let output = inner_handler(msg);
+            let bytes = zhur_sdk::reex::bincode::serialize(&output).unwrap(); // WE DO NOT EXPECT TO FAIL HERE
+            Ok(bytes)
+        }
+        fn inner_handler(msg: &[u8]) -> zhur_sdk::http::HttpRes {
+            let req: zhur_sdk::http::HttpReq = zhur_sdk::reex::bincode::deserialize(msg).unwrap(); // WE DO NOT EXPECT TO FAIL HERE
+            let mut res = zhur_sdk::http::HttpRes::default();
+            $http_handler(&req, &mut res);
+            res
+        }
+        #[no_mangle]
+        pub extern "C" fn wapc_init() {
+            zhur_sdk::reex::wapc_guest::register_function("handle_http", outer_handler);
+            $(zhur_sdk::__register_extra_handler!($extra, $value);)+
+        }
+    };
+}
+
+/// Registers one of the named extra handlers of `handle_http!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_extra_handler {
+    (kv_change, $kv_change_handler:expr) => {
+        zhur_sdk::reex::wapc_guest::register_function("handle_kv_change", |msg: &[u8]| {
+            let change: zhur_sdk::svc::kv::KvChange = zhur_sdk::reex::bincode::deserialize(msg).unwrap(); // WE DO NOT EXPECT TO FAIL HERE
+            $kv_change_handler(&change);
+            Ok(Vec::new())
+        });
+    };
 }
\ No newline at end of file
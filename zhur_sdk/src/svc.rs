@@ -2,5 +2,11 @@
 pub mod meta;
 /// Key-value data store access.
 pub mod kv;
+/// Typed collections of records on top of the key-value store.
+pub mod collection;
+/// Relational database access.
+pub mod sql;
+/// Storage for large files.
+pub mod blob;
 /// Date/time access.
 pub mod datetime;
\ No newline at end of file
@@ -0,0 +1,147 @@
+use std::io::{self, Read, Write};
+use bincode::{deserialize, serialize};
+use mime_sniffer::MimeTypeSniffer;
+use wapc_guest::host_call;
+pub use zhur_invk::blob::{BlobMeta, BLOB_CHUNK_SIZE};
+/// Stores `bytes` at `path` in this app's blob storage, replacing whatever was there.
+/// If no content type is given, one is sniffed from the bytes, falling back to `application/octet-stream`.
+/// ```ignore
+/// blob_put("avatars/alice.png", &bytes, None)?;
+/// ```
+pub fn blob_put(path: &str, bytes: &[u8], content_type: Option<&str>) -> Result<BlobMeta, String> {
+    let content_type = match content_type {
+        Some(c) => c.to_owned(),
+        None => bytes.sniff_mime_type().unwrap_or("application/octet-stream").to_owned(),
+    };
+    let mut writer = BlobWriter::new(path, &content_type)?;
+    for chunk in bytes.chunks(BLOB_CHUNK_SIZE) {
+        writer.put_chunk(chunk)?;
+    }
+    writer.finish()
+}
+/// Reads the whole blob at `path`, or returns `None` if there is none.
+pub fn blob_get(path: &str) -> Result<Option<Vec<u8>>, String> {
+    let mut reader = match BlobReader::open(path)? {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    // The size is only a hint, so no more than a chunk is set aside for it up front.
+    let mut bytes = Vec::with_capacity((reader.meta().size as usize).min(BLOB_CHUNK_SIZE));
+    reader.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(Some(bytes))
+}
+/// Gets the metadata of the blob at `path`, or `None` if there is none.
+pub fn blob_stat(path: &str) -> Result<Option<BlobMeta>, String> {
+    let req_bytes = serialize(&path.to_string()).unwrap();
+    let res_bytes = host_call("", "", "blob_stat", &req_bytes).unwrap();
+    deserialize::<Result<Option<BlobMeta>, String>>(&res_bytes).unwrap()
+}
+/// Deletes the blob at `path`. Returns whether there was one to delete.
+pub fn blob_delete(path: &str) -> Result<bool, String> {
+    let req_bytes = serialize(&path.to_string()).unwrap();
+    let res_bytes = host_call("", "", "blob_delete", &req_bytes).unwrap();
+    deserialize::<Result<bool, String>>(&res_bytes).unwrap()
+}
+/// Lists up to `limit` blobs whose paths start with `prefix`, in path order.
+/// Pass the path of the last blob listed as `after` to get the next page.
+pub fn blob_list(prefix: &str, after: Option<&str>, limit: u32) -> Result<Vec<BlobMeta>, String> {
+    let request = (prefix.to_string(), after.map(String::from), limit);
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = host_call("", "", "blob_list", &req_bytes).unwrap();
+    deserialize::<Result<Vec<BlobMeta>, String>>(&res_bytes).unwrap()
+}
+
+/// Puts a blob piece by piece, for when it is too big to have in memory all at once.
+/// Nothing is visible at the path until `finish` is called. Dropping the writer unfinished throws the upload away.
+pub struct BlobWriter {
+    id: Option<u64>,
+    buffer: Vec<u8>,
+}
+impl BlobWriter {
+    /// Starts putting a blob at `path` with the given content type.
+    pub fn new(path: &str, content_type: &str) -> Result<Self, String> {
+        let request = (path.to_string(), content_type.to_string());
+        let req_bytes = serialize(&request).unwrap();
+        let res_bytes = host_call("", "", "blob_begin_put", &req_bytes).unwrap();
+        let id = deserialize::<Result<u64, String>>(&res_bytes).unwrap()?;
+        Ok(Self {
+            id: Some(id),
+            buffer: Vec::with_capacity(BLOB_CHUNK_SIZE),
+        })
+    }
+    /// Stores the blob, replacing whatever was at its path.
+    pub fn finish(mut self) -> Result<BlobMeta, String> {
+        self.flush_buffer()?;
+        let id = self.id.take().unwrap();
+        let res_bytes = host_call("", "", "blob_finish_put", &serialize(&id).unwrap()).unwrap();
+        deserialize::<Result<BlobMeta, String>>(&res_bytes).unwrap()
+    }
+    fn put_chunk(&mut self, chunk: &[u8]) -> Result<(), String> {
+        let request = (self.id.unwrap(), chunk.to_vec());
+        let res_bytes = host_call("", "", "blob_put_chunk", &serialize(&request).unwrap()).unwrap();
+        deserialize::<Result<(), String>>(&res_bytes).unwrap()
+    }
+    fn flush_buffer(&mut self) -> Result<(), String> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.buffer);
+        self.put_chunk(&chunk)
+    }
+}
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOB_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == BLOB_CHUNK_SIZE {
+            self.flush_buffer().map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buffer().map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let _ = host_call("", "", "blob_abort_put", &serialize(&id).unwrap());
+        }
+    }
+}
+
+/// Reads a blob piece by piece, for when it is too big to have in memory all at once.
+/// The reader sticks to the version of the blob it opened: if the blob is put again or deleted partway through, reading fails.
+pub struct BlobReader {
+    meta: BlobMeta,
+    offset: u64,
+}
+impl BlobReader {
+    /// Opens the blob at `path`, or returns `None` if there is none.
+    pub fn open(path: &str) -> Result<Option<Self>, String> {
+        Ok(blob_stat(path)?.map(|meta| Self { meta, offset: 0 }))
+    }
+    /// The metadata of the blob being read.
+    pub fn meta(&self) -> &BlobMeta {
+        &self.meta
+    }
+}
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.offset >= self.meta.size {
+            return Ok(0);
+        }
+        let len = buf.len().min(BLOB_CHUNK_SIZE) as u32;
+        let request = (self.meta.path.clone(), self.offset, len, Some(self.meta.etag.clone()));
+        let res_bytes = host_call("", "", "blob_get_chunk", &serialize(&request).unwrap()).unwrap();
+        let chunk = deserialize::<Result<Option<Vec<u8>>, String>>(&res_bytes).unwrap()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{:?} was deleted while being read.", &self.meta.path)))?;
+        if chunk.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{:?} ended after {} of {} bytes.", &self.meta.path, self.offset, self.meta.size)));
+        }
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        self.offset += chunk.len() as u64;
+        Ok(chunk.len())
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use super::kv::{kv_del, kv_get, kv_increment, kv_raise_counter, kv_scan, kv_set};
+
+/// The table holding the ID counters of every collection, keyed by collection name.
+const ID_TABLE: &str = "_zhur_collection_ids";
+/// How many records `CollectionIter` fetches per round trip.
+const PAGE_SIZE: u32 = 64;
+
+/// A record as it sits in the key-value store: the schema version it was written with and its bincode-encoded data.
+#[derive(Deserialize, Serialize)]
+struct Stored {
+    version: u32,
+    data: Vec<u8>,
+}
+
+/// A typed collection of records kept in a key-value store table, with automatically assigned IDs.
+///
+/// Records remember the schema version they were written with. When `T` changes shape, bump the version with `version`
+/// and register a `migration` from the previous one; older records are then upgraded as they are read.
+pub struct Collection<T> {
+    table: String,
+    version: u32,
+    /// Functions turning the bincode encoding of a record at the version they are keyed by into one at the next version.
+    migrations: BTreeMap<u32, fn(Vec<u8>) -> Vec<u8>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Collection<T> {
+    /// Opens the collection stored in the given table, at schema version 0.
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            version: 0,
+            migrations: BTreeMap::new(),
+            _marker: PhantomData,
+        }
+    }
+    /// Sets the current schema version. Records written from now on are marked with it.
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+    /// Registers a function upgrading the bincode encoding of a record from version `from` to version `from + 1`.
+    pub fn migration(mut self, from: u32, migrate: fn(Vec<u8>) -> Vec<u8>) -> Self {
+        self.migrations.insert(from, migrate);
+        self
+    }
+    /// Stores a new record and returns the ID it was given. IDs start at 1 and are never reused.
+    pub fn insert(&self, item: &T) -> Result<u64, String> {
+        let id = kv_increment(ID_TABLE, &self.table)?;
+        self.put(id, item)?;
+        Ok(id)
+    }
+    /// Makes sure `insert` only hands out IDs greater than `id` from now on, e.g. after `put`ting records carried over from elsewhere.
+    /// The ID counter is raised in one step in the store, so inserts happening meanwhile never get an ID handed out again.
+    pub fn reserve_ids(&self, id: u64) -> Result<(), String> {
+        kv_raise_counter(ID_TABLE, &self.table, id).map(|_| ())
+    }
+    /// Gets a record by ID. Fails if the record is at a version it cannot be upgraded from.
+    pub fn get(&self, id: u64) -> Result<Option<T>, String> {
+        match kv_get::<Stored>(&self.table, &key(id)) {
+            Some(stored) => self.upgrade(id, stored).map(Some),
+            None => Ok(None),
+        }
+    }
+    /// Stores a record under the given ID, replacing whatever was there.
+    pub fn put(&self, id: u64, item: &T) -> Result<(), String> {
+        let stored = Stored {
+            version: self.version,
+            data: serialize(item).unwrap(),
+        };
+        kv_set(&self.table, &key(id), &stored)
+    }
+    /// Modifies a record in place, returning `false` if there is no record with the given ID.
+    pub fn update<F: FnOnce(&mut T)>(&self, id: u64, f: F) -> Result<bool, String> {
+        match self.get(id)? {
+            Some(mut item) => {
+                f(&mut item);
+                self.put(id, &item)?;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+    /// Deletes a record by ID.
+    pub fn delete(&self, id: u64) -> Result<(), String> {
+        kv_del(&self.table, &key(id))
+    }
+    /// Iterates over all records and their IDs, in ID order. Records are fetched a page at a time.
+    /// Records at a version they cannot be upgraded from come up as errors.
+    pub fn iter(&self) -> CollectionIter<'_, T> {
+        CollectionIter {
+            collection: self,
+            page: Vec::new(),
+            last_key: None,
+            done: false,
+        }
+    }
+    /// Brings a stored record up to the current version, writing it back if that took any migrations.
+    /// Failing to write it back, such as on a read-only replica, is no matter; it is simply upgraded again next time.
+    fn upgrade(&self, id: u64, stored: Stored) -> Result<T, String> {
+        let Stored { mut version, mut data } = stored;
+        if version > self.version {
+            return Err(format!("Record {} of collection {} is at version {}, newer than the current {}.", id, &self.table, version, self.version));
+        }
+        let migrated = version < self.version;
+        while version < self.version {
+            let migrate = self.migrations.get(&version)
+            .ok_or_else(|| format!("No migration is registered from version {} of collection {}.", version, &self.table))?;
+            data = migrate(data);
+            version += 1;
+        }
+        let item = deserialize::<T>(&data)
+        .map_err(|e| format!("Record {} of collection {} could not be read: {}", id, &self.table, e))?;
+        if migrated {
+            let _ = self.put(id, &item);
+        }
+        Ok(item)
+    }
+}
+
+/// Iterator over the records of a `Collection`, returned by `Collection::iter`.
+pub struct CollectionIter<'a, T> {
+    collection: &'a Collection<T>,
+    /// The rest of the current page, reversed so records can be popped off in order.
+    page: Vec<(String, Stored)>,
+    last_key: Option<String>,
+    done: bool,
+}
+
+impl<'a, T: Serialize + DeserializeOwned> Iterator for CollectionIter<'a, T> {
+    type Item = Result<(u64, T), String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, stored)) = self.page.pop() {
+                let id = match key.parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => continue,
+                };
+                return Some(self.collection.upgrade(id, stored).map(|item| (id, item)));
+            }
+            if self.done {
+                return None;
+            }
+            let page = kv_scan::<Stored>(&self.collection.table, self.last_key.as_deref(), PAGE_SIZE);
+            self.done = page.len() < PAGE_SIZE as usize;
+            self.last_key = page.last().map(|(key, _)| key.clone());
+            self.page = page;
+            self.page.reverse();
+        }
+    }
+}
+
+/// Produces the key a record is stored under. IDs are zero-padded so that key order matches ID order.
+fn key(id: u64) -> String {
+    format!("{:020}", id)
+}
@@ -1,6 +1,8 @@
-use bincode::*;
+use std::time::Duration;
+use bincode::{deserialize, serialize};
 use serde::{Serialize, de::DeserializeOwned};
 use wapc_guest::host_call;
+pub use zhur_invk::kv::{Durability, IndexExtractor, KvChange, KvOp};
 /// Gets a value from the key-value data store.
 pub fn kv_get<T: DeserializeOwned>(table: &str, key: &str) -> Option<T> {
     let request = (table.to_string(), key.to_string());
@@ -10,15 +12,151 @@ pub fn kv_get<T: DeserializeOwned>(table: &str, key: &str) -> Option<T> {
     Some(deserialize::<T>(&res_bytes).unwrap())
 }
 /// Sets a value in the key-value store.
-pub fn kv_set<T: Serialize>(table: &str, key: &str, value: &T) {
+/// Like every write, this fails if the store did not confirm it, such as when it is a read-only replica.
+pub fn kv_set<T: Serialize>(table: &str, key: &str, value: &T) -> Result<(), String> {
     let val_bytes = serialize(&value).unwrap();
     let request = (table.to_string(), key.to_string(), val_bytes);
     let req_bytes = serialize(&request).unwrap();
-    host_call("", "", "kv_set", &req_bytes).unwrap();
+    write_call("kv_set", &req_bytes).map(|_| ())
+}
+/// Gets several values from the same table in a single round trip to the key-value store.
+/// The results come in the same order as `keys`.
+pub fn kv_get_many<T: DeserializeOwned>(table: &str, keys: &[&str]) -> Vec<Option<T>> {
+    let keys = keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+    let request = (table.to_string(), keys);
+    let req_bytes = serialize(&request).unwrap();
+    let outer_res_bytes = host_call("", "", "kv_get_many", &req_bytes).unwrap();
+    let res_bytes = deserialize::<Vec<Option<Vec<u8>>>>(&outer_res_bytes).unwrap();
+    res_bytes.into_iter()
+    .map(|opt| opt.map(|bytes| deserialize::<T>(&bytes).unwrap()))
+    .collect()
+}
+/// Sets several values in the same table in a single round trip to the key-value store. They are all written at once.
+pub fn kv_set_many<T: Serialize>(table: &str, entries: &[(&str, T)]) -> Result<(), String> {
+    let pairs = entries.iter()
+    .map(|(key, value)| (key.to_string(), serialize(value).unwrap()))
+    .collect::<Vec<_>>();
+    let request = (table.to_string(), pairs);
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_set_many", &req_bytes).map(|_| ())
+}
+/// Sets a value in the key-value store that expires after `ttl`. Once expired, it is treated as absent and eventually deleted.
+/// The TTL is counted in whole seconds.
+pub fn kv_set_with_ttl<T: Serialize>(table: &str, key: &str, value: &T, ttl: Duration) -> Result<(), String> {
+    let val_bytes = serialize(&value).unwrap();
+    let request = (table.to_string(), key.to_string(), val_bytes, ttl.as_secs());
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_set_ttl", &req_bytes).map(|_| ())
+}
+/// Sets how sure the key-value store has to be that this app's writes have reached the disk before they return.
+/// The setting applies to every later set, delete and increment made while handling the current request or change, unless changed again.
+/// Use `Durability::Flush` or `Durability::GroupCommit` for data that must not be lost in a crash.
+pub fn kv_set_durability(durability: Durability) {
+    let req_bytes = serialize(&durability).unwrap();
+    host_call("", "", "kv_durability", &req_bytes).unwrap();
+}
+/// Runs `f` with the given durability for its writes, going back to `Durability::Default` afterwards.
+pub fn with_durability<T>(durability: Durability, f: impl FnOnce() -> T) -> T {
+    kv_set_durability(durability);
+    let result = f();
+    kv_set_durability(Durability::Default);
+    result
 }
 /// Deletes a value in the key-value store.
-pub fn kv_del(table: &str, key: &str) {
+pub fn kv_del(table: &str, key: &str) -> Result<(), String> {
+    let request = (table.to_string(), key.to_string());
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_del", &req_bytes).map(|_| ())
+}
+/// Gets a JSON-encoded value from the key-value data store. Use this for values stored with `kv_set_json`.
+pub fn kv_get_json<T: DeserializeOwned>(table: &str, key: &str) -> Option<T> {
+    let request = (table.to_string(), key.to_string());
+    let req_bytes = serialize(&request).unwrap();
+    let outer_res_bytes = host_call("", "", "kv_get", &req_bytes).unwrap();
+    let res_bytes = deserialize::<Option<Vec<u8>>>(&outer_res_bytes).unwrap()?;
+    Some(serde_json::from_slice::<T>(&res_bytes).unwrap())
+}
+/// Sets a value in the key-value store, encoded as JSON rather than bincode, so `JsonPointer` indexes can see into it.
+pub fn kv_set_json<T: Serialize>(table: &str, key: &str, value: &T) -> Result<(), String> {
+    let val_bytes = serde_json::to_vec(value).unwrap();
+    let request = (table.to_string(), key.to_string(), val_bytes);
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_set", &req_bytes).map(|_| ())
+}
+/// Defines a secondary index on a table, replacing any previous index of the same name.
+/// Entries already in the table are indexed right away, and later sets and deletes keep the index up to date.
+/// Index names, like table names, must not contain colons. The K/V store refuses requests naming them.
+pub fn kv_define_index(table: &str, index: &str, extractor: IndexExtractor) -> Result<(), String> {
+    let request = (table.to_string(), index.to_string(), extractor);
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_define_index", &req_bytes).map(|_| ())
+}
+/// Drops a secondary index.
+pub fn kv_drop_index(table: &str, index: &str) -> Result<(), String> {
+    let request = (table.to_string(), index.to_string());
+    let req_bytes = serialize(&request).unwrap();
+    write_call("kv_drop_index", &req_bytes).map(|_| ())
+}
+/// Finds the bincode-encoded entries of a table whose value for a `Bytes` index equals `value`, as key/value pairs.
+pub fn kv_query_index<T: DeserializeOwned>(table: &str, index: &str, value: &[u8]) -> Vec<(String, T)> {
+    query_index(table, index, value.to_vec())
+    .into_iter()
+    .map(|(key, bytes)| (key, deserialize::<T>(&bytes).unwrap()))
+    .collect()
+}
+/// Finds the JSON-encoded entries of a table whose value for a `JsonPointer` index equals `value`, as key/value pairs.
+pub fn kv_query_index_json<V: Serialize, T: DeserializeOwned>(table: &str, index: &str, value: &V) -> Vec<(String, T)> {
+    query_index(table, index, serde_json::to_vec(value).unwrap())
+    .into_iter()
+    .map(|(key, bytes)| (key, serde_json::from_slice::<T>(&bytes).unwrap()))
+    .collect()
+}
+fn query_index(table: &str, index: &str, value: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+    let request = (table.to_string(), index.to_string(), value);
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = host_call("", "", "kv_query_index", &req_bytes).unwrap();
+    deserialize(&res_bytes).unwrap()
+}
+/// Atomically increments a counter in the key-value store and returns its new value. Missing counters start at zero.
+/// Counters are plain `u64`s, so they can also be read with `kv_get::<u64>`.
+pub fn kv_increment(table: &str, key: &str) -> Result<u64, String> {
     let request = (table.to_string(), key.to_string());
     let req_bytes = serialize(&request).unwrap();
-    host_call("", "", "kv_del", &req_bytes).unwrap();
-}
\ No newline at end of file
+    let res_bytes = write_call("kv_increment", &req_bytes)?;
+    Ok(deserialize(&res_bytes).unwrap())
+}
+/// Atomically raises a counter in the key-value store to at least `at_least` and returns its value afterwards.
+/// Missing counters start at zero. Increments made at the same time are never lost, so the counter never goes down.
+pub fn kv_raise_counter(table: &str, key: &str, at_least: u64) -> Result<u64, String> {
+    let request = (table.to_string(), key.to_string(), at_least);
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = write_call("kv_raise_counter", &req_bytes)?;
+    Ok(deserialize(&res_bytes).unwrap())
+}
+/// Lists up to `limit` entries of a table in key order, starting right after the key `after` if given.
+/// To go through a whole table, pass the last key of each page as `after` for the next one, until a page comes back short.
+pub fn kv_scan<T: DeserializeOwned>(table: &str, after: Option<&str>, limit: u32) -> Vec<(String, T)> {
+    let request = (table.to_string(), after.map(|a| a.to_string()), limit);
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = host_call("", "", "kv_scan", &req_bytes).unwrap();
+    deserialize::<Vec<(String, Vec<u8>)>>(&res_bytes).unwrap()
+    .into_iter()
+    .map(|(key, bytes)| (key, deserialize::<T>(&bytes).unwrap()))
+    .collect()
+}
+/// Starts watching a table for changes. Every later set or delete in it, including expiries, is passed to the app's K/V change handler;
+/// see the `handle_http!` macro for how to declare one. Watches last until the platform restarts, so call this whenever convenient, e.g. on every request.
+/// Changes the app makes from within its change handler are not passed back to it, so the handler can write to the tables it watches.
+pub fn kv_watch(table: &str) {
+    let req_bytes = serialize(&table.to_string()).unwrap();
+    host_call("", "", "kv_watch", &req_bytes).unwrap();
+}
+/// Stops watching a table for changes.
+pub fn kv_unwatch(table: &str) {
+    let req_bytes = serialize(&table.to_string()).unwrap();
+    host_call("", "", "kv_unwatch", &req_bytes).unwrap();
+}
+/// Makes a host call that writes to the key-value store, passing on the reason it failed if it did.
+fn write_call(operation: &str, req_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    host_call("", "", operation, req_bytes).map_err(|e| e.to_string())
+}
@@ -5,4 +5,10 @@ use wapc_guest::host_call;
 pub fn whoami() -> (String, String) {
     let whoami_bytes = host_call("", "", "whoami", &[]).unwrap();
     deserialize(&whoami_bytes).unwrap()
+}
+/// Returns the ID of the HTTP request being handled, as logged by the gateway, the core and the K/V store.
+/// It is also echoed back to the client in the `X-Request-Id` header. Outside of HTTP requests, such as when handling K/V changes, there is none.
+pub fn request_id() -> Option<String> {
+    let id_bytes = host_call("", "", "request_id", &[]).unwrap();
+    deserialize(&id_bytes).unwrap()
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+use bincode::{deserialize, serialize};
+use wapc_guest::host_call;
+pub use zhur_invk::sql::{SqlExecuted, SqlRows, SqlValue};
+/// Runs a statement returning rows, such as a `SELECT`, against this app's SQL database.
+/// Parameters are bound to the `?` placeholders in `sql` in order, so values never need to be spliced into the SQL text.
+/// ```ignore
+/// let rows = sql_query("SELECT id, body FROM notes WHERE author = ?", &["alice".into()])?;
+/// ```
+pub fn sql_query(sql: &str, params: &[SqlValue]) -> Result<SqlRows, String> {
+    let request = (sql.to_string(), params.to_vec());
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = host_call("", "", "sql_query", &req_bytes).unwrap();
+    deserialize::<Result<SqlRows, String>>(&res_bytes).unwrap()
+}
+/// Runs a statement not returning rows, such as an `INSERT`, `UPDATE` or `DELETE`, against this app's SQL database.
+/// Parameters are bound the same way as in `sql_query`.
+pub fn sql_execute(sql: &str, params: &[SqlValue]) -> Result<SqlExecuted, String> {
+    let request = (sql.to_string(), params.to_vec());
+    let req_bytes = serialize(&request).unwrap();
+    let res_bytes = host_call("", "", "sql_execute", &req_bytes).unwrap();
+    deserialize::<Result<SqlExecuted, String>>(&res_bytes).unwrap()
+}
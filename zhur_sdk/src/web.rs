@@ -69,6 +69,24 @@ impl File {
         Self(bytes, Some(mime))
     }
 }
+/// A response with a blob from this app's blob storage as its body, and the content type it was stored with.
+/// The core reads the blob in after the app is done, so it never has to fit in the app's memory.
+/// It does have to fit in one reply to the gateway, though: blobs over 64 MiB fail the request with a 500.
+pub struct BlobFile(pub crate::svc::blob::BlobMeta);
+impl BlobFile {
+    /// Looks up the blob at `path`. Returns `None` if there is none, which responds with a 404.
+    pub fn open(path: &str) -> Result<Option<Self>, String> {
+        Ok(crate::svc::blob::blob_stat(path)?.map(Self))
+    }
+}
+impl Responder for BlobFile {
+    fn modify_response(self, res: &mut HttpRes) {
+        res.body = Vec::new();
+        res.headers.insert("Content-Type".to_owned(), self.0.content_type.clone());
+        let req_bytes = bincode::serialize(&self.0).unwrap();
+        wapc_guest::host_call("", "", "http_body_blob", &req_bytes).unwrap();
+    }
+}
 /// Default implementation for things that may or may not be there.
 impl<R: Responder> Responder for Option<R> {
     fn modify_response(self, res: &mut HttpRes) {
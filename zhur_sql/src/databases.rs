@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use rusqlite::limits::Limit;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, TransactionBehavior};
+use zhur_common::log::*;
+use zhur_common::msg::core_sql::{Core2Sql, Sql2Core, SqlExecuted, SqlRows, SqlValue};
+use crate::deadline::Deadline;
+
+/// How many databases are kept open at once, across all workers. Past this, the least recently used one is closed for every one opened.
+pub const MAX_OPEN_DATABASES: usize = 128;
+/// How many rows a query may return. Past this, it fails instead.
+const MAX_RESULT_ROWS: usize = 10_000;
+/// Roughly how many bytes of values a query may return, counting eight for every number. Past this, it fails instead.
+const MAX_RESULT_BYTES: usize = 16 * 1024 * 1024;
+
+/// A connection to an app's database, along with when it was last used.
+struct OpenDatabase {
+    conn: Connection,
+    /// The value of `Databases::uses` as of the last request to this database.
+    last_used: u64,
+}
+
+/// The SQLite databases of every app, opened as they are needed.
+pub struct Databases {
+    /// Directory holding one subdirectory per owner, each holding one database file per app.
+    dir: PathBuf,
+    /// Open connections, by owner and app name.
+    open: HashMap<(String, String), OpenDatabase>,
+    /// How many databases may be open at once.
+    max_open: usize,
+    /// Counts requests to any database, to tell which one was used least recently.
+    uses: u64,
+    /// How long a query or statement from an app may run for.
+    statement_timeout: Duration,
+    /// Stops queries and statements running past `statement_timeout`.
+    deadline: Deadline,
+}
+
+impl Databases {
+    pub fn new(dir: PathBuf, max_open: usize, statement_timeout: Duration, deadline: Deadline) -> Self {
+        Self {
+            dir,
+            open: HashMap::new(),
+            max_open,
+            uses: 0,
+            statement_timeout,
+            deadline,
+        }
+    }
+    /// Carries out a `Core2Sql` request and produces the reply to it.
+    pub fn handle(&mut self, request: Core2Sql) -> Sql2Core {
+        let timeout = self.statement_timeout;
+        let deadline = self.deadline.clone();
+        let result = match request {
+            Core2Sql::Query(owner, app_name, sql, params) => {
+                trace!("Got a query for {}:{}.", &owner, &app_name);
+                self.connection(&owner, &app_name)
+                .and_then(|conn| deadline.run(conn, timeout, |conn| query(conn, &sql, params)))
+                .map(Sql2Core::Rows)
+            },
+            Core2Sql::Execute(owner, app_name, sql, params) => {
+                trace!("Got a statement to execute for {}:{}.", &owner, &app_name);
+                self.connection(&owner, &app_name)
+                .and_then(|conn| deadline.run(conn, timeout, |conn| execute(conn, &sql, params)))
+                .map(Sql2Core::Executed)
+            },
+            Core2Sql::Migrate(owner, app_name, migrations) => {
+                trace!("Got {} migrations for {}:{}.", migrations.len(), &owner, &app_name);
+                self.connection(&owner, &app_name)
+                .and_then(|conn| migrate(conn, &owner, &app_name, &migrations))
+                .map(Sql2Core::Migrated)
+            }
+        };
+        result.unwrap_or_else(|e| {
+            debug!("SQL request failed: {}", &e);
+            Sql2Core::Error(e)
+        })
+    }
+    /// Gets the connection to an app's database, opening or creating it if need be.
+    fn connection(&mut self, owner: &str, app_name: &str) -> Result<&mut Connection, String> {
+        let key = (owner.to_owned(), app_name.to_owned());
+        self.uses += 1;
+        if !self.open.contains_key(&key) {
+            let conn = self.open_database(owner, app_name)?;
+            if self.open.len() >= self.max_open {
+                let evicted = self.open.iter()
+                .min_by_key(|(_, db)| db.last_used)
+                .map(|(k, _)| k.clone());
+                if let Some(evicted) = evicted {
+                    trace!("Closing the database of {}:{} to make room.", &evicted.0, &evicted.1);
+                    self.open.remove(&evicted);
+                }
+            }
+            self.open.insert(key.clone(), OpenDatabase { conn, last_used: 0 });
+        }
+        let db = self.open.get_mut(&key).unwrap();
+        db.last_used = self.uses;
+        Ok(&mut db.conn)
+    }
+    fn open_database(&self, owner: &str, app_name: &str) -> Result<Connection, String> {
+        // The names end up in a path, so they must not be able to point anywhere but their own file.
+        if !is_safe_name(owner) || !is_safe_name(app_name) {
+            return Err(format!("{:?}:{:?} cannot be used to name a database.", owner, app_name));
+        }
+        let owner_dir = self.dir.join(owner);
+        std::fs::create_dir_all(&owner_dir)
+        .map_err(|e| format!("Could not create the directory {:?}: {}", &owner_dir, e))?;
+        let path = owner_dir.join(format!("{}.sqlite3", app_name));
+        info!("Opening the database of {}:{} at {:?}.", owner, app_name, &path);
+        let conn = Connection::open(&path)
+        .map_err(|e| format!("Could not open the database at {:?}: {}", &path, e))?;
+        // Attaching other database files would let an app reach outside its own.
+        conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 0);
+        // Other workers may hold connections to the same file, so writers wait their turn rather than fail outright.
+        conn.busy_timeout(self.statement_timeout)
+        .map_err(|e| format!("Could not set a busy timeout on {:?}: {}", &path, e))?;
+        conn.query_row("PRAGMA journal_mode = WAL", params![], |_| Ok(()))
+        .map_err(|e| format!("Could not switch {:?} to WAL mode: {}", &path, e))?;
+        Ok(conn)
+    }
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+    && !name.starts_with('.')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn query(conn: &mut Connection, sql: &str, params: Vec<SqlValue>) -> Result<SqlRows, String> {
+    let params = params.into_iter().map(to_sqlite).collect::<Vec<_>>();
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let columns = stmt.column_names().into_iter().map(String::from).collect::<Vec<_>>();
+    let mut rows = stmt.query(&params).map_err(|e| e.to_string())?;
+    let mut result = Vec::new();
+    let mut bytes = 0;
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        if result.len() >= MAX_RESULT_ROWS {
+            return Err(format!("The query returned more than {} rows.", MAX_RESULT_ROWS));
+        }
+        let mut values = Vec::with_capacity(columns.len());
+        for i in 0..columns.len() {
+            let value = row.get::<_, Value>(i).map_err(|e| e.to_string())?;
+            bytes += match &value {
+                Value::Text(v) => v.len(),
+                Value::Blob(v) => v.len(),
+                _ => 8,
+            };
+            if bytes > MAX_RESULT_BYTES {
+                return Err(format!("The query returned more than {} bytes.", MAX_RESULT_BYTES));
+            }
+            values.push(from_sqlite(value));
+        }
+        result.push(values);
+    }
+    Ok(SqlRows { columns, rows: result })
+}
+
+fn execute(conn: &mut Connection, sql: &str, params: Vec<SqlValue>) -> Result<SqlExecuted, String> {
+    let params = params.into_iter().map(to_sqlite).collect::<Vec<_>>();
+    let rows_affected = conn.execute(sql, &params).map_err(|e| e.to_string())?;
+    Ok(SqlExecuted {
+        rows_affected: rows_affected as u64,
+        last_insert_id: conn.last_insert_rowid(),
+    })
+}
+
+/// Runs the migrations past the database's current schema version, which is kept in `PRAGMA user_version`.
+/// Each one reads the version again inside its own write transaction, as another worker may be migrating the same database.
+fn migrate(conn: &mut Connection, owner: &str, app_name: &str, migrations: &[String]) -> Result<u32, String> {
+    for (i, sql) in migrations.iter().enumerate() {
+        let version = i + 1;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+        let current = tx.query_row("PRAGMA user_version", params![], |row| row.get::<_, i64>(0))
+        .map_err(|e| e.to_string())? as usize;
+        if current > migrations.len() {
+            warn!(
+                "The database of {}:{} is at schema version {}, but the app only knows of {} migrations.",
+                owner, app_name, current, migrations.len()
+            );
+            return Ok(current as u32);
+        }
+        if current >= version {
+            continue;
+        }
+        tx.execute_batch(sql)
+        .map_err(|e| format!("Migration {} failed: {}", version, e))?;
+        tx.pragma_update(None, "user_version", &(version as i64))
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        info!("Migrated the database of {}:{} to schema version {}.", owner, app_name, version);
+    }
+    Ok(migrations.len() as u32)
+}
+
+fn to_sqlite(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(v) => Value::Integer(v),
+        SqlValue::Real(v) => Value::Real(v),
+        SqlValue::Text(v) => Value::Text(v),
+        SqlValue::Blob(v) => Value::Blob(v),
+    }
+}
+fn from_sqlite(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Integer(v) => SqlValue::Integer(v),
+        Value::Real(v) => SqlValue::Real(v),
+        Value::Text(v) => SqlValue::Text(v),
+        Value::Blob(v) => SqlValue::Blob(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zhur_common::testing::TempDir;
+
+    /// The databases go away along with the returned directory.
+    fn temp_databases(name: &str, max_open: usize) -> (TempDir, Databases) {
+        let dir = TempDir::new("zhur_sql", name);
+        let deadline = Deadline::new(format!("sql_test_{}", name));
+        let databases = Databases::new(dir.path().to_owned(), max_open, Duration::from_millis(200), deadline);
+        (dir, databases)
+    }
+    fn run_query(databases: &mut Databases, sql: &str) -> Sql2Core {
+        databases.handle(Core2Sql::Query("alice".into(), "a".into(), sql.into(), Vec::new()))
+    }
+    fn is_open(databases: &Databases, app_name: &str) -> bool {
+        databases.open.contains_key(&("alice".to_owned(), app_name.to_owned()))
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_database() {
+        let (_dir, mut databases) = temp_databases("lru", 2);
+        databases.connection("alice", "a").unwrap();
+        databases.connection("alice", "b").unwrap();
+        databases.connection("alice", "a").unwrap();
+        databases.connection("alice", "c").unwrap();
+        assert!(is_open(&databases, "a"));
+        assert!(!is_open(&databases, "b"));
+        assert!(is_open(&databases, "c"));
+    }
+    #[test]
+    fn migrations_only_run_once() {
+        let (_dir, mut databases) = temp_databases("migrate", 2);
+        let migrations = vec!["CREATE TABLE notes (id INTEGER PRIMARY KEY);".to_owned()];
+        let first = databases.handle(Core2Sql::Migrate("alice".into(), "a".into(), migrations.clone()));
+        assert!(matches!(first, Sql2Core::Migrated(1)));
+        let second = databases.handle(Core2Sql::Migrate("alice".into(), "a".into(), migrations));
+        assert!(matches!(second, Sql2Core::Migrated(1)));
+        let bad = databases.handle(Core2Sql::Migrate("alice".into(), "b".into(), vec!["NOT SQL".to_owned()]));
+        assert!(matches!(bad, Sql2Core::Error(_)));
+    }
+    #[test]
+    fn slow_statements_are_stopped() {
+        let (_dir, mut databases) = temp_databases("deadline", 2);
+        let endless = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c";
+        match run_query(&mut databases, endless) {
+            Sql2Core::Error(e) => assert!(e.contains("took longer")),
+            other => panic!("Expected an error, got {:?}.", other)
+        }
+        assert!(matches!(run_query(&mut databases, "SELECT 1"), Sql2Core::Rows(_)));
+    }
+    #[test]
+    fn results_are_capped() {
+        let (_dir, mut databases) = temp_databases("caps", 2);
+        let rows = format!("WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < {}) SELECT x FROM c", MAX_RESULT_ROWS);
+        assert!(matches!(run_query(&mut databases, &rows), Sql2Core::Rows(r) if r.rows.len() == MAX_RESULT_ROWS));
+        let too_many = format!("{} UNION ALL SELECT 0", rows);
+        assert!(matches!(run_query(&mut databases, &too_many), Sql2Core::Error(_)));
+        let too_big = format!("SELECT zeroblob({})", MAX_RESULT_BYTES + 1);
+        assert!(matches!(run_query(&mut databases, &too_big), Sql2Core::Error(_)));
+    }
+}
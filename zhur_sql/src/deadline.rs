@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use rusqlite::{Connection, InterruptHandle};
+
+/// How often the watchdog thread looks for statements past their deadline.
+const WATCHDOG_TICK: Duration = Duration::from_millis(50);
+
+/// The statement currently running on a worker's connection, and when it has to be done by.
+type Armed = Mutex<Option<(Instant, InterruptHandle)>>;
+
+/// Stops statements that run for too long, by interrupting their connection from a watchdog thread.
+/// SQLite checks for interruptions as it steps through a statement, so even one that never produces a row is stopped.
+#[derive(Clone)]
+pub struct Deadline {
+    armed: Arc<Armed>,
+}
+
+impl Deadline {
+    /// Launches the watchdog thread, which exits once every clone of the returned `Deadline` is gone.
+    pub fn new(name: String) -> Self {
+        let armed = Arc::new(Mutex::new(None));
+        let watched = Arc::downgrade(&armed);
+        std::thread::Builder::new()
+            .name(name)
+            .spawn(move || Self::watch(watched))
+            .expect("Could not launch a SQL watchdog thread!");
+        Self { armed }
+    }
+    fn watch(armed: Weak<Armed>) {
+        while let Some(armed) = armed.upgrade() {
+            if let Some((deadline, handle)) = armed.lock().unwrap().as_ref() {
+                if Instant::now() >= *deadline {
+                    handle.interrupt();
+                }
+            }
+            drop(armed);
+            std::thread::sleep(WATCHDOG_TICK);
+        }
+    }
+    /// Runs `f` on `conn`, interrupting it if it is still running after `timeout`.
+    pub fn run<T, F>(&self, conn: &mut Connection, timeout: Duration, f: F) -> Result<T, String>
+    where F: FnOnce(&mut Connection) -> Result<T, String> {
+        let deadline = Instant::now() + timeout;
+        *self.armed.lock().unwrap() = Some((deadline, conn.get_interrupt_handle()));
+        let result = f(conn);
+        *self.armed.lock().unwrap() = None;
+        match result {
+            Err(_) if Instant::now() >= deadline => Err(format!("The statement took longer than {} ms and was stopped.", timeout.as_millis())),
+            result => result
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use zhur_common::{init_logger, msg::core_sql::DEFAULT_SQL_ENDPOINT, zmq::{proxy, Context, SocketType}};
+use zhur_common::log::*;
+/// The per-app SQLite databases.
+mod databases;
+use databases::{Databases, MAX_OPEN_DATABASES};
+/// Stopping statements that run for too long.
+mod deadline;
+use deadline::Deadline;
+/// The threads requests are carried out on.
+mod worker;
+use worker::{Worker, WORKER_ENDPOINT};
+
+/// Where the databases go if `ZHUR_SQL_DIR` is not set, relative to the user's home directory.
+const DEFAULT_SQL_DIR: &str = ".zhur/sql";
+/// How many worker threads run requests if `ZHUR_SQL_WORKERS` is not set.
+const DEFAULT_WORKERS: usize = 4;
+/// How long an app's query or statement may run for if `ZHUR_SQL_STATEMENT_TIMEOUT_MS` is not set.
+const DEFAULT_STATEMENT_TIMEOUT_MS: u64 = 5_000;
+
+fn main() {
+    init_logger();
+    let dir = match std::env::var("ZHUR_SQL_DIR") {
+        Ok(d) => PathBuf::from(d),
+        Err(_) => {
+            let home = std::env::var("HOME").expect("Neither ZHUR_SQL_DIR nor HOME is set!");
+            let dir = PathBuf::from(home).join(DEFAULT_SQL_DIR);
+            warn!("ZHUR_SQL_DIR not set. Assuming default of {:?}.", &dir);
+            dir
+        }
+    };
+    let workers = env_or("ZHUR_SQL_WORKERS", DEFAULT_WORKERS);
+    if workers == 0 {
+        error!("ZHUR_SQL_WORKERS must be at least 1. Exiting.");
+        std::process::exit(1);
+    }
+    let statement_timeout = Duration::from_millis(env_or("ZHUR_SQL_STATEMENT_TIMEOUT_MS", DEFAULT_STATEMENT_TIMEOUT_MS));
+    let zmq_ctx = Context::new();
+    let router_socket = zmq_ctx.socket(SocketType::ROUTER).unwrap();
+    let endpoint = match std::env::var("ZHUR_SQL_ENDPOINT") {
+        Ok(e) => e,
+        Err(_) => {
+            warn!("ZHUR_SQL_ENDPOINT not set. Assuming default of {:?}.", DEFAULT_SQL_ENDPOINT);
+            DEFAULT_SQL_ENDPOINT.to_string()
+        }
+    };
+    router_socket.bind(&endpoint).expect("Could not bind the SQL service socket!");
+    let dealer_socket = zmq_ctx.socket(SocketType::DEALER).unwrap();
+    dealer_socket.bind(WORKER_ENDPOINT).expect("Could not bind the SQL worker endpoint!");
+    // Every worker keeps connections of its own, so the limit on open databases is shared out between them.
+    let max_open = (MAX_OPEN_DATABASES / workers).max(1);
+    for id in 0..workers {
+        let deadline = Deadline::new(format!("sql_watchdog_{}", id));
+        let databases = Databases::new(dir.clone(), max_open, statement_timeout, deadline);
+        Worker::new(id, &zmq_ctx, databases).run_as_thread();
+    }
+    info!("Serving SQL requests at {} with {} workers.", &endpoint, workers);
+    // Requests are tagged, so clients can keep several in flight over one DEALER socket. REQ clients work too, with a tag of their choosing.
+    proxy(&router_socket, &dealer_socket).expect("The SQL request proxy failed!");
+}
+
+/// Reads a setting from the environment, exiting if it is set but cannot be parsed.
+fn env_or<T: FromStr + std::fmt::Debug>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(v) => match v.parse::<T>() {
+            Ok(v) => v,
+            Err(_) => {
+                error!("{} set to invalid value \"{}\". Exiting.", name, v);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => {
+            warn!("{} not set. Assuming default of {:?}.", name, default);
+            default
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::thread::JoinHandle;
+use zhur_common::bincode::{deserialize, serialize};
+use zhur_common::log::*;
+use zhur_common::msg::core_sql::{Sql2Core, TaggedCore2Sql, TaggedSql2Core};
+use zhur_common::zmq::{Context, Socket, SocketType};
+use crate::databases::Databases;
+
+/// In-process endpoint the front ROUTER socket's requests are spread out over workers through.
+pub const WORKER_ENDPOINT: &str = "inproc://zhur_sql_workers";
+
+/// One of several threads running SQL requests, each with connections of its own, so a slow database does not hold up the rest.
+pub struct Worker {
+    /// Inner numeral ID, for logging.
+    id: usize,
+    /// ZMQ rep socket connected to `WORKER_ENDPOINT`.
+    rep_socket: Socket,
+    databases: Databases,
+}
+
+impl Worker {
+    /// Creates a worker. `WORKER_ENDPOINT` must already be bound on the same context.
+    pub fn new(id: usize, zmq_ctx: &Context, databases: Databases) -> Self {
+        let rep_socket = zmq_ctx.socket(SocketType::REP).unwrap();
+        rep_socket.connect(WORKER_ENDPOINT).expect("Could not connect a SQL worker to the worker endpoint!");
+        Self { id, rep_socket, databases }
+    }
+    fn handle(&mut self) {
+        let request_bytes = self.rep_socket.recv_bytes(0).unwrap();
+        trace!("SQL worker #{} got request bytes.", self.id);
+        let res_bytes = match deserialize::<TaggedCore2Sql>(&request_bytes) {
+            Ok((tag, request)) => {
+                let response: TaggedSql2Core = (tag, self.databases.handle(request));
+                serialize(&response).unwrap()
+            },
+            // A request that fails to deserialize as a whole may still start with a readable tag, which is all the client needs to fail it.
+            Err(_) => match deserialize::<u64>(&request_bytes) {
+                Ok(tag) => {
+                    warn!("SQL worker #{} got bytes tagged #{} that could not be deserialized to a TaggedCore2Sql.", self.id, tag);
+                    let response: TaggedSql2Core = (tag, Sql2Core::Error("Malformed request.".to_owned()));
+                    serialize(&response).unwrap()
+                },
+                Err(_) => {
+                    warn!("SQL worker #{} got bytes that could not be deserialized to a TaggedCore2Sql, nor even carry a tag.", self.id);
+                    Vec::new()
+                }
+            }
+        };
+        self.rep_socket.send(res_bytes, 0).unwrap();
+        trace!("SQL worker #{} sent its reply.", self.id);
+    }
+    pub fn run_as_thread(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name(format!("sql_worker_{}", self.id))
+            .spawn(move || {
+                let mut worker = self;
+                loop {
+                    worker.handle();
+                }
+            })
+            .expect("Could not launch a SQL worker thread!")
+    }
+}